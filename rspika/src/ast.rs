@@ -1,37 +1,54 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
 
-use chumsky::prelude::*;
+use chumsky::{prelude::*, Stream};
 
 use crate::{
+    diagnostic::ParseError,
     il,
-    token::{Ident, IntLiteral, Token},
+    layout::{Layout, Layouts},
+    resolve::{LocalId, Res},
+    token::{Ident, IntLiteral, Span, Token},
+    typeck::{self, TypeckResults},
 };
 
-pub fn module() -> impl Parser<Token, Module, Error = Simple<Token>> {
+/// Parses the output of [`lex`](crate::token::lex) for a source text of
+/// `source_len` bytes.
+pub fn parse(
+    tokens: Vec<(Token, Span)>,
+    source_len: usize,
+) -> Result<Module, Vec<ParseError<Token>>> {
+    let eoi = source_len..source_len;
+    module().parse(Stream::from_iter(eoi, tokens.into_iter()))
+}
+
+#[allow(clippy::result_large_err)]
+pub fn module() -> impl Parser<Token, Module, Error = ParseError<Token>> {
     let ident = select! {
         Token::Ident(x) => x,
-    };
+    }
+    .labelled("identifier");
     let int_literal = select! {
         Token::IntLiteral(x) => x,
-    };
+    }
+    .labelled("integer literal");
 
     let path = ident
         .separated_by(just(Token::Colon2))
         .at_least(1)
-        .map(|elements| Path { elements });
+        .map_with_span(|elements, span| Path { elements, span });
 
     let expr = recursive(|expr| {
         let field_init = ident
             .then_ignore(just(Token::Colon))
             .then(expr.clone())
-            .map(|(name, value)| FieldInit { name, value });
+            .map_with_span(|(name, value), span| FieldInit { name, value, span });
 
         let struct_init = path
             .clone()
             .then_ignore(just(Token::OpenBrace))
             .then(field_init.separated_by(just(Token::Comma)).allow_trailing())
             .then_ignore(just(Token::CloseBrace))
-            .map(|(name, fields)| StructInit { name, fields });
+            .map_with_span(|(name, fields), span| StructInit { name, fields, span });
 
         let array_init = just(Token::OpenBracket)
             .ignore_then(choice((
@@ -50,14 +67,22 @@ pub fn module() -> impl Parser<Token, Module, Error = Simple<Token>> {
             .then_ignore(just(Token::CloseBracket));
 
         let factor = choice((
-            struct_init.map(Expr::StructInit),
-            array_init.map(Expr::ArrayInit),
-            path.clone().map(Expr::Path),
-            int_literal.map(Expr::IntLiteral),
+            expr.clone()
+                .delimited_by(just(Token::OpenParen), just(Token::CloseParen))
+                .map_with_span(|expr: Expr, span| Expr { span, ..expr }),
+            choice((
+                struct_init.map(ExprKind::StructInit),
+                array_init.map(ExprKind::ArrayInit),
+                path.clone().map(ExprKind::Path),
+                int_literal.map(ExprKind::IntLiteral),
+                just(Token::True).to(ExprKind::BoolLiteral(true)),
+                just(Token::False).to(ExprKind::BoolLiteral(false)),
+            ))
+            .map_with_span(|kind, span| Expr { kind, span }),
         ));
-        let prefix_op = select! {
-            Token::Exclam => PrefixOp::Not,
-        };
+        let prefix_op = just(Token::Exclam)
+            .to(PrefixOp::Not)
+            .map_with_span(|op, span: Span| (op, span));
         let suffix_op = choice((
             just(Token::Dot)
                 .ignore_then(ident)
@@ -67,7 +92,8 @@ pub fn module() -> impl Parser<Token, Module, Error = Simple<Token>> {
                 .map(Box::new)
                 .then_ignore(just(Token::CloseBracket))
                 .map(SuffixOp::ArrayIndex),
-        ));
+        ))
+        .map_with_span(|op, span: Span| (op, span));
         let term = prefix_op
             .repeated()
             .then(factor)
@@ -75,11 +101,17 @@ pub fn module() -> impl Parser<Token, Module, Error = Simple<Token>> {
             .map(|((prefixes, factor), suffixes)| {
                 let mut acc = factor;
                 // Suffixes take precedence over prefixes
-                for suffix in suffixes {
-                    acc = Expr::Suffix(Box::new(acc), suffix);
+                for (suffix, span) in suffixes {
+                    acc = Expr {
+                        span: acc.span.start..span.end,
+                        kind: ExprKind::Suffix(Box::new(acc), suffix),
+                    };
                 }
-                for prefix in prefixes {
-                    acc = Expr::Prefix(prefix, Box::new(acc));
+                for (prefix, span) in prefixes.into_iter().rev() {
+                    acc = Expr {
+                        span: span.start..acc.span.end,
+                        kind: ExprKind::Prefix(prefix, Box::new(acc)),
+                    };
                 }
                 acc
             });
@@ -109,7 +141,7 @@ pub fn module() -> impl Parser<Token, Module, Error = Simple<Token>> {
                             {
                                 let r = output.pop().unwrap();
                                 let l = output.pop().unwrap();
-                                output.push(Expr::Binary(op2, Box::new(l), Box::new(r)));
+                                output.push(Expr::binary(op2, l, r));
                                 operators.pop();
                             }
                             _ => break,
@@ -121,7 +153,7 @@ pub fn module() -> impl Parser<Token, Module, Error = Simple<Token>> {
                 while let Some(op) = operators.pop() {
                     let r = output.pop().unwrap();
                     let l = output.pop().unwrap();
-                    output.push(Expr::Binary(op, Box::new(l), Box::new(r)));
+                    output.push(Expr::binary(op, l, r));
                 }
 
                 assert!(output.len() == 1);
@@ -135,9 +167,10 @@ pub fn module() -> impl Parser<Token, Module, Error = Simple<Token>> {
             .then_ignore(just(Token::Semicolon))
             .then(int_literal)
             .then_ignore(just(Token::CloseBracket))
-            .map(|(element, size)| ArrayType {
+            .map_with_span(|(element, size), span| ArrayType {
                 element: Box::new(element),
                 size,
+                span,
             });
         choice((path.map(Type::Path), array_type.map(Type::Array)))
     });
@@ -145,7 +178,11 @@ pub fn module() -> impl Parser<Token, Module, Error = Simple<Token>> {
     let fn_arg = ident
         .then_ignore(just(Token::Colon))
         .then(type_name.clone())
-        .map(|(arg_name, arg_type)| FnArg { arg_name, arg_type });
+        .map_with_span(|(arg_name, arg_type), span| FnArg {
+            arg_name,
+            arg_type,
+            span,
+        });
 
     let block = recursive(|block| {
         let let_statement = just(Token::Let)
@@ -171,7 +208,11 @@ pub fn module() -> impl Parser<Token, Module, Error = Simple<Token>> {
         let if_statement = just(Token::If)
             .ignore_then(expr.clone())
             .then(block.clone())
-            .map(|(condition, body)| IfCase { condition, body })
+            .map_with_span(|(condition, body), span| IfCase {
+                condition,
+                body,
+                span,
+            })
             .separated_by(just(Token::Else))
             .at_least(1)
             .then(just(Token::Else).ignore_then(block.clone()).or_not())
@@ -181,10 +222,14 @@ pub fn module() -> impl Parser<Token, Module, Error = Simple<Token>> {
             .then_ignore(just(Token::Dot2))
             .then(int_literal)
             .map(|(start, end)| Iterable::Range(start, end));
+        let for_header = ident.then_ignore(just(Token::In)).then(iterable);
         let for_statement = just(Token::For)
-            .ignore_then(ident)
-            .then_ignore(just(Token::In))
-            .then(iterable)
+            .ignore_then(choice((
+                for_header
+                    .clone()
+                    .delimited_by(just(Token::OpenParen), just(Token::CloseParen)),
+                for_header,
+            )))
             .then(block.clone())
             .map(|((binding, target), body)| For {
                 binding,
@@ -195,21 +240,31 @@ pub fn module() -> impl Parser<Token, Module, Error = Simple<Token>> {
             .ignore_then(expr.clone())
             .then_ignore(just(Token::Semicolon));
         let break_statement = just(Token::Break).then(just(Token::Semicolon));
+        // Block-like statements may optionally be terminated by a semicolon.
+        let block_like = choice((
+            block.map(StatementKind::Block),
+            if_statement.map(StatementKind::If),
+            for_statement.map(StatementKind::For),
+        ))
+        .then_ignore(just(Token::Semicolon).or_not());
         let statement = choice((
-            block.map(Statement::Block),
-            let_statement.map(Statement::Let),
-            assign_statement.map(Statement::Assign),
-            if_statement.map(Statement::If),
-            for_statement.map(Statement::For),
-            return_statement.map(Statement::Return),
-            break_statement.to(Statement::Break),
-        ));
+            block_like,
+            let_statement.map(StatementKind::Let),
+            assign_statement.map(StatementKind::Assign),
+            return_statement.map(StatementKind::Return),
+            break_statement.to(StatementKind::Break),
+        ))
+        .map_with_span(|kind, span| Statement { kind, span });
 
         just(Token::OpenBrace)
             .ignore_then(statement.repeated())
             .then(expr.or_not())
             .then_ignore(just(Token::CloseBrace))
-            .map(|(statements, expr)| Block { statements, expr })
+            .map_with_span(|(statements, expr), span| Block {
+                statements,
+                expr,
+                span,
+            })
     });
 
     let fn_item = just(Token::Fn)
@@ -222,36 +277,41 @@ pub fn module() -> impl Parser<Token, Module, Error = Simple<Token>> {
         .then_ignore(just(Token::RightArrow))
         .then(type_name.clone())
         .then(block)
-        .map(|(((name, args), return_type), body)| FnItem {
+        .map_with_span(|(((name, args), return_type), body), span| FnItem {
             name,
             args,
             return_type,
             body,
+            span,
         });
 
-    let field =
-        ident
-            .then_ignore(just(Token::Colon))
-            .then(type_name)
-            .map(|(field_name, field_type)| Field {
-                field_name,
-                field_type,
-            });
+    let field = ident
+        .then_ignore(just(Token::Colon))
+        .then(type_name)
+        .map_with_span(|(field_name, field_type), span| Field {
+            field_name,
+            field_type,
+            span,
+        });
 
     let struct_item = just(Token::Struct)
         .ignore_then(ident)
         .then_ignore(just(Token::OpenBrace))
         .then(field.separated_by(just(Token::Comma)).allow_trailing())
         .then_ignore(just(Token::CloseBrace))
-        .map(|(name, fields)| StructItem { name, fields });
+        .map_with_span(|(name, fields), span| StructItem { name, fields, span });
 
-    let variant = ident.map(|name| Variant { name });
+    let variant = ident.map_with_span(|name, span| Variant { name, span });
     let enum_item = just(Token::Enum)
         .ignore_then(ident)
         .then_ignore(just(Token::OpenBrace))
         .then(variant.separated_by(just(Token::Comma)).allow_trailing())
         .then_ignore(just(Token::CloseBrace))
-        .map(|(name, variants)| EnumItem { name, variants });
+        .map_with_span(|(name, variants), span| EnumItem {
+            name,
+            variants,
+            span,
+        });
 
     let item = choice((
         fn_item.map(Item::Fn),
@@ -263,38 +323,256 @@ pub fn module() -> impl Parser<Token, Module, Error = Simple<Token>> {
         .map(|items| Module { items })
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone)]
 pub struct Path {
     pub elements: Vec<Ident>,
+    pub span: Span,
 }
 
 impl From<Ident> for Path {
     fn from(value: Ident) -> Self {
         Self {
             elements: vec![value],
+            span: Span::default(),
         }
     }
 }
 
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, element) in self.elements.iter().enumerate() {
+            if i > 0 {
+                write!(f, "::")?;
+            }
+            write!(f, "{}", element)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Module {
     pub items: Vec<Item>,
 }
 
 impl Module {
-    pub fn visit_il(&self) -> il::Module {
+    pub fn visit_il(&self, types: &TypeckResults) -> il::Module {
         let mut module = il::Module {
             functions: HashMap::new(),
         };
 
+        let layouts = Layouts::new(types);
         for item in &self.items {
-            item.visit_il(&mut module);
+            item.visit_il(&mut module, types, &layouts);
         }
 
+        module.debug_verify("lowering");
         module
     }
 }
 
+/// Per-function state used while lowering to IL.
+///
+/// Values of aggregate types (structs and arrays) live in memory and are
+/// represented by their address. Aggregate arguments are passed as a pointer
+/// to the caller's copy, and aggregate results are written to memory provided
+/// by the caller, whose address is passed as a hidden first argument.
+pub struct Lowering<'a> {
+    pub types: &'a TypeckResults,
+    pub layouts: &'a Layouts<'a>,
+    pub assembly: il::Assembly,
+    /// Exit labels of the loops enclosing the code being lowered, innermost
+    /// last.
+    pub loop_exits: Vec<il::Label>,
+    /// Address and layout of the memory an aggregate result is written to.
+    return_slot: Option<(il::Value, Layout)>,
+    /// Stack allocations, which are hoisted to the start of the function so
+    /// that each one is only performed once.
+    allocs: Vec<il::Instruction>,
+    /// How each local and argument of the function is accessed.
+    locals: HashMap<LocalId, il::Variable>,
+    arguments: Vec<il::Variable>,
+}
+
+impl<'a> Lowering<'a> {
+    pub fn new(types: &'a TypeckResults, layouts: &'a Layouts<'a>) -> Self {
+        Self {
+            types,
+            layouts,
+            assembly: il::Assembly::new(),
+            loop_exits: Vec::new(),
+            return_slot: None,
+            allocs: Vec::new(),
+            locals: HashMap::new(),
+            arguments: Vec::new(),
+        }
+    }
+
+    /// Declares how the local `id` is accessed.
+    pub fn declare(&mut self, id: LocalId, variable: il::Variable) {
+        self.locals.insert(id, variable);
+    }
+
+    /// Returns how the variable `path` refers to is accessed.
+    pub fn variable(&self, path: &Path) -> &il::Variable {
+        match self.types.resolutions.path(path) {
+            Res::Local(id) => &self.locals[id],
+            Res::Argument(index) => &self.arguments[*index],
+            res => unreachable!("path resolved to {:?}, which is not a variable", res),
+        }
+    }
+
+    /// Completes lowering of the function body, returning its assembly.
+    pub fn finish(mut self) -> il::Assembly {
+        self.assembly.prepend(self.allocs);
+        self.assembly
+    }
+
+    /// Returns the IL type of values of type `ty`.
+    pub fn il_type(&self, ty: &typeck::Type) -> il::Type {
+        match ty {
+            typeck::Type::Primitive(ty) => *ty,
+            typeck::Type::Bool => il::Type::U8,
+            typeck::Type::Enum(name) => self.types.enums[name].repr(),
+            typeck::Type::Struct(_) | typeck::Type::Array(..) => il::Type::Ptr,
+            typeck::Type::Unit | typeck::Type::Error => {
+                unreachable!("values of type `{}` are never lowered", ty)
+            }
+        }
+    }
+
+    /// Emits a binary operation on operands of type `ty`, returning the
+    /// temporary holding its result.
+    pub fn binary(
+        &mut self,
+        op: il::BinaryOp,
+        ty: il::Type,
+        left: il::Value,
+        right: il::Value,
+    ) -> il::Value {
+        let dest = self.assembly.new_temporary();
+        let dest_type = if op.is_comparison() { il::Type::U8 } else { ty };
+        self.assembly.push(il::Instruction::Operation(
+            il::Output { dest, dest_type },
+            il::Operation::Binary(op, ty, left, right),
+        ));
+        il::Value::Temporary(dest)
+    }
+
+    /// Emits a unary operation on an operand of type `ty`, returning the
+    /// temporary holding its result of type `dest_type`.
+    pub fn unary(
+        &mut self,
+        op: il::UnaryOp,
+        ty: il::Type,
+        dest_type: il::Type,
+        value: il::Value,
+    ) -> il::Value {
+        let dest = self.assembly.new_temporary();
+        self.assembly.push(il::Instruction::Operation(
+            il::Output { dest, dest_type },
+            il::Operation::Unary(op, ty, value),
+        ));
+        il::Value::Temporary(dest)
+    }
+
+    /// Returns the address `offset` bytes past `base`.
+    pub fn offset(&mut self, base: il::Value, offset: u64) -> il::Value {
+        if offset == 0 {
+            return base;
+        }
+        let offset = il::Value::Literal(il::Literal::Int(offset.into()));
+        self.binary(il::BinaryOp::Add, il::Type::Ptr, base, offset)
+    }
+
+    /// Allocates a stack slot for a value of type `ty`, returning its address.
+    pub fn alloc(&mut self, ty: &typeck::Type) -> il::Value {
+        self.alloc_layout(self.layouts.of(ty))
+    }
+
+    /// Allocates a stack slot with the given layout, returning its address.
+    pub fn alloc_layout(&mut self, layout: Layout) -> il::Value {
+        let dest = self.assembly.new_temporary();
+        self.allocs.push(il::Instruction::Alloc {
+            addr_output: il::Output {
+                dest,
+                dest_type: il::Type::Ptr,
+            },
+            size: layout.size,
+            alignment: layout.alignment,
+        });
+        il::Value::Temporary(dest)
+    }
+
+    pub fn load(&mut self, addr: il::Value, ty: il::Type) -> il::Value {
+        let dest = self.assembly.new_temporary();
+        self.assembly.push(il::Instruction::Load {
+            output: il::Output {
+                dest,
+                dest_type: ty,
+            },
+            addr,
+        });
+        il::Value::Temporary(dest)
+    }
+
+    pub fn store(&mut self, addr: il::Value, value: il::Value, ty: il::Type) {
+        self.assembly
+            .push(il::Instruction::Store { ty, addr, value });
+    }
+
+    /// Reads a value of type `ty` stored at `addr`.
+    pub fn read(&mut self, addr: il::Value, ty: &typeck::Type) -> il::Value {
+        if ty.is_aggregate() {
+            addr
+        } else {
+            self.load(addr, self.il_type(ty))
+        }
+    }
+
+    /// Stores a value of type `ty` at `addr`, copying aggregates.
+    pub fn write(&mut self, addr: il::Value, value: il::Value, ty: &typeck::Type) {
+        if ty.is_aggregate() {
+            let layout = self.layouts.of(ty);
+            self.copy(addr, value, layout);
+        } else {
+            self.store(addr, value, self.il_type(ty));
+        }
+    }
+
+    /// Copies a value with the given layout from `src` to `dest`, in pieces
+    /// as large as its alignment allows.
+    pub fn copy(&mut self, dest: il::Value, src: il::Value, layout: Layout) {
+        let piece = match layout.alignment {
+            8.. => il::Type::U64,
+            4 => il::Type::U32,
+            2 => il::Type::U16,
+            _ => il::Type::U8,
+        };
+        for offset in (0..layout.size).step_by(piece.size() as usize) {
+            let src = self.offset(src.clone(), offset);
+            let value = self.load(src, piece);
+            let addr = self.offset(dest.clone(), offset);
+            self.store(addr, value, piece);
+        }
+    }
+
+    /// Returns `value` from the function being lowered.
+    pub fn ret(&mut self, value: il::Value) {
+        let value = match self.return_slot.clone() {
+            Some((slot, layout)) => {
+                self.copy(slot.clone(), value, layout);
+                slot
+            }
+            None => value,
+        };
+        self.assembly
+            .push(il::Instruction::Continuation(il::Continuation::Return(
+                value,
+            )));
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Item {
     Fn(FnItem),
@@ -303,11 +581,20 @@ pub enum Item {
 }
 
 impl Item {
-    pub fn visit_il(&self, module: &mut il::Module) {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Fn(fnn) => fnn.span.clone(),
+            Self::Struct(strukt) => strukt.span.clone(),
+            Self::Enum(enumm) => enumm.span.clone(),
+        }
+    }
+
+    pub fn visit_il(&self, module: &mut il::Module, types: &TypeckResults, layouts: &Layouts) {
         match self {
-            Self::Fn(fnn) => fnn.visit_il(module),
-            Self::Struct(_) => todo!(),
-            Self::Enum(_) => todo!(),
+            Self::Fn(fnn) => fnn.visit_il(module, types, layouts),
+            // Structs and enums have no code of their own; their layout is
+            // used by the functions working with them.
+            Self::Struct(_) | Self::Enum(_) => {}
         }
     }
 }
@@ -318,25 +605,45 @@ pub struct FnItem {
     pub args: Vec<FnArg>,
     pub return_type: Type,
     pub body: Block,
+    pub span: Span,
 }
 
 impl FnItem {
-    pub fn visit_il(&self, module: &mut il::Module) {
-        let mut assembly = il::Assembly::new();
-        let mut scope = il::Scope::new(None);
-        for (i, arg) in self.args.iter().enumerate() {
-            scope.declare(
-                arg.arg_name.clone().into(),
-                il::Value::Argument(il::Argument(i)),
-            )
+    pub fn visit_il(&self, module: &mut il::Module, types: &TypeckResults, layouts: &Layouts) {
+        let mut cx = Lowering::new(types, layouts);
+        let sig = &types.functions[&self.name];
+        let mut params = Vec::new();
+        if sig.return_type.is_aggregate() {
+            let slot = il::Value::Argument(il::Argument(0));
+            cx.return_slot = Some((slot, layouts.of(&sig.return_type)));
+            params.push(il::Type::Ptr);
         }
-        let implicit_return = self.body.visit_il(&mut scope, &mut assembly);
-        assembly.push(il::Instruction::Continuation(il::Continuation::Return(
-            implicit_return,
-        )));
-        module
-            .functions
-            .insert(self.name.clone(), il::Function { assembly });
+        let return_type =
+            (sig.return_type != typeck::Type::Unit).then(|| cx.il_type(&sig.return_type));
+
+        for ty in &sig.params {
+            let argument = il::Value::Argument(il::Argument(params.len()));
+            cx.arguments.push(il::Variable::Value(argument));
+            params.push(cx.il_type(ty));
+        }
+        let implicit_return = self.body.visit_il(&mut cx);
+        if self.body.expr.is_some() || sig.return_type == typeck::Type::Unit {
+            cx.ret(implicit_return);
+        } else {
+            // The type checker ensures that a body without a tail expression
+            // ends in a `return` unless the function returns `()`.
+            cx.assembly
+                .push(il::Instruction::Continuation(il::Continuation::Halt));
+        }
+        module.functions.insert(
+            self.name.clone(),
+            il::Function {
+                params,
+                return_type,
+                assembly: cx.finish(),
+                span: self.span.clone(),
+            },
+        );
     }
 }
 
@@ -344,6 +651,7 @@ impl FnItem {
 pub struct FnArg {
     pub arg_name: Ident,
     pub arg_type: Type,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -352,34 +660,52 @@ pub enum Type {
     Array(ArrayType),
 }
 
+impl Type {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Path(path) => path.span.clone(),
+            Self::Array(array) => array.span.clone(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ArrayType {
     pub element: Box<Type>,
     pub size: IntLiteral,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct Block {
     pub statements: Vec<Statement>,
     pub expr: Option<Expr>,
+    pub span: Span,
 }
 
 impl Block {
-    pub fn visit_il(&self, scope: &mut il::Scope, assembly: &mut il::Assembly) -> il::Value {
+    pub fn visit_il(&self, cx: &mut Lowering) -> il::Value {
         for stmt in &self.statements {
-            stmt.visit_il(scope, assembly);
+            stmt.visit_il(cx);
         }
         self.expr
             .as_ref()
-            .map(|expr| expr.visit_il(scope, assembly))
+            .map(|expr| expr.visit_il(cx))
             .unwrap_or(il::Value::Literal(il::Literal::Nil))
     }
 }
 
 #[derive(Debug, Clone)]
-pub enum Expr {
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
     Path(Path),
     IntLiteral(IntLiteral),
+    BoolLiteral(bool),
     StructInit(StructInit),
     ArrayInit(ArrayInit),
     Prefix(PrefixOp, Box<Expr>),
@@ -388,47 +714,121 @@ pub enum Expr {
 }
 
 impl Expr {
-    pub fn visit_il(&self, scope: &mut il::Scope, assembly: &mut il::Assembly) -> il::Value {
-        match self {
-            Self::Path(path) => scope.resolve(path).clone(),
-            Self::IntLiteral(int) => il::Value::Literal(il::Literal::Int(int.clone())),
-            Self::StructInit(_) => todo!(),
-            Self::ArrayInit(_) => todo!(),
-            Self::Prefix(op, expr) => {
-                let expr = expr.visit_il(scope, assembly);
-                let dest = assembly.new_temporary();
-                assembly.push(il::Instruction::Operation(
-                    il::Output { dest },
-                    il::Operation::Unary(op.into(), expr),
-                ));
-                il::Value::Temporary(dest)
+    fn binary(op: BinaryOp, left: Expr, right: Expr) -> Self {
+        Self {
+            span: left.span.start..right.span.end,
+            kind: ExprKind::Binary(op, Box::new(left), Box::new(right)),
+        }
+    }
+
+    pub fn visit_il(&self, cx: &mut Lowering) -> il::Value {
+        match &self.kind {
+            ExprKind::Path(path) => match cx.types.resolutions.path(path) {
+                // Variants lower to their discriminant.
+                Res::Variant(_, discriminant) => {
+                    il::Value::Literal(il::Literal::Int((*discriminant as u64).into()))
+                }
+                _ => match cx.variable(path).clone() {
+                    il::Variable::Value(value) => value,
+                    il::Variable::Memory(addr) => cx.read(addr, cx.types.expr_type(self)),
+                },
+            },
+            ExprKind::IntLiteral(int) => il::Value::Literal(il::Literal::Int(int.clone())),
+            ExprKind::BoolLiteral(b) => {
+                il::Value::Literal(il::Literal::Int(IntLiteral::from(*b as u64)))
+            }
+            ExprKind::StructInit(init) => {
+                let ty = cx.types.expr_type(self);
+                let typeck::Type::Struct(name) = ty else {
+                    unreachable!("struct initializer of a non-struct type");
+                };
+                let addr = cx.alloc(ty);
+                for field in &init.fields {
+                    let value = field.value.visit_il(cx);
+                    let offset = cx.layouts.field_offset(name, &field.name);
+                    let field_addr = cx.offset(addr.clone(), offset);
+                    cx.write(field_addr, value, cx.types.expr_type(&field.value));
+                }
+                addr
+            }
+            ExprKind::ArrayInit(init) => {
+                let ty = cx.types.expr_type(self);
+                let addr = cx.alloc(ty);
+                init.visit_il(addr.clone(), ty, cx);
+                addr
+            }
+            ExprKind::Prefix(op, operand) => {
+                let ty = cx.il_type(cx.types.expr_type(operand));
+                let operand = operand.visit_il(cx);
+                cx.unary(op.into(), ty, ty, operand)
             }
-            Self::Suffix(_, _) => todo!(),
-            Self::Binary(op, left, right) => {
-                let left = left.visit_il(scope, assembly);
-                let right = right.visit_il(scope, assembly);
-
-                let dest = assembly.new_temporary();
-                assembly.push(il::Instruction::Operation(
-                    il::Output { dest },
-                    il::Operation::Binary(op.into(), left, right),
-                ));
-                il::Value::Temporary(dest)
+            ExprKind::Suffix(_, _) => {
+                let addr = self.visit_place(cx);
+                cx.read(addr, cx.types.expr_type(self))
+            }
+            ExprKind::Binary(op, left, right) => {
+                let ty = cx.il_type(cx.types.expr_type(left));
+                let left = left.visit_il(cx);
+                let right = right.visit_il(cx);
+                cx.binary(op.into(), ty, left, right)
             }
         }
     }
 }
 
+impl Expr {
+    /// Lowers an expression that denotes a location in memory, such as the
+    /// destination of an assignment or the base of a field access, returning
+    /// its address.
+    pub fn visit_place(&self, cx: &mut Lowering) -> il::Value {
+        match &self.kind {
+            ExprKind::Path(path) => match cx.variable(path) {
+                il::Variable::Memory(addr) => addr.clone(),
+                // Immutable aggregates are held by address.
+                il::Variable::Value(addr) if cx.types.expr_type(self).is_aggregate() => {
+                    addr.clone()
+                }
+                il::Variable::Value(_) => {
+                    unreachable!("immutable variables are rejected by the type checker")
+                }
+            },
+            ExprKind::Suffix(base, SuffixOp::ArrayIndex(index)) => {
+                let base = base.visit_place(cx);
+                let index_type = cx.il_type(cx.types.expr_type(index));
+                let index = index.visit_il(cx);
+                let index = cx.unary(il::UnaryOp::Convert, index_type, il::Type::Ptr, index);
+                let stride = cx.layouts.of(cx.types.expr_type(self)).size;
+                let stride = il::Value::Literal(il::Literal::Int(stride.into()));
+                let offset = cx.binary(il::BinaryOp::Mul, il::Type::Ptr, index, stride);
+                cx.binary(il::BinaryOp::Add, il::Type::Ptr, base, offset)
+            }
+            ExprKind::Suffix(base_expr, SuffixOp::FieldAccess(field)) => {
+                let base = base_expr.visit_place(cx);
+                let typeck::Type::Struct(name) = cx.types.expr_type(base_expr) else {
+                    unreachable!("field access on a non-struct type");
+                };
+                let offset = cx.layouts.field_offset(name, field);
+                cx.offset(base, offset)
+            }
+            // Other aggregate values are already lowered to their address.
+            _ if cx.types.expr_type(self).is_aggregate() => self.visit_il(cx),
+            _ => unreachable!("assignment destinations are checked by the type checker"),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct StructInit {
     pub name: Path,
     pub fields: Vec<FieldInit>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct FieldInit {
     pub name: Ident,
     pub value: Expr,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -440,6 +840,60 @@ pub enum ArrayInit {
     },
 }
 
+impl ArrayInit {
+    /// Fills with more elements than this are lowered to a loop rather than
+    /// a store per element.
+    const MAX_UNROLLED_FILL: u64 = 8;
+
+    /// Stores the elements of an array of type `ty` at `addr`.
+    fn visit_il(&self, addr: il::Value, ty: &typeck::Type, cx: &mut Lowering) {
+        let typeck::Type::Array(element_type, len) = ty else {
+            unreachable!("array initializer of a non-array type");
+        };
+        let stride = cx.layouts.of(element_type).size;
+        match self {
+            Self::Elements(elements) => {
+                for (i, element) in elements.iter().enumerate() {
+                    let value = element.visit_il(cx);
+                    let element_addr = cx.offset(addr.clone(), i as u64 * stride);
+                    cx.write(element_addr, value, element_type);
+                }
+            }
+            Self::Fill { element, .. } if *len <= Self::MAX_UNROLLED_FILL => {
+                let value = element.visit_il(cx);
+                for i in 0..*len {
+                    let element_addr = cx.offset(addr.clone(), i * stride);
+                    cx.write(element_addr, value.clone(), element_type);
+                }
+            }
+            Self::Fill { element, .. } => {
+                // The cursor holds the address of the next element to write.
+                let value = element.visit_il(cx);
+                let end = cx.offset(addr.clone(), len * stride);
+                let cursor = cx.alloc_layout(Layout::scalar(il::Type::Ptr));
+                cx.store(cursor.clone(), addr, il::Type::Ptr);
+
+                let head = cx.assembly.new_label();
+                let exit = cx.assembly.new_label();
+                cx.assembly.set_label(head);
+                let element_addr = cx.load(cursor.clone(), il::Type::Ptr);
+                let condition =
+                    cx.binary(il::BinaryOp::Lt, il::Type::Ptr, element_addr.clone(), end);
+                cx.assembly
+                    .push(il::Instruction::Continuation(il::Continuation::BranchZero(
+                        condition, exit,
+                    )));
+                cx.write(element_addr.clone(), value, element_type);
+                let next = cx.offset(element_addr, stride);
+                cx.store(cursor, next, il::Type::Ptr);
+                cx.assembly
+                    .push(il::Instruction::Continuation(il::Continuation::Jump(head)));
+                cx.assembly.set_label(exit);
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum PrefixOp {
     Not,
@@ -453,6 +907,14 @@ impl From<&PrefixOp> for il::UnaryOp {
     }
 }
 
+impl fmt::Display for PrefixOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Not => write!(f, "!"),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum SuffixOp {
     FieldAccess(Ident),
@@ -478,8 +940,21 @@ impl From<&BinaryOp> for il::BinaryOp {
     }
 }
 
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            Self::Plus => "+",
+            Self::Minus => "-",
+            Self::CmpEq => "==",
+            Self::LogicAnd => "&&",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
 impl BinaryOp {
-    fn precedence(&self) -> usize {
+    /// Operators of higher precedence bind more tightly.
+    pub fn precedence(&self) -> usize {
         match self {
             Self::Plus | Self::Minus => 2,
             Self::CmpEq => 1,
@@ -488,9 +963,7 @@ impl BinaryOp {
     }
 
     fn is_left_associative(&self) -> bool {
-        match self {
-            _ => true,
-        }
+        true
     }
 }
 
@@ -498,27 +971,37 @@ impl BinaryOp {
 pub struct StructItem {
     pub name: Ident,
     pub fields: Vec<Field>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct Field {
     pub field_name: Ident,
     pub field_type: Type,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct EnumItem {
     pub name: Ident,
     pub variants: Vec<Variant>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct Variant {
     pub name: Ident,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
-pub enum Statement {
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum StatementKind {
     Block(Block),
     Let(Let),
     Assign(Assign),
@@ -529,22 +1012,27 @@ pub enum Statement {
 }
 
 impl Statement {
-    pub fn visit_il(&self, scope: &mut il::Scope, assembly: &mut il::Assembly) {
-        match self {
-            Self::Block(block) => {
-                block.visit_il(scope, assembly);
+    pub fn visit_il(&self, cx: &mut Lowering) {
+        match &self.kind {
+            StatementKind::Block(block) => {
+                block.visit_il(cx);
             }
-            Self::Let(lett) => lett.visit_il(assembly),
-            Self::Assign(assign) => assign.visit_il(assembly),
-            Self::If(iff) => iff.visit_il(scope, assembly),
-            Self::For(forr) => forr.visit_il(assembly),
-            Self::Return(expr) => {
-                let expr = expr.visit_il(scope, assembly);
-                assembly.push(il::Instruction::Continuation(il::Continuation::Return(
-                    expr,
-                )));
+            StatementKind::Let(lett) => lett.visit_il(cx.types.resolutions.binding(self), cx),
+            StatementKind::Assign(assign) => assign.visit_il(cx),
+            StatementKind::If(iff) => iff.visit_il(cx),
+            StatementKind::For(forr) => forr.visit_il(cx.types.resolutions.binding(self), cx),
+            StatementKind::Return(expr) => {
+                let expr = expr.visit_il(cx);
+                cx.ret(expr);
+            }
+            StatementKind::Break => {
+                let exit = *cx
+                    .loop_exits
+                    .last()
+                    .expect("`break` outside of a loop is rejected by the type checker");
+                cx.assembly
+                    .push(il::Instruction::Continuation(il::Continuation::Jump(exit)));
             }
-            Self::Break => todo!(),
         }
     }
 }
@@ -558,8 +1046,35 @@ pub struct Let {
 }
 
 impl Let {
-    fn visit_il(&self, assembly: &mut il::Assembly) {
-        todo!()
+    fn visit_il(&self, id: LocalId, cx: &mut Lowering) {
+        let value = self.value.visit_il(cx);
+        let ty = cx.types.expr_type(&self.value);
+        // Immutable bindings simply name the value of their initializer;
+        // mutable ones need a stack slot that assignments can update.
+        let variable = if ty.is_aggregate() {
+            // Aggregates already live in memory, but one that names existing
+            // memory is copied so that the binding has a value of its own.
+            let addr = match self.value.kind {
+                ExprKind::StructInit(_) | ExprKind::ArrayInit(_) => value,
+                _ => {
+                    let addr = cx.alloc(ty);
+                    cx.write(addr.clone(), value, ty);
+                    addr
+                }
+            };
+            if self.is_mut {
+                il::Variable::Memory(addr)
+            } else {
+                il::Variable::Value(addr)
+            }
+        } else if self.is_mut {
+            let addr = cx.alloc(ty);
+            cx.write(addr.clone(), value, ty);
+            il::Variable::Memory(addr)
+        } else {
+            il::Variable::Value(value)
+        };
+        cx.declare(id, variable);
     }
 }
 
@@ -570,8 +1085,10 @@ pub struct Assign {
 }
 
 impl Assign {
-    fn visit_il(&self, assembly: &mut il::Assembly) {
-        todo!()
+    fn visit_il(&self, cx: &mut Lowering) {
+        let value = self.src.visit_il(cx);
+        let addr = self.dest.visit_place(cx);
+        cx.write(addr, value, cx.types.expr_type(&self.dest));
     }
 }
 
@@ -582,32 +1099,34 @@ pub struct If {
 }
 
 impl If {
-    fn visit_il(&self, scope: &mut il::Scope, assembly: &mut il::Assembly) {
+    fn visit_il(&self, cx: &mut Lowering) {
         let end = if self.cases.len() > 1 || self.else_case.is_some() {
-            Some(assembly.new_label())
+            Some(cx.assembly.new_label())
         } else {
             None
         };
 
         for case in &self.cases {
-            let condition = case.condition.visit_il(scope, assembly);
-            let skip = assembly.new_label();
-            assembly.push(il::Instruction::Continuation(il::Continuation::BranchZero(
-                condition, skip,
-            )));
-            case.body.visit_il(scope, assembly);
+            let condition = case.condition.visit_il(cx);
+            let skip = cx.assembly.new_label();
+            cx.assembly
+                .push(il::Instruction::Continuation(il::Continuation::BranchZero(
+                    condition, skip,
+                )));
+            case.body.visit_il(cx);
             if let Some(end) = end {
-                assembly.push(il::Instruction::Continuation(il::Continuation::Jump(end)));
+                cx.assembly
+                    .push(il::Instruction::Continuation(il::Continuation::Jump(end)));
             }
-            assembly.set_label(skip);
+            cx.assembly.set_label(skip);
         }
 
         if let Some(else_case) = &self.else_case {
-            else_case.visit_il(scope, assembly);
+            else_case.visit_il(cx);
         }
 
         if let Some(end) = end {
-            assembly.set_label(end);
+            cx.assembly.set_label(end);
         }
     }
 }
@@ -616,6 +1135,7 @@ impl If {
 pub struct IfCase {
     pub condition: Expr,
     pub body: Block,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -626,8 +1146,46 @@ pub struct For {
 }
 
 impl For {
-    fn visit_il(&self, assembly: &mut il::Assembly) {
-        todo!()
+    fn visit_il(&self, id: LocalId, cx: &mut Lowering) {
+        let Iterable::Range(start, end) = &self.iterable;
+        let ty = cx.il_type(&typeck::Type::DEFAULT_INT);
+        let counter = cx.alloc(&typeck::Type::DEFAULT_INT);
+        cx.store(
+            counter.clone(),
+            il::Value::Literal(il::Literal::Int(start.clone())),
+            ty,
+        );
+
+        let head = cx.assembly.new_label();
+        let exit = cx.assembly.new_label();
+        cx.assembly.set_label(head);
+        let index = cx.load(counter.clone(), ty);
+        let condition = cx.binary(
+            il::BinaryOp::Lt,
+            ty,
+            index.clone(),
+            il::Value::Literal(il::Literal::Int(end.clone())),
+        );
+        cx.assembly
+            .push(il::Instruction::Continuation(il::Continuation::BranchZero(
+                condition, exit,
+            )));
+
+        cx.declare(id, il::Variable::Value(index.clone()));
+        cx.loop_exits.push(exit);
+        self.body.visit_il(cx);
+        cx.loop_exits.pop();
+
+        let next = cx.binary(
+            il::BinaryOp::Add,
+            ty,
+            index,
+            il::Value::Literal(il::Literal::Int(1.into())),
+        );
+        cx.store(counter, next, ty);
+        cx.assembly
+            .push(il::Instruction::Continuation(il::Continuation::Jump(head)));
+        cx.assembly.set_label(exit);
     }
 }
 
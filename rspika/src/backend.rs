@@ -0,0 +1,6 @@
+//! Code generation backends.
+
+pub mod c;
+pub mod qbe;
+pub mod wasm;
+pub mod x86_64;
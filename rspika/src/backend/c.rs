@@ -0,0 +1,482 @@
+//! Emits C99 source for checked modules.
+//!
+//! Unlike the other backends this one works on the AST, so that structs,
+//! enums and arrays keep their names and shape. Arrays are wrapped in a
+//! struct per array type, as C arrays cannot be assigned or returned.
+//!
+//! Every name from the source is prefixed with `p_`, and enum variants with
+//! `v_` and the name of their enum, so that they cannot clash with keywords,
+//! the names from the standard headers or the array types and helpers.
+//!
+//! Integer arithmetic wraps around like in Pika: signed operands are
+//! converted to their unsigned counterpart for the operation, whose result
+//! is converted back, which relies on the conversion wrapping around as it
+//! does with every common C compiler. Indexing is not bounds-checked.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Write},
+};
+
+use crate::{
+    ast, il,
+    token::Ident,
+    typeck::{self, TypeckResults},
+};
+
+pub fn emit_module(module: &ast::Module, types: &TypeckResults) -> String {
+    let mut out = String::new();
+    write_module(&mut out, module, types).expect("writing to a String cannot fail");
+    out
+}
+
+pub fn write_module(
+    out: &mut impl Write,
+    module: &ast::Module,
+    types: &TypeckResults,
+) -> fmt::Result {
+    let mut cx = Context {
+        types,
+        arrays: Vec::new(),
+        fills: Vec::new(),
+    };
+    let mut functions = String::new();
+    for item in &module.items {
+        if let ast::Item::Fn(fnn) = item {
+            writeln!(functions)?;
+            FnContext::new(&mut cx).function(&mut functions, fnn)?;
+        }
+    }
+
+    writeln!(out, "#include <stdbool.h>")?;
+    writeln!(out, "#include <stdint.h>")?;
+    writeln!(out, "#include <stdlib.h>")?;
+    for item in &module.items {
+        if let ast::Item::Enum(enumm) = item {
+            writeln!(out)?;
+            writeln!(out, "enum {} {{", user_name(&enumm.name))?;
+            for variant in &enumm.variants {
+                writeln!(out, "\t{},", variant_name(&enumm.name, &variant.name))?;
+            }
+            writeln!(out, "}};")?;
+        }
+    }
+
+    // Types have to be defined before the types containing them.
+    let mut defined = HashSet::new();
+    for item in &module.items {
+        if let ast::Item::Struct(strukt) = item {
+            cx.define(
+                out,
+                &typeck::Type::Struct(strukt.name.clone()),
+                &mut defined,
+            )?;
+        }
+    }
+    for ty in cx.arrays.clone() {
+        cx.define(out, &ty, &mut defined)?;
+    }
+
+    for ty in cx.fills.clone() {
+        let typeck::Type::Array(element, len) = &ty else {
+            unreachable!("fill of a non-array type");
+        };
+        let name = cx.c_type(&ty);
+        writeln!(out)?;
+        writeln!(
+            out,
+            "static {} {}_fill({} element) {{",
+            name,
+            name,
+            cx.c_type(element)
+        )?;
+        writeln!(out, "\t{} array;", name)?;
+        writeln!(out, "\tfor (size_t i = 0; i < {}; i++) {{", len)?;
+        writeln!(out, "\t\tarray.e[i] = element;")?;
+        writeln!(out, "\t}}")?;
+        writeln!(out, "\treturn array;")?;
+        writeln!(out, "}}")?;
+    }
+
+    write!(out, "{}", functions)
+}
+
+/// State shared by all the functions of a module.
+struct Context<'a> {
+    types: &'a TypeckResults,
+    /// The array types used, in order of first use.
+    arrays: Vec<typeck::Type>,
+    /// The array types filled with copies of one element.
+    fills: Vec<typeck::Type>,
+}
+
+impl Context<'_> {
+    /// Returns the C type for values of type `ty`.
+    fn c_type(&mut self, ty: &typeck::Type) -> String {
+        match ty {
+            typeck::Type::Primitive(ty) => primitive_type(*ty).to_string(),
+            typeck::Type::Bool => "bool".to_string(),
+            typeck::Type::Struct(name) => format!("struct {}", user_name(name)),
+            typeck::Type::Enum(name) => format!("enum {}", user_name(name)),
+            typeck::Type::Array(..) => {
+                if !self.arrays.contains(ty) {
+                    self.arrays.push(ty.clone());
+                }
+                array_name(ty)
+            }
+            typeck::Type::Unit => "void".to_string(),
+            typeck::Type::Error => unreachable!("modules with type errors are not compiled"),
+        }
+    }
+
+    /// Writes the definition of struct or array type `ty` after those of
+    /// the types it contains, unless it is already `defined`.
+    fn define(
+        &mut self,
+        out: &mut impl Write,
+        ty: &typeck::Type,
+        defined: &mut HashSet<typeck::Type>,
+    ) -> fmt::Result {
+        if !defined.insert(ty.clone()) {
+            return Ok(());
+        }
+        match ty {
+            typeck::Type::Struct(name) => {
+                let fields = self.types.structs[name].fields.clone();
+                for (_, field_type) in &fields {
+                    self.define(out, field_type, defined)?;
+                }
+                writeln!(out)?;
+                writeln!(out, "struct {} {{", user_name(name))?;
+                for (field, field_type) in &fields {
+                    writeln!(out, "\t{} {};", self.c_type(field_type), user_name(field))?;
+                }
+                writeln!(out, "}};")
+            }
+            typeck::Type::Array(element, len) => {
+                self.define(out, element, defined)?;
+                writeln!(out)?;
+                writeln!(out, "typedef struct {{")?;
+                writeln!(out, "\t{} e[{}];", self.c_type(element), len)?;
+                writeln!(out, "}} {};", self.c_type(ty))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// State for emitting a single function.
+struct FnContext<'a, 'b> {
+    cx: &'a mut Context<'b>,
+    /// The C names of the variables in scope, innermost scope last.
+    scopes: Vec<HashMap<Ident, String>>,
+    /// All the names declared in the function so far. C does not allow
+    /// redeclaring a name in the same scope, so shadowing bindings get a
+    /// fresh name.
+    declared: HashSet<String>,
+}
+
+impl<'a, 'b> FnContext<'a, 'b> {
+    fn new(cx: &'a mut Context<'b>) -> Self {
+        Self {
+            cx,
+            scopes: Vec::new(),
+            declared: HashSet::new(),
+        }
+    }
+
+    fn declare(&mut self, name: &Ident) -> String {
+        let base = user_name(name);
+        let mut c_name = base.clone();
+        let mut suffix = 1;
+        while !self.declared.insert(c_name.clone()) {
+            c_name = format!("{}_{}", base, suffix);
+            suffix += 1;
+        }
+        self.scopes
+            .last_mut()
+            .expect("no scope to declare in")
+            .insert(name.clone(), c_name.clone());
+        c_name
+    }
+
+    fn lookup(&self, name: &Ident) -> &str {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .expect("variables are checked by name resolution")
+    }
+
+    fn function(&mut self, out: &mut String, fnn: &ast::FnItem) -> fmt::Result {
+        let sig = &self.cx.types.functions[&fnn.name];
+        let return_type = sig.return_type.clone();
+        let params = sig.params.clone();
+
+        self.scopes.push(HashMap::new());
+        write!(
+            out,
+            "{} {}(",
+            self.cx.c_type(&return_type),
+            user_name(&fnn.name)
+        )?;
+        if fnn.args.is_empty() {
+            write!(out, "void")?;
+        }
+        for (i, (arg, ty)) in fnn.args.iter().zip(&params).enumerate() {
+            if i > 0 {
+                write!(out, ", ")?;
+            }
+            let name = self.declare(&arg.arg_name);
+            write!(out, "{} {}", self.cx.c_type(ty), name)?;
+        }
+        writeln!(out, ") {{")?;
+
+        self.statements(out, &fnn.body, 1)?;
+        match &fnn.body.expr {
+            Some(expr) if return_type != typeck::Type::Unit => {
+                let expr = self.expr(expr);
+                writeln!(out, "\treturn {};", expr)?;
+            }
+            Some(expr) => {
+                let expr = self.expr(expr);
+                writeln!(out, "\t(void)({});", expr)?;
+            }
+            // The type checker ensures that a body without a tail expression
+            // always returns unless the function returns `()`, but the C
+            // compiler cannot always tell.
+            None if return_type != typeck::Type::Unit && !ends_in_return(&fnn.body) => {
+                writeln!(out, "\tabort();")?
+            }
+            None => {}
+        }
+        writeln!(out, "}}")?;
+        self.scopes.pop();
+        Ok(())
+    }
+
+    /// Writes a block whose tail expression is unused.
+    fn block(&mut self, out: &mut String, block: &ast::Block, depth: usize) -> fmt::Result {
+        self.scopes.push(HashMap::new());
+        self.statements(out, block, depth)?;
+        if let Some(expr) = &block.expr {
+            let expr = self.expr(expr);
+            writeln!(out, "{}(void)({});", indent(depth), expr)?;
+        }
+        self.scopes.pop();
+        Ok(())
+    }
+
+    fn statements(&mut self, out: &mut String, block: &ast::Block, depth: usize) -> fmt::Result {
+        for stmt in &block.statements {
+            self.statement(out, stmt, depth)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, out: &mut String, stmt: &ast::Statement, depth: usize) -> fmt::Result {
+        let tabs = indent(depth);
+        match &stmt.kind {
+            ast::StatementKind::Block(block) => {
+                writeln!(out, "{}{{", tabs)?;
+                self.block(out, block, depth + 1)?;
+                writeln!(out, "{}}}", tabs)
+            }
+            ast::StatementKind::Let(lett) => {
+                let ty = self.cx.types.expr_type(&lett.value).clone();
+                let value = self.expr(&lett.value);
+                let ty = self.cx.c_type(&ty);
+                let name = self.declare(&lett.binding);
+                writeln!(out, "{}{} {} = {};", tabs, ty, name, value)
+            }
+            ast::StatementKind::Assign(assign) => {
+                let dest = self.expr(&assign.dest);
+                let src = self.expr(&assign.src);
+                writeln!(out, "{}{} = {};", tabs, dest, src)
+            }
+            ast::StatementKind::If(iff) => {
+                for (i, case) in iff.cases.iter().enumerate() {
+                    let condition = self.expr(&case.condition);
+                    if i == 0 {
+                        writeln!(out, "{}if ({}) {{", tabs, condition)?;
+                    } else {
+                        writeln!(out, "{}}} else if ({}) {{", tabs, condition)?;
+                    }
+                    self.block(out, &case.body, depth + 1)?;
+                }
+                if let Some(else_case) = &iff.else_case {
+                    writeln!(out, "{}}} else {{", tabs)?;
+                    self.block(out, else_case, depth + 1)?;
+                }
+                writeln!(out, "{}}}", tabs)
+            }
+            ast::StatementKind::For(forr) => {
+                let ast::Iterable::Range(start, end) = &forr.iterable;
+                self.scopes.push(HashMap::new());
+                let name = self.declare(&forr.binding);
+                let ty = self.cx.c_type(&typeck::Type::DEFAULT_INT);
+                writeln!(
+                    out,
+                    "{}for ({} {} = {}; {} < {}; {}++) {{",
+                    tabs,
+                    ty,
+                    name,
+                    int_literal(start),
+                    name,
+                    int_literal(end),
+                    name
+                )?;
+                self.block(out, &forr.body, depth + 1)?;
+                self.scopes.pop();
+                writeln!(out, "{}}}", tabs)
+            }
+            ast::StatementKind::Return(expr) => {
+                if *self.cx.types.expr_type(expr) == typeck::Type::Unit {
+                    writeln!(out, "{}return;", tabs)
+                } else {
+                    let expr = self.expr(expr);
+                    writeln!(out, "{}return {};", tabs, expr)
+                }
+            }
+            ast::StatementKind::Break => writeln!(out, "{}break;", tabs),
+        }
+    }
+
+    fn expr(&mut self, expr: &ast::Expr) -> String {
+        let ty = self.cx.types.expr_type(expr).clone();
+        match &expr.kind {
+            ast::ExprKind::Path(path) => match path.elements.as_slice() {
+                [name] => self.lookup(name).to_string(),
+                [name, variant] => variant_name(name, variant),
+                _ => unreachable!("paths are checked by name resolution"),
+            },
+            ast::ExprKind::IntLiteral(int) => int_literal(int),
+            ast::ExprKind::BoolLiteral(b) => b.to_string(),
+            ast::ExprKind::StructInit(init) => {
+                let fields: Vec<String> = init
+                    .fields
+                    .iter()
+                    .map(|field| {
+                        format!(".{} = {}", user_name(&field.name), self.expr(&field.value))
+                    })
+                    .collect();
+                format!("({}){{ {} }}", self.cx.c_type(&ty), fields.join(", "))
+            }
+            ast::ExprKind::ArrayInit(ast::ArrayInit::Elements(elements)) => {
+                let elements: Vec<String> =
+                    elements.iter().map(|element| self.expr(element)).collect();
+                format!(
+                    "({}){{ {{ {} }} }}",
+                    self.cx.c_type(&ty),
+                    elements.join(", ")
+                )
+            }
+            ast::ExprKind::ArrayInit(ast::ArrayInit::Fill { element, .. }) => {
+                if !self.cx.fills.contains(&ty) {
+                    self.cx.fills.push(ty.clone());
+                }
+                let element = self.expr(element);
+                format!("{}_fill({})", self.cx.c_type(&ty), element)
+            }
+            ast::ExprKind::Prefix(ast::PrefixOp::Not, operand) => {
+                format!("!{}", self.expr(operand))
+            }
+            ast::ExprKind::Suffix(base, ast::SuffixOp::FieldAccess(field)) => {
+                format!("{}.{}", self.expr(base), user_name(field))
+            }
+            ast::ExprKind::Suffix(base, ast::SuffixOp::ArrayIndex(index)) => {
+                format!("{}.e[{}]", self.expr(base), self.expr(index))
+            }
+            ast::ExprKind::Binary(op, left, right) => {
+                let (left, right) = (self.expr(left), self.expr(right));
+                // The operators are spelled the same in C.
+                match ty {
+                    typeck::Type::Primitive(ty) if ty.is_integer() => {
+                        let unsigned = primitive_type(unsigned(ty));
+                        format!(
+                            "({})(({}){} {} ({}){})",
+                            primitive_type(ty),
+                            unsigned,
+                            left,
+                            op,
+                            unsigned,
+                            right
+                        )
+                    }
+                    _ => format!("({} {} {})", left, op, right),
+                }
+            }
+        }
+    }
+}
+
+fn primitive_type(ty: il::Type) -> &'static str {
+    match ty {
+        il::Type::I8 => "int8_t",
+        il::Type::I16 => "int16_t",
+        il::Type::I32 => "int32_t",
+        il::Type::I64 => "int64_t",
+        il::Type::U8 => "uint8_t",
+        il::Type::U16 => "uint16_t",
+        il::Type::U32 => "uint32_t",
+        il::Type::U64 => "uint64_t",
+        il::Type::Isize => "intptr_t",
+        il::Type::Usize => "uintptr_t",
+        il::Type::F32 => "float",
+        il::Type::F64 => "double",
+        il::Type::Ptr => unreachable!("pointers are not a source type"),
+    }
+}
+
+/// Returns the unsigned integer type of the same size as `ty`.
+fn unsigned(ty: il::Type) -> il::Type {
+    match ty {
+        il::Type::I8 => il::Type::U8,
+        il::Type::I16 => il::Type::U16,
+        il::Type::I32 => il::Type::U32,
+        il::Type::I64 => il::Type::U64,
+        il::Type::Isize => il::Type::Usize,
+        ty => ty,
+    }
+}
+
+/// Returns the name of the struct wrapping arrays of type `ty`, e.g.
+/// `Array_2_Array_8_i32` for `[[i32; 8]; 2]`.
+fn array_name(ty: &typeck::Type) -> String {
+    match ty {
+        typeck::Type::Array(element, len) => format!("Array_{}_{}", len, array_name(element)),
+        typeck::Type::Struct(name) | typeck::Type::Enum(name) => user_name(name),
+        ty => ty.to_string(),
+    }
+}
+
+fn variant_name(name: &Ident, variant: &Ident) -> String {
+    format!("v_{}_{}", name, variant)
+}
+
+fn int_literal(int: &crate::token::IntLiteral) -> String {
+    // Unsuffixed literals have type `int` if they fit.
+    match int.value() {
+        Some(value) if value > i32::MAX as u64 => format!("UINT64_C({})", value),
+        _ => int.to_string(),
+    }
+}
+
+/// Returns the C identifier for the source name `name`.
+fn user_name(name: &Ident) -> String {
+    format!("p_{}", name)
+}
+
+fn ends_in_return(block: &ast::Block) -> bool {
+    matches!(
+        block.statements.last(),
+        Some(ast::Statement {
+            kind: ast::StatementKind::Return(_),
+            ..
+        })
+    )
+}
+
+fn indent(depth: usize) -> String {
+    "\t".repeat(depth)
+}
@@ -0,0 +1,427 @@
+//! Emits the textual IL consumed by the QBE compiler backend.
+//!
+//! https://c9x.me/compile/doc/il.html
+//!
+//! Temporaries are named `%t<n>`, function arguments `%a<n>` and labels
+//! `@l<n>`, after their index in the IL. Labels at the start of a function
+//! name its `@start` block. `%c<n>` holds the condition of a branch on a
+//! long at position `n`.
+//!
+//! Values of 32 bits or less are held in words and 64-bit values and
+//! pointers in longs. Values narrower than a word are kept sign- or
+//! zero-extended to a full word, so that comparisons and division on words
+//! give the right answer; the results of arithmetic on them are extended
+//! again after each operation.
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Write},
+};
+
+use crate::{il, token::Ident};
+
+pub fn emit_module(module: &il::Module) -> String {
+    let mut out = String::new();
+    write_module(&mut out, module).expect("writing to a String cannot fail");
+    out
+}
+
+pub fn write_module(out: &mut impl Write, module: &il::Module) -> fmt::Result {
+    let mut functions: Vec<_> = module.functions.iter().collect();
+    functions.sort_by_key(|(name, _)| name.to_string());
+
+    for (i, (name, function)) in functions.into_iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        write_function(out, module, name, function)?;
+    }
+    Ok(())
+}
+
+/// Writes `function`, a member of `module`, which is needed for the
+/// signatures of the functions it calls.
+pub fn write_function(
+    out: &mut impl Write,
+    module: &il::Module,
+    name: &Ident,
+    function: &il::Function,
+) -> fmt::Result {
+    let assembly = &function.assembly;
+    let instructions = assembly.instructions();
+
+    write!(out, "export function ")?;
+    if let Some(ty) = function.return_type {
+        write!(out, "{} ", class(ty))?;
+    }
+    write!(out, "${}(", name)?;
+    for (i, &ty) in function.params.iter().enumerate() {
+        if i > 0 {
+            write!(out, ", ")?;
+        }
+        write!(out, "{} %a{}", class(ty), i)?;
+    }
+    writeln!(out, ") {{")?;
+    writeln!(out, "@start")?;
+
+    // QBE requires every block to begin with a label, and conditional jumps
+    // to name both of their targets, so fresh labels are created past the
+    // ones used by the assembly wherever the IL relies on fallthrough.
+    let mut next_label = (0..=instructions.len())
+        .flat_map(|position| assembly.labels_at(position))
+        .map(|label| label.index() + 1)
+        .max()
+        .unwrap_or(0);
+    // Several labels at one position are written as the first of them, as
+    // QBE would otherwise see an empty block for each of the others. Labels
+    // at the very start name the start block.
+    let canonical: HashMap<il::Label, String> = (0..=instructions.len())
+        .flat_map(|position| {
+            let labels = assembly.labels_at(position);
+            let name = match position {
+                0 => "start".to_string(),
+                _ => labels
+                    .first()
+                    .map(|label| format!("l{}", label.index()))
+                    .unwrap_or_default(),
+            };
+            labels.iter().map(move |label| (*label, name.clone()))
+        })
+        .collect();
+    let label = |label: &il::Label| &canonical[label];
+    let mut fresh_label = || {
+        let label = format!("l{}", next_label);
+        next_label += 1;
+        label
+    };
+
+    let types: HashMap<il::Temporary, il::Type> = instructions
+        .iter()
+        .filter_map(|instr| Some((instr.output()?, instr.output_type()?)))
+        .collect();
+    let value_type = |value: &il::Value| match value {
+        il::Value::Temporary(temporary) => types.get(temporary).copied(),
+        il::Value::Argument(argument) => function.params.get(argument.0).copied(),
+        il::Value::Literal(_) => None,
+    };
+
+    let mut pending_label = None;
+    let mut terminated = false;
+    for position in 0..=instructions.len() {
+        let labels = assembly.labels_at(position);
+        if let Some(first) = labels.first().filter(|_| position > 0) {
+            writeln!(out, "@{}", label(first))?;
+        }
+        if let Some(label) = pending_label.take() {
+            writeln!(out, "@{}", label)?;
+        } else if terminated && labels.is_empty() && position < instructions.len() {
+            writeln!(out, "@{}", fresh_label())?;
+        }
+
+        let Some(instr) = instructions.get(position) else {
+            if !terminated || !labels.is_empty() {
+                writeln!(out, "\thlt")?;
+            }
+            break;
+        };
+
+        terminated = false;
+        match instr {
+            il::Instruction::Continuation(continuation) => {
+                terminated = true;
+                let mut fallthrough = || match assembly.labels_at(position + 1).first() {
+                    Some(next) => (label(next).clone(), None),
+                    None => {
+                        let label = fresh_label();
+                        (label.clone(), Some(label))
+                    }
+                };
+                match continuation {
+                    il::Continuation::Jump(target) => {
+                        writeln!(out, "\tjmp @{}", label(target))?;
+                    }
+                    il::Continuation::BranchZero(value, target) => {
+                        let condition = write_condition(out, value, value_type(value), position)?;
+                        let (next, fresh) = fallthrough();
+                        pending_label = fresh;
+                        writeln!(out, "\tjnz {}, @{}, @{}", condition, next, label(target))?;
+                    }
+                    il::Continuation::BranchNonZero(value, target) => {
+                        let condition = write_condition(out, value, value_type(value), position)?;
+                        let (next, fresh) = fallthrough();
+                        pending_label = fresh;
+                        writeln!(out, "\tjnz {}, @{}, @{}", condition, label(target), next)?;
+                    }
+                    il::Continuation::Return(value) => match function.return_type {
+                        Some(ty) => writeln!(out, "\tret {}", Value(value, ty))?,
+                        None => writeln!(out, "\tret")?,
+                    },
+                    il::Continuation::Halt => {
+                        writeln!(out, "\thlt")?;
+                    }
+                }
+            }
+            il::Instruction::Phi(output, sources) => {
+                let ty = output.dest_type;
+                write!(out, "\t{} ={} phi", Temporary(output.dest), class(ty))?;
+                for (i, (source, value)) in sources.iter().enumerate() {
+                    let sep = if i > 0 { "," } else { "" };
+                    write!(out, "{} @{} {}", sep, label(source), Value(value, ty))?;
+                }
+                writeln!(out)?;
+            }
+            instr => write_instruction(out, module, instr)?,
+        }
+    }
+
+    writeln!(out, "}}")
+}
+
+fn write_instruction(
+    out: &mut impl Write,
+    module: &il::Module,
+    instr: &il::Instruction,
+) -> fmt::Result {
+    match instr {
+        il::Instruction::Operation(output, il::Operation::Binary(op, ty, left, right)) => {
+            let dest = Temporary(output.dest);
+            let (left, right) = (Value(left, *ty), Value(right, *ty));
+            let signed = ty.is_signed();
+            let name = match op {
+                il::BinaryOp::Add => "add",
+                il::BinaryOp::Sub => "sub",
+                il::BinaryOp::Mul => "mul",
+                il::BinaryOp::Div if signed => "div",
+                il::BinaryOp::Div => "udiv",
+                il::BinaryOp::Rem if signed => "rem",
+                il::BinaryOp::Rem => "urem",
+                il::BinaryOp::Or => "or",
+                il::BinaryOp::Xor => "xor",
+                il::BinaryOp::And => "and",
+                il::BinaryOp::Shr if signed => "sar",
+                il::BinaryOp::Shr => "shr",
+                il::BinaryOp::Shl => "shl",
+                il::BinaryOp::Eq => "ceq",
+                il::BinaryOp::Ne => "cne",
+                il::BinaryOp::Lt if signed => "cslt",
+                il::BinaryOp::Lt => "cult",
+                il::BinaryOp::Le if signed => "csle",
+                il::BinaryOp::Le => "cule",
+                il::BinaryOp::Gt if signed => "csgt",
+                il::BinaryOp::Gt => "cugt",
+                il::BinaryOp::Ge if signed => "csge",
+                il::BinaryOp::Ge => "cuge",
+            };
+            let dest_class = class(output.dest_type);
+            if op.is_comparison() {
+                return writeln!(
+                    out,
+                    "\t{} ={} {}{} {}, {}",
+                    dest,
+                    dest_class,
+                    name,
+                    class(*ty),
+                    left,
+                    right
+                );
+            }
+            if matches!(op, il::BinaryOp::Shl | il::BinaryOp::Shr) && ty.size() < 4 {
+                // QBE only takes shift amounts modulo the width of a word.
+                writeln!(out, "\t{} =w and {}, {}", dest, right, ty.size() * 8 - 1)?;
+                writeln!(out, "\t{} =w {} {}, {}", dest, name, left, dest)?;
+            } else {
+                writeln!(
+                    out,
+                    "\t{} ={} {} {}, {}",
+                    dest, dest_class, name, left, right
+                )?;
+            }
+            write_extension(out, output)
+        }
+        il::Instruction::Operation(output, il::Operation::Unary(op, ty, value)) => {
+            let dest = Temporary(output.dest);
+            let dest_class = class(output.dest_type);
+            let value = Value(value, *ty);
+            match op {
+                il::UnaryOp::Neg => writeln!(out, "\t{} ={} neg {}", dest, dest_class, value)?,
+                il::UnaryOp::Not => writeln!(
+                    out,
+                    "\t{} ={} ceq{} {}, 0",
+                    dest,
+                    dest_class,
+                    class(*ty),
+                    value
+                )?,
+                il::UnaryOp::Convert if dest_class == "l" && class(*ty) == "w" => {
+                    let ext = if ty.is_signed() { "extsw" } else { "extuw" };
+                    writeln!(out, "\t{} =l {} {}", dest, ext, value)?
+                }
+                il::UnaryOp::Convert | il::UnaryOp::Cast => {
+                    writeln!(out, "\t{} ={} copy {}", dest, dest_class, value)?
+                }
+            }
+            write_extension(out, output)
+        }
+        il::Instruction::Call(output, call) => {
+            write!(out, "\t")?;
+            if let Some(output) = output {
+                write!(
+                    out,
+                    "{} ={} ",
+                    Temporary(output.dest),
+                    class(output.dest_type)
+                )?;
+            }
+            write!(out, "call ${}(", call.function_name)?;
+            let callee = module
+                .function(&call.function_name)
+                .expect("call to unknown function");
+            for (i, (argument, &ty)) in call.arguments.iter().zip(&callee.params).enumerate() {
+                if i > 0 {
+                    write!(out, ", ")?;
+                }
+                write!(out, "{} {}", class(ty), Value(argument, ty))?;
+            }
+            writeln!(out, ")")
+        }
+        il::Instruction::Load { output, addr } => {
+            let load = match output.dest_type.size() {
+                1 if output.dest_type.is_signed() => "loadsb",
+                1 => "loadub",
+                2 if output.dest_type.is_signed() => "loadsh",
+                2 => "loaduh",
+                4 => "loadw",
+                _ => "loadl",
+            };
+            writeln!(
+                out,
+                "\t{} ={} {} {}",
+                Temporary(output.dest),
+                class(output.dest_type),
+                load,
+                Value(addr, il::Type::Ptr)
+            )
+        }
+        il::Instruction::Store { ty, addr, value } => {
+            let store = match ty.size() {
+                1 => "storeb",
+                2 => "storeh",
+                4 => "storew",
+                _ => "storel",
+            };
+            writeln!(
+                out,
+                "\t{} {}, {}",
+                store,
+                Value(value, *ty),
+                Value(addr, il::Type::Ptr)
+            )
+        }
+        il::Instruction::Alloc {
+            addr_output,
+            size,
+            alignment,
+        } => {
+            let alloc = match alignment {
+                0..=4 => "alloc4",
+                5..=8 => "alloc8",
+                _ => "alloc16",
+            };
+            writeln!(
+                out,
+                "\t{} =l {} {}",
+                Temporary(addr_output.dest),
+                alloc,
+                size
+            )
+        }
+        il::Instruction::Phi(..) | il::Instruction::Continuation(_) => {
+            unreachable!("phis and continuations are written by the caller")
+        }
+    }
+}
+
+/// Writes whatever is needed to branch on `value`, of type `ty` if it is
+/// not a literal, and returns the operand for `jnz`. As `jnz` only tests
+/// the low word of its operand, longs are first compared with zero.
+fn write_condition(
+    out: &mut impl Write,
+    value: &il::Value,
+    ty: Option<il::Type>,
+    position: usize,
+) -> Result<String, fmt::Error> {
+    match ty {
+        Some(ty) if class(ty) == "l" => {
+            let condition = format!("%c{}", position);
+            writeln!(out, "\t{} =w cnel {}, 0", condition, Value(value, ty))?;
+            Ok(condition)
+        }
+        _ => Ok(Condition(value).to_string()),
+    }
+}
+
+/// Extends the result of an operation narrower than a word back to a full
+/// word.
+fn write_extension(out: &mut impl Write, output: &il::Output) -> fmt::Result {
+    let ty = output.dest_type;
+    let ext = match (ty.size(), ty.is_signed()) {
+        (1, true) => "extsb",
+        (1, false) => "extub",
+        (2, true) => "extsh",
+        (2, false) => "extuh",
+        _ => return Ok(()),
+    };
+    let dest = Temporary(output.dest);
+    writeln!(out, "\t{} =w {} {}", dest, ext, dest)
+}
+
+/// Returns the QBE class holding values of type `ty`.
+fn class(ty: il::Type) -> &'static str {
+    match ty.size() {
+        8 => "l",
+        _ => "w",
+    }
+}
+
+struct Temporary(il::Temporary);
+
+impl fmt::Display for Temporary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "%t{}", self.0.index())
+    }
+}
+
+/// A value used as a value of the given type. Literals are written the way
+/// a value of that type is held, e.g. the bit pattern of -1 as an `i8` is
+/// written as -1 rather than 255.
+struct Value<'a>(&'a il::Value, il::Type);
+
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            il::Value::Temporary(temporary) => write!(f, "{}", Temporary(*temporary)),
+            il::Value::Argument(argument) => write!(f, "%a{}", argument.0),
+            il::Value::Literal(il::Literal::Int(int)) => {
+                let bits = int.value().expect("literal does not fit in 64 bits");
+                write!(f, "{}", self.1.extend(bits))
+            }
+            il::Value::Literal(il::Literal::Nil) => write!(f, "0"),
+        }
+    }
+}
+
+/// A branch condition, which is tested as a word.
+struct Condition<'a>(&'a il::Value);
+
+impl fmt::Display for Condition<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Any non-zero literal does, as long as it is non-zero as a word.
+        match self.0 {
+            il::Value::Literal(il::Literal::Int(int)) => {
+                let bits = int.value().expect("literal does not fit in 64 bits");
+                write!(f, "{}", u8::from(bits != 0))
+            }
+            value => write!(f, "{}", Value(value, il::Type::U32)),
+        }
+    }
+}
@@ -0,0 +1,1055 @@
+//! Emits WebAssembly modules, in the binary format or as WAT text.
+//!
+//! Every function is exported under its own name, along with the linear
+//! memory. Allocations live on a shadow stack in linear memory, which grows
+//! down from the top of memory and whose pointer is kept in a global; a
+//! function with allocations reserves a frame for all of them on entry.
+//! Temporaries and arguments become locals, and phis are assigned on each
+//! edge into their block, through the operand stack so that the phis of a
+//! block still take their values at the same time.
+//!
+//! WebAssembly only has structured control flow, so blocks are arranged
+//! into nested `block`s, `loop`s and `if`s following "Beyond Relooper" by
+//! Norman Ramsey, which handles any reducible control-flow graph.
+//!
+//! Values of 64-bit types are `i64`s and all others, including pointers,
+//! `i32`s. Values narrower than 32 bits are kept sign- or zero-extended
+//! from their type, and the results of arithmetic on them are extended
+//! again after each operation. Unlike in the VM, dividing the smallest
+//! 32-bit or 64-bit value by -1 traps.
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Write},
+};
+
+use crate::{
+    il::{
+        self,
+        cfg::{BlockId, Cfg, Dominators},
+        BinaryOp, Continuation, Instruction, Label, Operation, Temporary, Type, UnaryOp, Value,
+    },
+    token::Ident,
+};
+
+/// Size of the linear memory in pages, matching the VM.
+const MEMORY_PAGES: u32 = 16;
+
+const PAGE_SIZE: u32 = 1 << 16;
+
+/// Index of the global holding the shadow stack pointer.
+const STACK_POINTER: u32 = 0;
+
+/// Returns the module in the binary format.
+pub fn emit_module(module: &il::Module) -> Vec<u8> {
+    encode_module(&compile_module(module))
+}
+
+/// Returns the module in the WebAssembly text format.
+pub fn emit_wat(module: &il::Module) -> String {
+    let mut out = String::new();
+    write_wat(&mut out, module).expect("writing to a String cannot fail");
+    out
+}
+
+pub fn write_wat(out: &mut impl Write, module: &il::Module) -> fmt::Result {
+    let functions = compile_module(module);
+    writeln!(out, "(module")?;
+    writeln!(out, "  (memory (export \"memory\") {})", MEMORY_PAGES)?;
+    writeln!(
+        out,
+        "  (global $sp (mut i32) (i32.const {}))",
+        MEMORY_PAGES * PAGE_SIZE
+    )?;
+    for function in &functions {
+        write!(
+            out,
+            "  (func ${} (export \"{}\")",
+            function.name, function.name
+        )?;
+        let list =
+            |types: &[ValType]| -> String { types.iter().map(|ty| format!(" {}", ty)).collect() };
+        if !function.params.is_empty() {
+            write!(out, " (param{})", list(&function.params))?;
+        }
+        if let Some(result) = function.result {
+            write!(out, " (result {})", result)?;
+        }
+        if !function.locals.is_empty() {
+            write!(out, " (local{})", list(&function.locals))?;
+        }
+        writeln!(out)?;
+        let mut depth = 2;
+        for instr in &function.body {
+            if matches!(instr, Instr::Else | Instr::End) {
+                depth -= 1;
+            }
+            write!(out, "{}", "  ".repeat(depth))?;
+            match instr {
+                Instr::Call(index) => writeln!(out, "call ${}", functions[*index as usize].name)?,
+                Instr::GlobalGet(STACK_POINTER) => writeln!(out, "global.get $sp")?,
+                Instr::GlobalSet(STACK_POINTER) => writeln!(out, "global.set $sp")?,
+                instr => writeln!(out, "{}", instr)?,
+            }
+            if matches!(instr, Instr::Block | Instr::Loop | Instr::If | Instr::Else) {
+                depth += 1;
+            }
+        }
+        writeln!(out, "  )")?;
+    }
+    writeln!(out, ")")
+}
+
+struct WasmFunction {
+    name: String,
+    params: Vec<ValType>,
+    result: Option<ValType>,
+    /// Types of the locals besides the parameters.
+    locals: Vec<ValType>,
+    body: Vec<Instr>,
+}
+
+impl WasmFunction {
+    fn signature(&self) -> Signature {
+        (self.params.clone(), self.result)
+    }
+}
+
+/// The parameter and result types of a function.
+type Signature = (Vec<ValType>, Option<ValType>);
+
+/// A WebAssembly value type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum ValType {
+    I32,
+    I64,
+}
+
+impl ValType {
+    /// Returns the type holding values of IL type `ty`.
+    fn of(ty: Type) -> Self {
+        match ty {
+            Type::I64 | Type::U64 | Type::Isize | Type::Usize | Type::F64 => Self::I64,
+            _ => Self::I32,
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            Self::I32 => 0x7f,
+            Self::I64 => 0x7e,
+        }
+    }
+}
+
+impl fmt::Display for ValType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::I32 => write!(f, "i32"),
+            Self::I64 => write!(f, "i64"),
+        }
+    }
+}
+
+fn compile_module(module: &il::Module) -> Vec<WasmFunction> {
+    let mut functions: Vec<_> = module.functions.iter().collect();
+    functions.sort_by_key(|(name, _)| name.to_string());
+    let indices: HashMap<String, u32> = functions
+        .iter()
+        .enumerate()
+        .map(|(i, (name, _))| (name.to_string(), i as u32))
+        .collect();
+    functions
+        .into_iter()
+        .map(|(name, function)| compile_function(module, name, function, &indices))
+        .collect()
+}
+
+fn compile_function(
+    module: &il::Module,
+    name: &Ident,
+    function: &il::Function,
+    indices: &HashMap<String, u32>,
+) -> WasmFunction {
+    let cfg = Cfg::new(function.assembly.clone());
+    let params: Vec<ValType> = function.params.iter().copied().map(ValType::of).collect();
+    // The frame pointer comes before the temporaries, which are numbered
+    // after their index whether or not they are still defined.
+    let mut locals = vec![ValType::I32];
+    for instr in cfg.blocks.iter().flat_map(|block| &block.instructions) {
+        if let Some(output) = instr.output() {
+            let local = 1 + output.index();
+            if local >= locals.len() {
+                locals.resize(local + 1, ValType::I32);
+            }
+            locals[local] = ValType::of(instr.output_type().unwrap());
+        }
+    }
+    let frame = Frame::new(&cfg);
+
+    let all_locals = params.iter().chain(&locals).copied().collect();
+    let mut compiler = FunctionCompiler::new(&cfg, module, function, indices, all_locals, frame);
+    if compiler.frame.size > 0 {
+        compiler.code.extend([
+            Instr::GlobalGet(STACK_POINTER),
+            Instr::I32Const(compiler.frame.size as i32),
+            Instr::I32Sub,
+            Instr::LocalTee(compiler.frame_pointer()),
+            Instr::GlobalSet(STACK_POINTER),
+        ]);
+    }
+    compiler.do_tree(BlockId::ENTRY);
+    // Every path through the body ends in a branch or a return, but the
+    // validator does not know that once the outermost construct ends.
+    compiler.code.push(Instr::Unreachable);
+
+    WasmFunction {
+        name: name.to_string(),
+        params,
+        result: function.return_type.map(ValType::of),
+        locals,
+        body: compiler.code,
+    }
+}
+
+/// The allocations of a function, all made on entry.
+struct Frame {
+    /// Size of the frame in bytes, keeping the stack 16-byte aligned.
+    size: u32,
+    /// Offset of each allocation from the start of the frame.
+    offsets: HashMap<Temporary, u32>,
+}
+
+impl Frame {
+    fn new(cfg: &Cfg) -> Self {
+        let mut size = 0;
+        let mut offsets = HashMap::new();
+        for instr in cfg.blocks.iter().flat_map(|block| &block.instructions) {
+            if let Instruction::Alloc {
+                addr_output,
+                size: alloc_size,
+                alignment,
+            } = instr
+            {
+                let offset = (size as u64).next_multiple_of((*alignment).max(1));
+                offsets.insert(addr_output.dest, offset as u32);
+                size = (offset + alloc_size) as u32;
+            }
+        }
+        Self {
+            size: size.next_multiple_of(16),
+            offsets,
+        }
+    }
+}
+
+/// An enclosing construct that branches can target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Construct {
+    /// A `loop`, branching to which continues with the given block.
+    Loop(BlockId),
+    /// A `block`, branching out of which continues with the given block.
+    Block(BlockId),
+    If,
+}
+
+struct FunctionCompiler<'a> {
+    cfg: &'a Cfg,
+    dominators: Dominators,
+    /// Position of each block in reverse postorder, or `usize::MAX` for
+    /// unreachable blocks.
+    rank: Vec<usize>,
+    /// The blocks immediately dominated by each block.
+    children: Vec<Vec<BlockId>>,
+    block_of: HashMap<Label, BlockId>,
+    module: &'a il::Module,
+    function: &'a il::Function,
+    indices: &'a HashMap<String, u32>,
+    /// The types of the parameters and locals, in order.
+    locals: Vec<ValType>,
+    frame: Frame,
+    /// The constructs enclosing the code being emitted, innermost last.
+    constructs: Vec<Construct>,
+    code: Vec<Instr>,
+}
+
+impl<'a> FunctionCompiler<'a> {
+    fn new(
+        cfg: &'a Cfg,
+        module: &'a il::Module,
+        function: &'a il::Function,
+        indices: &'a HashMap<String, u32>,
+        locals: Vec<ValType>,
+        frame: Frame,
+    ) -> Self {
+        let dominators = cfg.dominators();
+        let mut rank = vec![usize::MAX; cfg.blocks.len()];
+        for (i, id) in cfg.reverse_postorder().into_iter().enumerate() {
+            rank[id.index()] = i;
+        }
+        let mut children = vec![Vec::new(); cfg.blocks.len()];
+        for id in cfg.block_ids() {
+            if let Some(idom) = dominators.immediate_dominator(id) {
+                children[idom.index()].push(id);
+            }
+        }
+        let block_of = cfg
+            .block_ids()
+            .flat_map(|id| cfg.block(id).labels.iter().map(move |&label| (label, id)))
+            .collect();
+        Self {
+            cfg,
+            dominators,
+            rank,
+            children,
+            block_of,
+            module,
+            function,
+            indices,
+            locals,
+            frame,
+            constructs: Vec::new(),
+            code: Vec::new(),
+        }
+    }
+
+    fn frame_pointer(&self) -> u32 {
+        self.function.params.len() as u32
+    }
+
+    fn local(&self, temporary: Temporary) -> u32 {
+        self.frame_pointer() + 1 + temporary.index() as u32
+    }
+
+    fn is_backward(&self, from: BlockId, to: BlockId) -> bool {
+        self.rank[to.index()] <= self.rank[from.index()]
+    }
+
+    /// Whether `id` is the target of a back edge.
+    fn is_loop_header(&self, id: BlockId) -> bool {
+        self.cfg
+            .predecessors(id)
+            .iter()
+            .any(|&pred| self.rank[pred.index()] != usize::MAX && self.is_backward(pred, id))
+    }
+
+    /// Whether `id` can be reached along more than one forward edge, so
+    /// that it cannot be nested inside the code of its predecessor.
+    fn is_merge_node(&self, id: BlockId) -> bool {
+        let forward = self
+            .cfg
+            .predecessors(id)
+            .iter()
+            .filter(|&&pred| self.rank[pred.index()] < self.rank[id.index()])
+            .count();
+        forward >= 2
+    }
+
+    /// Emits block `id` and the blocks it dominates.
+    fn do_tree(&mut self, id: BlockId) {
+        let mut merges: Vec<BlockId> = self.children[id.index()]
+            .iter()
+            .copied()
+            .filter(|&child| self.is_merge_node(child))
+            .collect();
+        // The merge node that comes last is placed outermost.
+        merges.sort_by_key(|child| std::cmp::Reverse(self.rank[child.index()]));
+        if self.is_loop_header(id) {
+            self.code.push(Instr::Loop);
+            self.constructs.push(Construct::Loop(id));
+            self.node_within(id, &merges);
+            self.constructs.pop();
+            self.code.push(Instr::End);
+        } else {
+            self.node_within(id, &merges);
+        }
+    }
+
+    /// Emits block `id` followed by the merge nodes it dominates, each one
+    /// after a `block` that branches to it can leave.
+    fn node_within(&mut self, id: BlockId, merges: &[BlockId]) {
+        if let Some((&merge, rest)) = merges.split_first() {
+            self.code.push(Instr::Block);
+            self.constructs.push(Construct::Block(merge));
+            self.node_within(id, rest);
+            self.constructs.pop();
+            self.code.push(Instr::End);
+            self.do_tree(merge);
+            return;
+        }
+
+        let block = self.cfg.block(id);
+        for instr in &block.instructions {
+            if !matches!(instr, Instruction::Phi(..) | Instruction::Continuation(_)) {
+                self.instruction(instr);
+            }
+        }
+        let successors = self.cfg.successors(id);
+        match block.terminator() {
+            Some(
+                Continuation::BranchZero(value, target)
+                | Continuation::BranchNonZero(value, target),
+            ) if successors.len() == 2 => {
+                let target = self.block_of[target];
+                let next = successors[0];
+                let (taken, not_taken) = match block.terminator() {
+                    Some(Continuation::BranchZero(..)) => (next, target),
+                    _ => (target, next),
+                };
+                self.condition(value);
+                self.code.push(Instr::If);
+                self.constructs.push(Construct::If);
+                self.do_branch(id, taken);
+                self.code.push(Instr::Else);
+                self.do_branch(id, not_taken);
+                self.constructs.pop();
+                self.code.push(Instr::End);
+            }
+            Some(Continuation::Return(value)) => {
+                if let Some(ty) = self.function.return_type {
+                    self.value(value, ty);
+                }
+                if self.frame.size > 0 {
+                    self.code.extend([
+                        Instr::LocalGet(self.frame_pointer()),
+                        Instr::I32Const(self.frame.size as i32),
+                        Instr::I32Add,
+                        Instr::GlobalSet(STACK_POINTER),
+                    ]);
+                }
+                self.code.push(Instr::Return);
+            }
+            Some(Continuation::Halt) => self.code.push(Instr::Unreachable),
+            // Jumps, falling through, and branches whose targets coincide.
+            _ => match successors.first() {
+                Some(&successor) => self.do_branch(id, successor),
+                // Only reachable if control falls off the end.
+                None => self.code.push(Instr::Unreachable),
+            },
+        }
+    }
+
+    /// Emits the transfer of control along the edge from `from` to `to`.
+    fn do_branch(&mut self, from: BlockId, to: BlockId) {
+        self.phi_copies(from, to);
+        let backward = self.is_backward(from, to);
+        if backward && !self.dominators.dominates(to, from) {
+            panic!("irreducible control flow cannot be structured");
+        }
+        let construct = if backward {
+            Construct::Loop(to)
+        } else if self.is_merge_node(to) {
+            Construct::Block(to)
+        } else {
+            self.do_tree(to);
+            return;
+        };
+        let depth = self
+            .constructs
+            .iter()
+            .rev()
+            .position(|&enclosing| enclosing == construct)
+            .expect("branch target is not an enclosing construct");
+        self.code.push(Instr::Br(depth as u32));
+    }
+
+    /// Assigns the phis of `to` their values for the edge from `from`.
+    fn phi_copies(&mut self, from: BlockId, to: BlockId) {
+        let mut outputs = Vec::new();
+        for instr in &self.cfg.block(to).instructions {
+            let Instruction::Phi(output, sources) = instr else {
+                break;
+            };
+            let (_, value) = sources
+                .iter()
+                .find(|(label, _)| self.block_of[label] == from)
+                .expect("phi has no value for an edge into its block");
+            self.value(value, output.dest_type);
+            outputs.push(output.dest);
+        }
+        for output in outputs.into_iter().rev() {
+            self.code.push(Instr::LocalSet(self.local(output)));
+        }
+    }
+
+    /// Pushes `value`, used as a value of type `ty`.
+    fn value(&mut self, value: &Value, ty: Type) {
+        let instr = match value {
+            Value::Temporary(temporary) => Instr::LocalGet(self.local(*temporary)),
+            Value::Argument(argument) => Instr::LocalGet(argument.0 as u32),
+            Value::Literal(literal) => {
+                let value = match literal {
+                    il::Literal::Int(int) => {
+                        ty.extend(int.value().expect("literal does not fit in 64 bits"))
+                    }
+                    il::Literal::Nil => 0,
+                };
+                match ValType::of(ty) {
+                    ValType::I32 => Instr::I32Const(value as i32),
+                    ValType::I64 => Instr::I64Const(value as i64),
+                }
+            }
+        };
+        self.code.push(instr);
+    }
+
+    /// Pushes whether `value` is non-zero, as an `i32`.
+    fn condition(&mut self, value: &Value) {
+        let local = match value {
+            Value::Temporary(temporary) => self.local(*temporary),
+            Value::Argument(argument) => argument.0 as u32,
+            Value::Literal(literal) => {
+                let non_zero = match literal {
+                    il::Literal::Int(int) => int.value() != Some(0),
+                    il::Literal::Nil => false,
+                };
+                self.code.push(Instr::I32Const(non_zero.into()));
+                return;
+            }
+        };
+        self.code.push(Instr::LocalGet(local));
+        if self.locals[local as usize] == ValType::I64 {
+            self.code.extend([Instr::I64Const(0), Instr::I64Ne]);
+        }
+    }
+
+    /// Converts the `i32` or `i64` on top of the stack to the value type
+    /// of `ty`, extending it according to `from` or wrapping it.
+    fn convert(&mut self, from: Type, ty: Type) {
+        match (ValType::of(from), ValType::of(ty)) {
+            (ValType::I32, ValType::I64) if from.is_signed() => {
+                self.code.push(Instr::I64ExtendI32S)
+            }
+            (ValType::I32, ValType::I64) => self.code.push(Instr::I64ExtendI32U),
+            (ValType::I64, ValType::I32) => self.code.push(Instr::I32WrapI64),
+            _ => {}
+        }
+    }
+
+    /// Extends the low bits of the `i32` on top of the stack, holding a
+    /// value of type `ty` narrower than 32 bits, to the whole `i32`.
+    fn extend(&mut self, ty: Type) {
+        match (ty.size(), ty.is_signed()) {
+            (1, true) => self.code.push(Instr::I32Extend8S),
+            (2, true) => self.code.push(Instr::I32Extend16S),
+            (1, false) => self.code.extend([Instr::I32Const(0xff), Instr::I32And]),
+            (2, false) => self.code.extend([Instr::I32Const(0xffff), Instr::I32And]),
+            _ => {}
+        }
+    }
+
+    fn instruction(&mut self, instr: &Instruction) {
+        match instr {
+            Instruction::Operation(output, Operation::Binary(op, ty, left, right)) => {
+                self.value(left, *ty);
+                self.value(right, *ty);
+                let shift = matches!(op, BinaryOp::Shl | BinaryOp::Shr);
+                if shift && ty.size() < 4 {
+                    // Shift amounts are taken modulo the width of the type,
+                    // which WebAssembly only does for 32 and 64 bits.
+                    let bits = ty.size() as i32 * 8;
+                    self.code.extend([Instr::I32Const(bits - 1), Instr::I32And]);
+                }
+                self.code.push(binary(op, *ty));
+                if op.is_comparison() {
+                    self.convert(Type::U32, output.dest_type);
+                } else {
+                    self.extend(*ty);
+                }
+                self.code.push(Instr::LocalSet(self.local(output.dest)));
+            }
+            Instruction::Operation(output, Operation::Unary(op, ty, value)) => {
+                let dest_type = output.dest_type;
+                match op {
+                    UnaryOp::Neg => {
+                        self.value(&Value::Literal(il::Literal::Int(0.into())), *ty);
+                        self.value(value, *ty);
+                        self.code.push(binary(&BinaryOp::Sub, *ty));
+                        self.extend(*ty);
+                    }
+                    UnaryOp::Not => {
+                        self.value(value, *ty);
+                        self.code.push(match ValType::of(*ty) {
+                            ValType::I32 => Instr::I32Eqz,
+                            ValType::I64 => Instr::I64Eqz,
+                        });
+                        self.convert(Type::U32, dest_type);
+                    }
+                    UnaryOp::Convert | UnaryOp::Cast => {
+                        self.value(value, *ty);
+                        self.convert(*ty, dest_type);
+                        self.extend(dest_type);
+                    }
+                }
+                self.code.push(Instr::LocalSet(self.local(output.dest)));
+            }
+            Instruction::Call(output, call) => {
+                let callee = self
+                    .module
+                    .function(&call.function_name)
+                    .expect("call to unknown function");
+                for (argument, &ty) in call.arguments.iter().zip(&callee.params) {
+                    self.value(argument, ty);
+                }
+                self.code
+                    .push(Instr::Call(self.indices[&call.function_name]));
+                match (output, callee.return_type) {
+                    (Some(output), Some(_)) => {
+                        self.code.push(Instr::LocalSet(self.local(output.dest)))
+                    }
+                    // Functions returning no value return `Nil`.
+                    (Some(output), None) => {
+                        self.value(&Value::Literal(il::Literal::Nil), output.dest_type);
+                        self.code.push(Instr::LocalSet(self.local(output.dest)));
+                    }
+                    (None, Some(_)) => self.code.push(Instr::Drop),
+                    (None, None) => {}
+                }
+            }
+            Instruction::Load { output, addr } => {
+                let ty = output.dest_type;
+                self.value(addr, Type::Ptr);
+                self.code.push(match (ty.size(), ty.is_signed()) {
+                    (1, true) => Instr::I32Load8S,
+                    (1, false) => Instr::I32Load8U,
+                    (2, true) => Instr::I32Load16S,
+                    (2, false) => Instr::I32Load16U,
+                    _ => match ValType::of(ty) {
+                        ValType::I32 => Instr::I32Load,
+                        ValType::I64 => Instr::I64Load,
+                    },
+                });
+                self.code.push(Instr::LocalSet(self.local(output.dest)));
+            }
+            Instruction::Store { ty, addr, value } => {
+                self.value(addr, Type::Ptr);
+                self.value(value, *ty);
+                self.code.push(match ty.size() {
+                    1 => Instr::I32Store8,
+                    2 => Instr::I32Store16,
+                    _ => match ValType::of(*ty) {
+                        ValType::I32 => Instr::I32Store,
+                        ValType::I64 => Instr::I64Store,
+                    },
+                });
+            }
+            Instruction::Alloc { addr_output, .. } => {
+                let offset = self.frame.offsets[&addr_output.dest];
+                self.code.extend([
+                    Instr::LocalGet(self.frame_pointer()),
+                    Instr::I32Const(offset as i32),
+                    Instr::I32Add,
+                    Instr::LocalSet(self.local(addr_output.dest)),
+                ]);
+            }
+            Instruction::Phi(..) | Instruction::Continuation(_) => {
+                unreachable!("phis and continuations are handled with control flow")
+            }
+        }
+    }
+}
+
+/// Returns the instruction for a binary operation on operands of type `ty`.
+fn binary(op: &BinaryOp, ty: Type) -> Instr {
+    let signed = ty.is_signed();
+    match ValType::of(ty) {
+        ValType::I32 => match op {
+            BinaryOp::Add => Instr::I32Add,
+            BinaryOp::Sub => Instr::I32Sub,
+            BinaryOp::Mul => Instr::I32Mul,
+            BinaryOp::Div if signed => Instr::I32DivS,
+            BinaryOp::Div => Instr::I32DivU,
+            BinaryOp::Rem if signed => Instr::I32RemS,
+            BinaryOp::Rem => Instr::I32RemU,
+            BinaryOp::Or => Instr::I32Or,
+            BinaryOp::Xor => Instr::I32Xor,
+            BinaryOp::And => Instr::I32And,
+            BinaryOp::Shr if signed => Instr::I32ShrS,
+            BinaryOp::Shr => Instr::I32ShrU,
+            BinaryOp::Shl => Instr::I32Shl,
+            BinaryOp::Eq => Instr::I32Eq,
+            BinaryOp::Ne => Instr::I32Ne,
+            BinaryOp::Lt if signed => Instr::I32LtS,
+            BinaryOp::Lt => Instr::I32LtU,
+            BinaryOp::Le if signed => Instr::I32LeS,
+            BinaryOp::Le => Instr::I32LeU,
+            BinaryOp::Gt if signed => Instr::I32GtS,
+            BinaryOp::Gt => Instr::I32GtU,
+            BinaryOp::Ge if signed => Instr::I32GeS,
+            BinaryOp::Ge => Instr::I32GeU,
+        },
+        ValType::I64 => match op {
+            BinaryOp::Add => Instr::I64Add,
+            BinaryOp::Sub => Instr::I64Sub,
+            BinaryOp::Mul => Instr::I64Mul,
+            BinaryOp::Div if signed => Instr::I64DivS,
+            BinaryOp::Div => Instr::I64DivU,
+            BinaryOp::Rem if signed => Instr::I64RemS,
+            BinaryOp::Rem => Instr::I64RemU,
+            BinaryOp::Or => Instr::I64Or,
+            BinaryOp::Xor => Instr::I64Xor,
+            BinaryOp::And => Instr::I64And,
+            BinaryOp::Shr if signed => Instr::I64ShrS,
+            BinaryOp::Shr => Instr::I64ShrU,
+            BinaryOp::Shl => Instr::I64Shl,
+            BinaryOp::Eq => Instr::I64Eq,
+            BinaryOp::Ne => Instr::I64Ne,
+            BinaryOp::Lt if signed => Instr::I64LtS,
+            BinaryOp::Lt => Instr::I64LtU,
+            BinaryOp::Le if signed => Instr::I64LeS,
+            BinaryOp::Le => Instr::I64LeU,
+            BinaryOp::Gt if signed => Instr::I64GtS,
+            BinaryOp::Gt => Instr::I64GtU,
+            BinaryOp::Ge if signed => Instr::I64GeS,
+            BinaryOp::Ge => Instr::I64GeU,
+        },
+    }
+}
+
+/// A WebAssembly instruction. Structured instructions have no results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Instr {
+    Unreachable,
+    Block,
+    Loop,
+    If,
+    Else,
+    End,
+    Br(u32),
+    Return,
+    Call(u32),
+    Drop,
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    I32Load,
+    I64Load,
+    I32Load8S,
+    I32Load8U,
+    I32Load16S,
+    I32Load16U,
+    I32Store,
+    I64Store,
+    I32Store8,
+    I32Store16,
+    I32Const(i32),
+    I64Const(i64),
+    I32Eqz,
+    I32Eq,
+    I32Ne,
+    I32LtS,
+    I32LtU,
+    I32GtS,
+    I32GtU,
+    I32LeS,
+    I32LeU,
+    I32GeS,
+    I32GeU,
+    I64Eqz,
+    I64Eq,
+    I64Ne,
+    I64LtS,
+    I64LtU,
+    I64GtS,
+    I64GtU,
+    I64LeS,
+    I64LeU,
+    I64GeS,
+    I64GeU,
+    I32Add,
+    I32Sub,
+    I32Mul,
+    I32DivS,
+    I32DivU,
+    I32RemS,
+    I32RemU,
+    I32And,
+    I32Or,
+    I32Xor,
+    I32Shl,
+    I32ShrS,
+    I32ShrU,
+    I64Add,
+    I64Sub,
+    I64Mul,
+    I64DivS,
+    I64DivU,
+    I64RemS,
+    I64RemU,
+    I64And,
+    I64Or,
+    I64Xor,
+    I64Shl,
+    I64ShrS,
+    I64ShrU,
+    I32WrapI64,
+    I64ExtendI32S,
+    I64ExtendI32U,
+    I32Extend8S,
+    I32Extend16S,
+}
+
+impl Instr {
+    /// Returns the opcode and text name of the instruction.
+    fn opcode(self) -> (u8, &'static str) {
+        match self {
+            Self::Unreachable => (0x00, "unreachable"),
+            Self::Block => (0x02, "block"),
+            Self::Loop => (0x03, "loop"),
+            Self::If => (0x04, "if"),
+            Self::Else => (0x05, "else"),
+            Self::End => (0x0b, "end"),
+            Self::Br(_) => (0x0c, "br"),
+            Self::Return => (0x0f, "return"),
+            Self::Call(_) => (0x10, "call"),
+            Self::Drop => (0x1a, "drop"),
+            Self::LocalGet(_) => (0x20, "local.get"),
+            Self::LocalSet(_) => (0x21, "local.set"),
+            Self::LocalTee(_) => (0x22, "local.tee"),
+            Self::GlobalGet(_) => (0x23, "global.get"),
+            Self::GlobalSet(_) => (0x24, "global.set"),
+            Self::I32Load => (0x28, "i32.load"),
+            Self::I64Load => (0x29, "i64.load"),
+            Self::I32Load8S => (0x2c, "i32.load8_s"),
+            Self::I32Load8U => (0x2d, "i32.load8_u"),
+            Self::I32Load16S => (0x2e, "i32.load16_s"),
+            Self::I32Load16U => (0x2f, "i32.load16_u"),
+            Self::I32Store => (0x36, "i32.store"),
+            Self::I64Store => (0x37, "i64.store"),
+            Self::I32Store8 => (0x3a, "i32.store8"),
+            Self::I32Store16 => (0x3b, "i32.store16"),
+            Self::I32Const(_) => (0x41, "i32.const"),
+            Self::I64Const(_) => (0x42, "i64.const"),
+            Self::I32Eqz => (0x45, "i32.eqz"),
+            Self::I32Eq => (0x46, "i32.eq"),
+            Self::I32Ne => (0x47, "i32.ne"),
+            Self::I32LtS => (0x48, "i32.lt_s"),
+            Self::I32LtU => (0x49, "i32.lt_u"),
+            Self::I32GtS => (0x4a, "i32.gt_s"),
+            Self::I32GtU => (0x4b, "i32.gt_u"),
+            Self::I32LeS => (0x4c, "i32.le_s"),
+            Self::I32LeU => (0x4d, "i32.le_u"),
+            Self::I32GeS => (0x4e, "i32.ge_s"),
+            Self::I32GeU => (0x4f, "i32.ge_u"),
+            Self::I64Eqz => (0x50, "i64.eqz"),
+            Self::I64Eq => (0x51, "i64.eq"),
+            Self::I64Ne => (0x52, "i64.ne"),
+            Self::I64LtS => (0x53, "i64.lt_s"),
+            Self::I64LtU => (0x54, "i64.lt_u"),
+            Self::I64GtS => (0x55, "i64.gt_s"),
+            Self::I64GtU => (0x56, "i64.gt_u"),
+            Self::I64LeS => (0x57, "i64.le_s"),
+            Self::I64LeU => (0x58, "i64.le_u"),
+            Self::I64GeS => (0x59, "i64.ge_s"),
+            Self::I64GeU => (0x5a, "i64.ge_u"),
+            Self::I32Add => (0x6a, "i32.add"),
+            Self::I32Sub => (0x6b, "i32.sub"),
+            Self::I32Mul => (0x6c, "i32.mul"),
+            Self::I32DivS => (0x6d, "i32.div_s"),
+            Self::I32DivU => (0x6e, "i32.div_u"),
+            Self::I32RemS => (0x6f, "i32.rem_s"),
+            Self::I32RemU => (0x70, "i32.rem_u"),
+            Self::I32And => (0x71, "i32.and"),
+            Self::I32Or => (0x72, "i32.or"),
+            Self::I32Xor => (0x73, "i32.xor"),
+            Self::I32Shl => (0x74, "i32.shl"),
+            Self::I32ShrS => (0x75, "i32.shr_s"),
+            Self::I32ShrU => (0x76, "i32.shr_u"),
+            Self::I64Add => (0x7c, "i64.add"),
+            Self::I64Sub => (0x7d, "i64.sub"),
+            Self::I64Mul => (0x7e, "i64.mul"),
+            Self::I64DivS => (0x7f, "i64.div_s"),
+            Self::I64DivU => (0x80, "i64.div_u"),
+            Self::I64RemS => (0x81, "i64.rem_s"),
+            Self::I64RemU => (0x82, "i64.rem_u"),
+            Self::I64And => (0x83, "i64.and"),
+            Self::I64Or => (0x84, "i64.or"),
+            Self::I64Xor => (0x85, "i64.xor"),
+            Self::I64Shl => (0x86, "i64.shl"),
+            Self::I64ShrS => (0x87, "i64.shr_s"),
+            Self::I64ShrU => (0x88, "i64.shr_u"),
+            Self::I32WrapI64 => (0xa7, "i32.wrap_i64"),
+            Self::I64ExtendI32S => (0xac, "i64.extend_i32_s"),
+            Self::I64ExtendI32U => (0xad, "i64.extend_i32_u"),
+            Self::I32Extend8S => (0xc0, "i32.extend8_s"),
+            Self::I32Extend16S => (0xc1, "i32.extend16_s"),
+        }
+    }
+
+    fn encode(self, out: &mut Vec<u8>) {
+        out.push(self.opcode().0);
+        match self {
+            // Blocks have no parameters or results.
+            Self::Block | Self::Loop | Self::If => out.push(0x40),
+            Self::Br(index)
+            | Self::Call(index)
+            | Self::LocalGet(index)
+            | Self::LocalSet(index)
+            | Self::LocalTee(index)
+            | Self::GlobalGet(index)
+            | Self::GlobalSet(index) => unsigned(out, index.into()),
+            // Accesses are aligned to their size, with no offset.
+            Self::I32Load8S | Self::I32Load8U | Self::I32Store8 => {
+                unsigned(out, 0);
+                unsigned(out, 0);
+            }
+            Self::I32Load16S | Self::I32Load16U | Self::I32Store16 => {
+                unsigned(out, 1);
+                unsigned(out, 0);
+            }
+            Self::I32Load | Self::I32Store => {
+                unsigned(out, 2);
+                unsigned(out, 0);
+            }
+            Self::I64Load | Self::I64Store => {
+                unsigned(out, 3);
+                unsigned(out, 0);
+            }
+            Self::I32Const(value) => signed(out, value.into()),
+            Self::I64Const(value) => signed(out, value),
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.opcode().1)?;
+        match self {
+            Self::Br(index)
+            | Self::Call(index)
+            | Self::LocalGet(index)
+            | Self::LocalSet(index)
+            | Self::LocalTee(index)
+            | Self::GlobalGet(index)
+            | Self::GlobalSet(index) => write!(f, " {}", index),
+            Self::I32Const(value) => write!(f, " {}", value),
+            Self::I64Const(value) => write!(f, " {}", value),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn encode_module(functions: &[WasmFunction]) -> Vec<u8> {
+    let mut out = b"\0asm".to_vec();
+    out.extend(1u32.to_le_bytes());
+
+    // Functions with the same signature share a type.
+    let mut signatures: Vec<Signature> = functions.iter().map(WasmFunction::signature).collect();
+    signatures.sort();
+    signatures.dedup();
+    section(&mut out, 1, |out| {
+        unsigned(out, signatures.len() as u64);
+        for (params, result) in &signatures {
+            out.push(0x60);
+            unsigned(out, params.len() as u64);
+            out.extend(params.iter().map(|ty| ty.code()));
+            unsigned(out, result.iter().len() as u64);
+            out.extend(result.iter().map(|ty| ty.code()));
+        }
+    });
+
+    section(&mut out, 3, |out| {
+        unsigned(out, functions.len() as u64);
+        for function in functions {
+            let index = signatures.binary_search(&function.signature()).unwrap();
+            unsigned(out, index as u64);
+        }
+    });
+
+    section(&mut out, 5, |out| {
+        unsigned(out, 1);
+        out.push(0x00);
+        unsigned(out, MEMORY_PAGES.into());
+    });
+
+    section(&mut out, 6, |out| {
+        unsigned(out, 1);
+        out.extend([ValType::I32.code(), 0x01]);
+        Instr::I32Const((MEMORY_PAGES * PAGE_SIZE) as i32).encode(out);
+        Instr::End.encode(out);
+    });
+
+    section(&mut out, 7, |out| {
+        unsigned(out, functions.len() as u64 + 1);
+        name(out, "memory");
+        out.extend([0x02, 0x00]);
+        for (i, function) in functions.iter().enumerate() {
+            name(out, &function.name);
+            out.push(0x00);
+            unsigned(out, i as u64);
+        }
+    });
+
+    section(&mut out, 10, |out| {
+        unsigned(out, functions.len() as u64);
+        for function in functions {
+            // Locals are declared in runs of the same type.
+            let mut runs: Vec<(u32, ValType)> = Vec::new();
+            for &ty in &function.locals {
+                match runs.last_mut() {
+                    Some((count, last)) if *last == ty => *count += 1,
+                    _ => runs.push((1, ty)),
+                }
+            }
+            let mut body = Vec::new();
+            unsigned(&mut body, runs.len() as u64);
+            for (count, ty) in runs {
+                unsigned(&mut body, count.into());
+                body.push(ty.code());
+            }
+            for instr in &function.body {
+                instr.encode(&mut body);
+            }
+            Instr::End.encode(&mut body);
+            unsigned(out, body.len() as u64);
+            out.extend(body);
+        }
+    });
+
+    out
+}
+
+/// Writes a section with the contents written by `contents`, prefixed by
+/// their size.
+fn section(out: &mut Vec<u8>, id: u8, contents: impl FnOnce(&mut Vec<u8>)) {
+    let mut bytes = Vec::new();
+    contents(&mut bytes);
+    out.push(id);
+    unsigned(out, bytes.len() as u64);
+    out.extend(bytes);
+}
+
+fn name(out: &mut Vec<u8>, name: &str) {
+    unsigned(out, name.len() as u64);
+    out.extend(name.as_bytes());
+}
+
+/// Writes `value` in unsigned LEB128.
+fn unsigned(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Writes `value` in signed LEB128.
+fn signed(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let sign_clear = byte & 0x40 == 0;
+        if (value == 0 && sign_clear) || (value == -1 && !sign_clear) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
@@ -0,0 +1,806 @@
+//! Emits x86-64 assembly in GNU assembler syntax.
+//!
+//! Functions follow the System V calling convention. Temporaries are
+//! assigned to registers by linear scan over their live intervals, and
+//! spilled to stack slots when registers run out. Phis are replaced by
+//! copies at the end of each predecessor, through a fresh temporary per phi
+//! so that the phis of a block still take their values at the same time.
+//!
+//! Every value is held in a whole 64-bit register, sign- or zero-extended
+//! from its type, so that comparisons and division can work on whole
+//! registers too. Operations on narrower types are done on whole registers
+//! and their results extended again afterwards.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Write},
+};
+
+use crate::{
+    il::{
+        self,
+        cfg::{BlockId, Cfg},
+        BinaryOp, Continuation, Instruction, Operation, Temporary, Type, UnaryOp, Value,
+    },
+    token::Ident,
+};
+
+pub fn emit_module(module: &il::Module) -> String {
+    let mut out = String::new();
+    write_module(&mut out, module).expect("writing to a String cannot fail");
+    out
+}
+
+pub fn write_module(out: &mut impl Write, module: &il::Module) -> fmt::Result {
+    let mut functions: Vec<_> = module.functions.iter().collect();
+    functions.sort_by_key(|(name, _)| name.to_string());
+
+    writeln!(out, "\t.text")?;
+    for (name, function) in functions {
+        writeln!(out)?;
+        write_function(out, module, name, function)?;
+    }
+    // Tell the linker that the code does not need an executable stack.
+    writeln!(out)?;
+    writeln!(out, "\t.section .note.GNU-stack,\"\",@progbits")
+}
+
+/// Writes `function`, a member of `module`, which is needed for the
+/// signatures of the functions it calls.
+pub fn write_function(
+    out: &mut impl Write,
+    module: &il::Module,
+    name: &Ident,
+    function: &il::Function,
+) -> fmt::Result {
+    let mut cfg = Cfg::new(function.assembly.clone());
+    let arguments = replace_arguments(&mut cfg, function.params.len());
+    destruct_phis(&mut cfg);
+    let liveness = Liveness::new(&cfg, &arguments);
+    let allocation = allocate_registers(&liveness);
+    let frame = Frame::new(&cfg, &allocation);
+
+    let mut emitter = Emitter {
+        out,
+        module,
+        name: name.to_string(),
+        return_type: function.return_type,
+        frame,
+    };
+    emitter.function(&cfg, &arguments, &function.params)
+}
+
+const ARGUMENT_REGISTERS: [Register; 6] = [
+    Register::Rdi,
+    Register::Rsi,
+    Register::Rdx,
+    Register::Rcx,
+    Register::R8,
+    Register::R9,
+];
+
+/// Registers available to temporaries, in order of preference. `rax`,
+/// `rcx`, `rdx` and `r11` are kept free as scratch registers, and the
+/// argument registers for moving arguments in and out. Callee-saved
+/// registers come last, as using them costs saving them.
+const ALLOCATABLE: [Register; 6] = [
+    Register::R10,
+    Register::Rbx,
+    Register::R12,
+    Register::R13,
+    Register::R14,
+    Register::R15,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Register {
+    Rbx,
+    Rcx,
+    Rdx,
+    Rsi,
+    Rdi,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
+}
+
+impl Register {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Rbx => "rbx",
+            Self::Rcx => "rcx",
+            Self::Rdx => "rdx",
+            Self::Rsi => "rsi",
+            Self::Rdi => "rdi",
+            Self::R8 => "r8",
+            Self::R9 => "r9",
+            Self::R10 => "r10",
+            Self::R11 => "r11",
+            Self::R12 => "r12",
+            Self::R13 => "r13",
+            Self::R14 => "r14",
+            Self::R15 => "r15",
+        }
+    }
+
+    /// Whether the register keeps its value across calls.
+    fn is_callee_saved(self) -> bool {
+        matches!(
+            self,
+            Self::Rbx | Self::R12 | Self::R13 | Self::R14 | Self::R15
+        )
+    }
+}
+
+/// Replaces the arguments of the function with temporaries, returning the
+/// temporary of each argument. The temporaries are defined on entry.
+fn replace_arguments(cfg: &mut Cfg, count: usize) -> Vec<Temporary> {
+    let arguments: Vec<Temporary> = (0..count).map(|_| cfg.new_temporary()).collect();
+    for block in &mut cfg.blocks {
+        for instr in &mut block.instructions {
+            for operand in instr.operands_mut() {
+                if let Value::Argument(argument) = operand {
+                    *operand = Value::Temporary(arguments[argument.0]);
+                }
+            }
+        }
+    }
+    arguments
+}
+
+fn copy(dest: Temporary, ty: Type, value: Value) -> Instruction {
+    Instruction::Operation(
+        il::Output {
+            dest,
+            dest_type: ty,
+        },
+        Operation::Unary(UnaryOp::Convert, ty, value),
+    )
+}
+
+/// Replaces each phi with a copy from a fresh temporary, which every
+/// predecessor assigns its value to just before leaving.
+fn destruct_phis(cfg: &mut Cfg) {
+    let block_of: HashMap<il::Label, BlockId> = cfg
+        .block_ids()
+        .flat_map(|id| cfg.block(id).labels.iter().map(move |&label| (label, id)))
+        .collect();
+    for id in cfg.block_ids().collect::<Vec<_>>() {
+        let mut copies = Vec::new();
+        for instr in &mut cfg.block_mut(id).instructions {
+            if let Instruction::Phi(output, sources) = instr {
+                copies.push((output.clone(), std::mem::take(sources)));
+            }
+        }
+        if copies.is_empty() {
+            continue;
+        }
+        cfg.block_mut(id)
+            .instructions
+            .retain(|instr| !matches!(instr, Instruction::Phi(..)));
+
+        let mut entry_copies = Vec::new();
+        for (output, sources) in copies {
+            let ty = output.dest_type;
+            let incoming = cfg.new_temporary();
+            for (label, value) in sources {
+                let pred = cfg.block_mut(block_of[&label]);
+                let end = pred.instructions.len() - usize::from(pred.terminator().is_some());
+                pred.instructions.insert(end, copy(incoming, ty, value));
+            }
+            entry_copies.push(copy(output.dest, ty, Value::Temporary(incoming)));
+        }
+        cfg.block_mut(id).instructions.splice(0..0, entry_copies);
+    }
+}
+
+/// The live intervals of all temporaries, over positions numbering the
+/// start of each block and each instruction in order.
+struct Liveness {
+    intervals: Vec<Interval>,
+    calls: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Interval {
+    temporary: Temporary,
+    start: usize,
+    end: usize,
+}
+
+impl Liveness {
+    fn new(cfg: &Cfg, arguments: &[Temporary]) -> Self {
+        let ids: Vec<BlockId> = cfg.block_ids().collect();
+        let mut uses = vec![HashSet::new(); ids.len()];
+        let mut defs = vec![HashSet::new(); ids.len()];
+        for &id in &ids {
+            for instr in &cfg.block(id).instructions {
+                for operand in instr.operands() {
+                    if let Value::Temporary(temporary) = operand {
+                        if !defs[id.index()].contains(temporary) {
+                            uses[id.index()].insert(*temporary);
+                        }
+                    }
+                }
+                if let Some(output) = instr.output() {
+                    defs[id.index()].insert(output);
+                }
+            }
+        }
+
+        let mut live_in: Vec<HashSet<Temporary>> = vec![HashSet::new(); ids.len()];
+        let mut live_out: Vec<HashSet<Temporary>> = vec![HashSet::new(); ids.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &id in ids.iter().rev() {
+                let i = id.index();
+                let out: HashSet<Temporary> = cfg
+                    .successors(id)
+                    .iter()
+                    .flat_map(|succ| live_in[succ.index()].iter().copied())
+                    .collect();
+                let mut inn = uses[i].clone();
+                inn.extend(out.difference(&defs[i]).copied());
+                if inn != live_in[i] || out != live_out[i] {
+                    live_in[i] = inn;
+                    live_out[i] = out;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut ranges: HashMap<Temporary, (usize, usize)> = HashMap::new();
+        let mut extend = |temporary: Temporary, position: usize| {
+            let range = ranges.entry(temporary).or_insert((position, position));
+            range.0 = range.0.min(position);
+            range.1 = range.1.max(position);
+        };
+        for &argument in arguments {
+            extend(argument, 0);
+        }
+        let mut calls = Vec::new();
+        let mut position = 0;
+        for &id in &ids {
+            let start = position;
+            for &temporary in &live_in[id.index()] {
+                extend(temporary, start);
+            }
+            for instr in &cfg.block(id).instructions {
+                position += 1;
+                for operand in instr.operands() {
+                    if let Value::Temporary(temporary) = operand {
+                        extend(*temporary, position);
+                    }
+                }
+                if let Some(output) = instr.output() {
+                    extend(output, position);
+                }
+                if let Instruction::Call(..) = instr {
+                    calls.push(position);
+                }
+            }
+            for &temporary in &live_out[id.index()] {
+                extend(temporary, position);
+            }
+            position += 1;
+        }
+
+        let mut intervals: Vec<Interval> = ranges
+            .into_iter()
+            .map(|(temporary, (start, end))| Interval {
+                temporary,
+                start,
+                end,
+            })
+            .collect();
+        intervals.sort_by_key(|interval| (interval.start, interval.temporary.index()));
+        Self { intervals, calls }
+    }
+
+    /// Whether a call happens while `interval` is live, other than the one
+    /// defining it or the last one using it.
+    fn crosses_call(&self, interval: &Interval) -> bool {
+        self.calls
+            .iter()
+            .any(|&call| interval.start < call && call < interval.end)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Assignment {
+    Register(Register),
+    /// The index of a stack slot.
+    Spill(usize),
+}
+
+struct Allocation {
+    assignments: HashMap<Temporary, Assignment>,
+    spills: usize,
+}
+
+/// Assigns registers to temporaries with the linear scan algorithm from
+/// "Linear Scan Register Allocation" by Poletto and Sarkar. Temporaries live
+/// across a call only get callee-saved registers.
+fn allocate_registers(liveness: &Liveness) -> Allocation {
+    let mut assignments = HashMap::new();
+    let mut spills = 0;
+    // The intervals currently holding a register.
+    let mut active: Vec<(Interval, Register)> = Vec::new();
+    for &interval in &liveness.intervals {
+        // Instructions read their operands before writing their result, so
+        // an interval ending where this one starts can share its register.
+        active.retain(|(other, _)| other.end > interval.start);
+        let crosses_call = liveness.crosses_call(&interval);
+        let eligible = |register: Register| !crosses_call || register.is_callee_saved();
+
+        let free = ALLOCATABLE.into_iter().find(|&register| {
+            eligible(register) && active.iter().all(|&(_, other)| other != register)
+        });
+        if let Some(register) = free {
+            assignments.insert(interval.temporary, Assignment::Register(register));
+            active.push((interval, register));
+            continue;
+        }
+
+        // Spill whichever interval ends last, to free a register for as
+        // long as possible.
+        let victim = active
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, register))| eligible(*register))
+            .max_by_key(|(_, (other, _))| other.end)
+            .map(|(i, _)| i);
+        match victim {
+            Some(i) if active[i].0.end > interval.end => {
+                let (victim, register) = active.swap_remove(i);
+                assignments.insert(victim.temporary, Assignment::Spill(spills));
+                assignments.insert(interval.temporary, Assignment::Register(register));
+                active.push((interval, register));
+            }
+            _ => {
+                assignments.insert(interval.temporary, Assignment::Spill(spills));
+            }
+        }
+        spills += 1;
+    }
+    Allocation {
+        assignments,
+        spills,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Location {
+    Register(Register),
+    /// An offset from the frame pointer.
+    Stack(i64),
+}
+
+/// The stack frame of a function. Below the saved frame pointer come the
+/// saved callee-saved registers, the allocations, and the spill slots.
+///
+/// Each allocation gets a slot of its own for the whole call, which relies
+/// on lowering placing them at the start of the function.
+struct Frame {
+    locations: HashMap<Temporary, Location>,
+    /// Offsets of allocations from the frame pointer.
+    allocs: HashMap<Temporary, i64>,
+    saved: Vec<Register>,
+    /// How far to move the stack pointer past the saved registers.
+    size: u64,
+}
+
+impl Frame {
+    fn new(cfg: &Cfg, allocation: &Allocation) -> Self {
+        let saved: Vec<Register> = ALLOCATABLE
+            .into_iter()
+            .filter(|&register| {
+                register.is_callee_saved()
+                    && allocation
+                        .assignments
+                        .values()
+                        .any(|&assignment| assignment == Assignment::Register(register))
+            })
+            .collect();
+
+        let mut used = 8 * saved.len() as u64;
+        let mut allocs = HashMap::new();
+        for instr in cfg.blocks.iter().flat_map(|block| &block.instructions) {
+            if let Instruction::Alloc {
+                addr_output,
+                size,
+                alignment,
+            } = instr
+            {
+                used = (used + size).next_multiple_of((*alignment).max(1));
+                allocs.insert(addr_output.dest, -(used as i64));
+            }
+        }
+        let mut spill_offsets = Vec::with_capacity(allocation.spills);
+        for _ in 0..allocation.spills {
+            used += 8;
+            spill_offsets.push(-(used as i64));
+        }
+
+        let locations = allocation
+            .assignments
+            .iter()
+            .map(|(&temporary, &assignment)| {
+                let location = match assignment {
+                    Assignment::Register(register) => Location::Register(register),
+                    Assignment::Spill(slot) => Location::Stack(spill_offsets[slot]),
+                };
+                (temporary, location)
+            })
+            .collect();
+        Self {
+            locations,
+            allocs,
+            // The stack pointer is 16-byte aligned after pushing the frame
+            // pointer, and has to be again at calls.
+            size: used.next_multiple_of(16) - 8 * saved.len() as u64,
+            saved,
+        }
+    }
+}
+
+struct Emitter<'a, W> {
+    out: &'a mut W,
+    module: &'a il::Module,
+    name: String,
+    return_type: Option<Type>,
+    frame: Frame,
+}
+
+impl<W: Write> Emitter<'_, W> {
+    fn function(&mut self, cfg: &Cfg, arguments: &[Temporary], params: &[Type]) -> fmt::Result {
+        writeln!(self.out, "\t.globl {}", self.name)?;
+        writeln!(self.out, "\t.type {}, @function", self.name)?;
+        writeln!(self.out, "{}:", self.name)?;
+        writeln!(self.out, "\tpushq %rbp")?;
+        writeln!(self.out, "\tmovq %rsp, %rbp")?;
+        for register in &self.frame.saved {
+            writeln!(self.out, "\tpushq %{}", register.name())?;
+        }
+        if self.frame.size > 0 {
+            writeln!(self.out, "\tsubq ${}, %rsp", self.frame.size)?;
+        }
+
+        // Callers only have to pass the bits of the type, so the arguments
+        // are extended on entry.
+        for (i, (&argument, &ty)) in arguments.iter().zip(params).enumerate() {
+            let source = match ARGUMENT_REGISTERS.get(i) {
+                Some(register) => format!("%{}", register.name()),
+                None => format!("{}(%rbp)", 16 + 8 * (i - ARGUMENT_REGISTERS.len())),
+            };
+            writeln!(self.out, "\tmovq {}, %rax", source)?;
+            self.extend_rax(ty)?;
+            self.store_rax(argument)?;
+        }
+
+        let ids: Vec<BlockId> = cfg.block_ids().collect();
+        for (i, &id) in ids.iter().enumerate() {
+            let block = cfg.block(id);
+            for label in &block.labels {
+                writeln!(self.out, "{}:", self.label(*label))?;
+            }
+            for instr in &block.instructions {
+                self.instruction(instr)?;
+            }
+            if i + 1 == ids.len() && block.terminator().is_none() {
+                writeln!(self.out, "\tud2")?;
+            }
+        }
+        writeln!(self.out, "\t.size {}, .-{}", self.name, self.name)
+    }
+
+    fn label(&self, label: il::Label) -> String {
+        format!(".L{}_{}", self.name, label.index())
+    }
+
+    /// Returns the assembly operand for `value`, used as a value of type
+    /// `ty`, which may be a register, a memory location or an immediate.
+    /// Literals that do not fit in an immediate are moved to `r11` first.
+    fn operand(&mut self, value: &Value, ty: Type) -> Result<String, fmt::Error> {
+        match value {
+            Value::Temporary(temporary) => Ok(match self.frame.locations[temporary] {
+                Location::Register(register) => format!("%{}", register.name()),
+                Location::Stack(offset) => format!("{}(%rbp)", offset),
+            }),
+            Value::Literal(literal) => {
+                let value = literal_value(literal, ty);
+                if i32::try_from(value).is_ok() {
+                    Ok(format!("${}", value))
+                } else {
+                    writeln!(self.out, "\tmovabsq ${}, %r11", value)?;
+                    Ok("%r11".to_string())
+                }
+            }
+            Value::Argument(_) => unreachable!("arguments are replaced by temporaries"),
+        }
+    }
+
+    fn load_rax(&mut self, value: &Value, ty: Type) -> fmt::Result {
+        if let Value::Literal(literal) = value {
+            let value = literal_value(literal, ty);
+            if i32::try_from(value).is_err() {
+                return writeln!(self.out, "\tmovabsq ${}, %rax", value);
+            }
+        }
+        let operand = self.operand(value, ty)?;
+        writeln!(self.out, "\tmovq {}, %rax", operand)
+    }
+
+    /// Sign- or zero-extends the low bits of `rax` holding a value of type
+    /// `ty` to the whole register.
+    fn extend_rax(&mut self, ty: Type) -> fmt::Result {
+        let extension = match (ty.size(), ty.is_signed()) {
+            (1, true) => "movsbq %al, %rax",
+            (1, false) => "movzbl %al, %eax",
+            (2, true) => "movswq %ax, %rax",
+            (2, false) => "movzwl %ax, %eax",
+            (4, true) => "cltq",
+            (4, false) => "movl %eax, %eax",
+            _ => return Ok(()),
+        };
+        writeln!(self.out, "\t{}", extension)
+    }
+
+    /// Stores `rax` in `dest`, which should hold a value extended to 64
+    /// bits.
+    fn store_rax(&mut self, dest: Temporary) -> fmt::Result {
+        match self.frame.locations[&dest] {
+            Location::Register(register) => writeln!(self.out, "\tmovq %rax, %{}", register.name()),
+            Location::Stack(offset) => writeln!(self.out, "\tmovq %rax, {}(%rbp)", offset),
+        }
+    }
+
+    /// Returns a memory operand addressing `addr`, loading it into a
+    /// scratch register if it is not in a register already.
+    fn address(&mut self, addr: &Value) -> Result<String, fmt::Error> {
+        if let Value::Temporary(temporary) = addr {
+            if let Location::Register(register) = self.frame.locations[temporary] {
+                return Ok(format!("(%{})", register.name()));
+            }
+        }
+        let scratch = Register::R11.name();
+        let operand = self.operand(addr, Type::Ptr)?;
+        if operand != format!("%{}", scratch) {
+            writeln!(self.out, "\tmovq {}, %{}", operand, scratch)?;
+        }
+        Ok(format!("(%{})", scratch))
+    }
+
+    fn instruction(&mut self, instr: &Instruction) -> fmt::Result {
+        match instr {
+            Instruction::Operation(output, Operation::Binary(op, ty, left, right)) => {
+                self.binary(output.dest, op, *ty, left, right)
+            }
+            Instruction::Operation(output, Operation::Unary(op, ty, value)) => {
+                self.load_rax(value, *ty)?;
+                match op {
+                    UnaryOp::Neg => writeln!(self.out, "\tnegq %rax")?,
+                    UnaryOp::Not => {
+                        writeln!(self.out, "\ttestq %rax, %rax")?;
+                        writeln!(self.out, "\tsete %al")?;
+                        writeln!(self.out, "\tmovzbl %al, %eax")?;
+                    }
+                    // Values are already extended according to their type,
+                    // so converting only needs to truncate to the output.
+                    UnaryOp::Convert | UnaryOp::Cast => {}
+                }
+                self.extend_rax(output.dest_type)?;
+                self.store_rax(output.dest)
+            }
+            Instruction::Call(output, call) => {
+                let params = self
+                    .module
+                    .function(&call.function_name)
+                    .expect("call to unknown function")
+                    .params
+                    .clone();
+                let stack_arguments = call.arguments.len().saturating_sub(6);
+                // Keep the stack pointer 16-byte aligned at the call.
+                let padding = if stack_arguments % 2 == 1 { 8 } else { 0 };
+                if padding > 0 {
+                    writeln!(self.out, "\tsubq $8, %rsp")?;
+                }
+                for (argument, &ty) in call.arguments.iter().zip(&params).skip(6).rev() {
+                    let operand = self.operand(argument, ty)?;
+                    writeln!(self.out, "\tpushq {}", operand)?;
+                }
+                for ((argument, &ty), register) in
+                    call.arguments.iter().zip(&params).zip(ARGUMENT_REGISTERS)
+                {
+                    let operand = self.operand(argument, ty)?;
+                    writeln!(self.out, "\tmovq {}, %{}", operand, register.name())?;
+                }
+                writeln!(self.out, "\tcall {}", call.function_name)?;
+                let pushed = 8 * stack_arguments + padding;
+                if pushed > 0 {
+                    writeln!(self.out, "\taddq ${}, %rsp", pushed)?;
+                }
+                match output {
+                    Some(output) => {
+                        self.extend_rax(output.dest_type)?;
+                        self.store_rax(output.dest)
+                    }
+                    None => Ok(()),
+                }
+            }
+            Instruction::Load { output, addr } => {
+                let ty = output.dest_type;
+                let addr = self.address(addr)?;
+                let load = match (ty.size(), ty.is_signed()) {
+                    (1, true) => "movsbq {}, %rax",
+                    (1, false) => "movzbl {}, %eax",
+                    (2, true) => "movswq {}, %rax",
+                    (2, false) => "movzwl {}, %eax",
+                    (4, true) => "movslq {}, %rax",
+                    (4, false) => "movl {}, %eax",
+                    _ => "movq {}, %rax",
+                };
+                writeln!(self.out, "\t{}", load.replace("{}", &addr))?;
+                self.store_rax(output.dest)
+            }
+            Instruction::Store { ty, addr, value } => {
+                self.load_rax(value, *ty)?;
+                let addr = self.address(addr)?;
+                let store = match ty.size() {
+                    1 => "movb %al",
+                    2 => "movw %ax",
+                    4 => "movl %eax",
+                    _ => "movq %rax",
+                };
+                writeln!(self.out, "\t{}, {}", store, addr)
+            }
+            Instruction::Alloc { addr_output, .. } => {
+                let offset = self.frame.allocs[&addr_output.dest];
+                writeln!(self.out, "\tleaq {}(%rbp), %rax", offset)?;
+                self.store_rax(addr_output.dest)
+            }
+            Instruction::Phi(..) => unreachable!("phis are replaced by copies"),
+            Instruction::Continuation(continuation) => self.continuation(continuation),
+        }
+    }
+
+    fn binary(
+        &mut self,
+        dest: Temporary,
+        op: &BinaryOp,
+        ty: Type,
+        left: &Value,
+        right: &Value,
+    ) -> fmt::Result {
+        self.load_rax(left, ty)?;
+        let right = self.operand(right, ty)?;
+        let signed = ty.is_signed();
+        let arithmetic = match op {
+            BinaryOp::Add => Some("addq"),
+            BinaryOp::Sub => Some("subq"),
+            BinaryOp::Mul => Some("imulq"),
+            BinaryOp::Or => Some("orq"),
+            BinaryOp::Xor => Some("xorq"),
+            BinaryOp::And => Some("andq"),
+            _ => None,
+        };
+        let condition = match op {
+            BinaryOp::Eq => Some("e"),
+            BinaryOp::Ne => Some("ne"),
+            BinaryOp::Lt if signed => Some("l"),
+            BinaryOp::Lt => Some("b"),
+            BinaryOp::Le if signed => Some("le"),
+            BinaryOp::Le => Some("be"),
+            BinaryOp::Gt if signed => Some("g"),
+            BinaryOp::Gt => Some("a"),
+            BinaryOp::Ge if signed => Some("ge"),
+            BinaryOp::Ge => Some("ae"),
+            _ => None,
+        };
+        if let Some(instruction) = arithmetic {
+            writeln!(self.out, "\t{} {}, %rax", instruction, right)?;
+            self.extend_rax(ty)?;
+        } else if let Some(condition) = condition {
+            writeln!(self.out, "\tcmpq {}, %rax", right)?;
+            writeln!(self.out, "\tset{} %al", condition)?;
+            writeln!(self.out, "\tmovzbl %al, %eax")?;
+        } else {
+            // Division and shifts take one of their operands in a fixed
+            // register.
+            writeln!(self.out, "\tmovq {}, %rcx", right)?;
+            match op {
+                BinaryOp::Div | BinaryOp::Rem => {
+                    if signed {
+                        writeln!(self.out, "\tcqto")?;
+                        writeln!(self.out, "\tidivq %rcx")?;
+                    } else {
+                        writeln!(self.out, "\txorl %edx, %edx")?;
+                        writeln!(self.out, "\tdivq %rcx")?;
+                    }
+                    if let BinaryOp::Rem = op {
+                        writeln!(self.out, "\tmovq %rdx, %rax")?;
+                    }
+                }
+                BinaryOp::Shr | BinaryOp::Shl => {
+                    // Shift amounts are taken modulo the width of the type,
+                    // which the processor only does for 64 bits.
+                    if ty.size() < 8 {
+                        writeln!(self.out, "\tandl ${}, %ecx", ty.size() * 8 - 1)?;
+                    }
+                    let shift = match op {
+                        BinaryOp::Shl => "shlq",
+                        _ if signed => "sarq",
+                        _ => "shrq",
+                    };
+                    writeln!(self.out, "\t{} %cl, %rax", shift)?;
+                }
+                _ => unreachable!(),
+            }
+            self.extend_rax(ty)?;
+        }
+        self.store_rax(dest)
+    }
+
+    fn continuation(&mut self, continuation: &Continuation) -> fmt::Result {
+        match continuation {
+            Continuation::Jump(target) => writeln!(self.out, "\tjmp {}", self.label(*target)),
+            Continuation::BranchZero(value, target)
+            | Continuation::BranchNonZero(value, target) => {
+                match value {
+                    Value::Temporary(temporary) => match self.frame.locations[temporary] {
+                        Location::Register(register) => {
+                            let name = register.name();
+                            writeln!(self.out, "\ttestq %{}, %{}", name, name)?;
+                        }
+                        Location::Stack(offset) => {
+                            writeln!(self.out, "\tcmpq $0, {}(%rbp)", offset)?;
+                        }
+                    },
+                    value => {
+                        self.load_rax(value, Type::U64)?;
+                        writeln!(self.out, "\ttestq %rax, %rax")?;
+                    }
+                }
+                let jump = match continuation {
+                    Continuation::BranchZero(..) => "je",
+                    _ => "jne",
+                };
+                writeln!(self.out, "\t{} {}", jump, self.label(*target))
+            }
+            Continuation::Return(value) => {
+                if let Some(ty) = self.return_type {
+                    self.load_rax(value, ty)?;
+                }
+                if self.frame.saved.is_empty() {
+                    writeln!(self.out, "\tleave")?;
+                } else {
+                    let saved = 8 * self.frame.saved.len();
+                    writeln!(self.out, "\tleaq -{}(%rbp), %rsp", saved)?;
+                    for register in self.frame.saved.iter().rev() {
+                        writeln!(self.out, "\tpopq %{}", register.name())?;
+                    }
+                    writeln!(self.out, "\tpopq %rbp")?;
+                }
+                writeln!(self.out, "\tret")
+            }
+            Continuation::Halt => writeln!(self.out, "\tud2"),
+        }
+    }
+}
+
+/// Returns the value of an integer literal used as a value of type `ty`,
+/// extended to 64 bits.
+fn literal_value(literal: &il::Literal, ty: Type) -> i64 {
+    match literal {
+        il::Literal::Int(int) => {
+            ty.extend(int.value().expect("literal does not fit in 64 bits")) as i64
+        }
+        il::Literal::Nil => 0,
+    }
+}
@@ -1,44 +1,91 @@
+use std::io::Write;
+
 use anyhow::Context;
-use chumsky::Parser;
-use rspika::ast::module;
-use rspika::token::tokenize;
+use rspika::backend::{c, qbe, wasm, x86_64};
+use rspika::diagnostic::Source;
+use rspika::driver;
+use rspika::il::cfg::Cfg;
 
 fn main() -> anyhow::Result<()> {
-    let mut args = std::env::args_os();
-    let infile = args.nth(1).context("missing argument: INFILE")?;
-    let raw_module = std::fs::read_to_string(infile).context("cannot read input file")?;
-
-    let tokens = match tokenize().parse(raw_module) {
-        Ok(x) => x,
-        Err(errs) => {
-            for err in errs {
-                eprintln!("{:?}", err);
-            }
-            return Ok(());
-        }
-    };
-    let ast = match module().parse(tokens) {
-        Ok(x) => x,
-        Err(errs) => {
-            for err in errs {
-                eprintln!("{:?}", err);
-            }
-            return Ok(());
+    let mut emit_c = false;
+    let mut emit_qbe = false;
+    let mut emit_asm = false;
+    let mut emit_wasm = false;
+    let mut emit_wat = false;
+    let mut emit_dot = false;
+    let mut optimize = false;
+    let mut infile = None;
+    for arg in std::env::args_os().skip(1) {
+        if arg == "--c" {
+            emit_c = true;
+        } else if arg == "--qbe" {
+            emit_qbe = true;
+        } else if arg == "--asm" {
+            emit_asm = true;
+        } else if arg == "--wasm" {
+            emit_wasm = true;
+        } else if arg == "--wat" {
+            emit_wat = true;
+        } else if arg == "--dot" {
+            emit_dot = true;
+        } else if arg == "-O" {
+            optimize = true;
+        } else {
+            infile = Some(arg);
         }
-    };
-
-    let il = ast.visit_il();
-
-    for (name, func) in &il.functions {
-        println!();
-        println!("{}()", name);
-        for (i, instr) in func.assembly.instructions().iter().enumerate() {
-            for label in func.assembly.labels_at(i) {
-                println!("  {:?}:", label);
-            }
-            println!("    {:?}", instr);
+    }
+    let infile = infile.context("missing argument: INFILE")?;
+    let source = Source::read(infile).context("cannot read input file")?;
+
+    let ast =
+        driver::parse(&source).unwrap_or_else(|diags| driver::report_and_exit(&source, &diags));
+
+    let types =
+        driver::check(&ast).unwrap_or_else(|diags| driver::report_and_exit(&source, &diags));
+
+    if emit_c {
+        print!("{}", c::emit_module(&ast, &types));
+        return Ok(());
+    }
+
+    let mut il = ast.visit_il(&types);
+    if optimize {
+        il.optimize();
+    }
+
+    if emit_qbe {
+        print!("{}", qbe::emit_module(&il));
+        return Ok(());
+    }
+
+    if emit_asm {
+        print!("{}", x86_64::emit_module(&il));
+        return Ok(());
+    }
+
+    if emit_wasm {
+        std::io::stdout()
+            .write_all(&wasm::emit_module(&il))
+            .context("cannot write module")?;
+        return Ok(());
+    }
+
+    if emit_wat {
+        print!("{}", wasm::emit_wat(&il));
+        return Ok(());
+    }
+
+    if emit_dot {
+        for (name, func) in il.functions_in_order() {
+            print!(
+                "{}",
+                Cfg::new(func.assembly.clone()).to_dot(&name.to_string())
+            );
         }
+        return Ok(());
     }
 
+    print!("{}", il);
+
     Ok(())
 }
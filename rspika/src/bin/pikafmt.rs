@@ -0,0 +1,48 @@
+use anyhow::{bail, Context};
+use rspika::diagnostic::Source;
+use rspika::driver;
+use rspika::format;
+
+fn main() -> anyhow::Result<()> {
+    let mut check = false;
+    let mut write = false;
+    let mut infiles = Vec::new();
+    for arg in std::env::args_os().skip(1) {
+        if arg == "--check" {
+            check = true;
+        } else if arg == "--write" {
+            write = true;
+        } else {
+            infiles.push(arg);
+        }
+    }
+    if infiles.is_empty() {
+        bail!("missing argument: INFILE");
+    }
+
+    let mut unformatted = 0;
+    for infile in &infiles {
+        let source = Source::read(infile).context("cannot read input file")?;
+        let (module, comments) = driver::parse_with_comments(&source)
+            .unwrap_or_else(|diags| driver::report_and_exit(&source, &diags));
+        let formatted = format::format_module(&module, &source.text, &comments);
+
+        if check {
+            if formatted != source.text {
+                eprintln!("{} is not formatted", source.name);
+                unformatted += 1;
+            }
+        } else if write {
+            if formatted != source.text {
+                std::fs::write(infile, formatted).context("cannot write output file")?;
+            }
+        } else {
+            print!("{}", formatted);
+        }
+    }
+
+    if unformatted > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
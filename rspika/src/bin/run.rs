@@ -0,0 +1,54 @@
+use anyhow::{bail, Context};
+use rspika::diagnostic::Source;
+use rspika::driver;
+use rspika::interp::{self, Interpreter, Value};
+use rspika::typeck::Type;
+
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let infile = args.next().context("missing argument: INFILE")?;
+    let function = args.next().context("missing argument: FUNCTION")?;
+    let source = Source::read(infile).context("cannot read input file")?;
+
+    let ast =
+        driver::parse(&source).unwrap_or_else(|diags| driver::report_and_exit(&source, &diags));
+
+    let types =
+        driver::check(&ast).unwrap_or_else(|diags| driver::report_and_exit(&source, &diags));
+
+    let (name, sig) = types
+        .functions
+        .iter()
+        .find(|(name, _)| name.to_string() == function)
+        .with_context(|| format!("no function named `{}`", function))?;
+
+    let args: Vec<String> = args.collect();
+    if args.len() != sig.params.len() {
+        bail!(
+            "`{}` takes {} argument(s) but {} were given",
+            function,
+            sig.params.len(),
+            args.len()
+        );
+    }
+    let mut values = Vec::new();
+    for (arg, ty) in args.iter().zip(&sig.params) {
+        let Type::Primitive(prim) = ty else {
+            bail!("cannot pass an argument of type `{}`", ty);
+        };
+        let value: i128 = arg
+            .parse()
+            .with_context(|| format!("invalid integer argument `{}`", arg))?;
+        if interp::wrap(value, *prim) != value {
+            bail!("argument `{}` is out of range for `{}`", arg, ty);
+        }
+        values.push(Value::Int(value));
+    }
+
+    let result = Interpreter::new(&ast, &types)
+        .call(name, values)
+        .unwrap_or_else(|diag| driver::report_and_exit(&source, &[diag]));
+    println!("{}", result);
+
+    Ok(())
+}
@@ -0,0 +1,226 @@
+//! Error reporting against the source text.
+
+use std::{collections::HashSet, fmt, hash::Hash, path::Path};
+
+use chumsky::error::{Error, Simple, SimpleReason};
+
+use crate::token::Span;
+
+/// A named source text that diagnostics can point into.
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub name: String,
+    pub text: String,
+}
+
+impl Source {
+    pub fn new(name: impl Into<String>, text: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            text: text.into(),
+        }
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        Ok(Self::new(path.display().to_string(), text))
+    }
+
+    /// Returns the 1-based line and column of a byte offset.
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let before = &self.text[..offset.min(self.text.len())];
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line = before.matches('\n').count() + 1;
+        let column = before[line_start..].chars().count() + 1;
+        (line, column)
+    }
+
+    /// Returns the text of the 1-based line `line`, without its terminator.
+    pub fn line(&self, line: usize) -> &str {
+        self.text
+            .split('\n')
+            .nth(line - 1)
+            .unwrap_or("")
+            .trim_end_matches('\r')
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            message: message.into(),
+            span,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Renders the diagnostic with its location and an underlined excerpt of
+    /// `source`.
+    pub fn render(&self, source: &Source) -> String {
+        let (line, column) = source.line_col(self.span.start);
+        let text = source.line(line);
+        let gutter = " ".repeat(line.to_string().len());
+
+        // Keep tabs in the padding so the carets line up with the excerpt.
+        let padding: String = text
+            .chars()
+            .take(column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let (end_line, end_column) = source.line_col(self.span.end);
+        let underline_len = if end_line == line {
+            end_column.saturating_sub(column)
+        } else {
+            text.chars().count().saturating_sub(column - 1)
+        };
+        let carets = "^".repeat(underline_len.max(1));
+
+        let mut out = format!("error: {}\n", self.message);
+        out += &format!("{}--> {}:{}:{}\n", gutter, source.name, line, column);
+        out += &format!("{} |\n", gutter);
+        out += &format!("{} | {}\n", line, text);
+        out += &format!("{} | {}{}\n", gutter, padding, carets);
+        for note in &self.notes {
+            out += &format!("{} = note: {}\n", gutter, note);
+        }
+        out
+    }
+}
+
+/// A parse error that keeps the labels of the parsers it expected.
+///
+/// [`Simple`] drops the labels of alternatives that fail at the same position
+/// when they differ, so a labelled parser that matches any token of a kind,
+/// such as an identifier, would be missing from the expected tokens. This
+/// error lists such parsers among them by their label.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError<T: Hash + Eq> {
+    simple: Simple<T>,
+    expected_labels: HashSet<&'static str>,
+}
+
+impl<T: Hash + Eq> From<Simple<T>> for ParseError<T> {
+    fn from(simple: Simple<T>) -> Self {
+        Self {
+            simple,
+            expected_labels: HashSet::new(),
+        }
+    }
+}
+
+impl<T: Hash + Eq> Error<T> for ParseError<T> {
+    type Span = Span;
+    type Label = &'static str;
+
+    fn expected_input_found<Iter: IntoIterator<Item = Option<T>>>(
+        span: Span,
+        expected: Iter,
+        found: Option<T>,
+    ) -> Self {
+        Simple::expected_input_found(span, expected, found).into()
+    }
+
+    fn unclosed_delimiter(
+        unclosed_span: Span,
+        unclosed: T,
+        span: Span,
+        expected: T,
+        found: Option<T>,
+    ) -> Self {
+        Simple::unclosed_delimiter(unclosed_span, unclosed, span, expected, found).into()
+    }
+
+    fn with_label(mut self, label: &'static str) -> Self {
+        // The innermost label of an error that expected no particular token
+        // names the kind of token that was expected. Any outer label names
+        // what was being parsed.
+        let expected_nothing = self.simple.expected().next().is_none()
+            && self.expected_labels.is_empty()
+            && self.simple.label().is_none()
+            && matches!(self.simple.reason(), SimpleReason::Unexpected);
+        if expected_nothing {
+            self.expected_labels.insert(label);
+        } else {
+            self.simple = self.simple.with_label(label);
+        }
+        self
+    }
+
+    fn merge(mut self, other: Self) -> Self {
+        self.simple = self.simple.merge(other.simple);
+        self.expected_labels.extend(other.expected_labels);
+        self
+    }
+}
+
+impl<T: fmt::Display + Hash + Eq> From<Simple<T>> for Diagnostic {
+    fn from(err: Simple<T>) -> Self {
+        ParseError::from(err).into()
+    }
+}
+
+impl<T: fmt::Display + Hash + Eq> From<ParseError<T>> for Diagnostic {
+    fn from(err: ParseError<T>) -> Self {
+        let ParseError {
+            simple: err,
+            expected_labels,
+        } = err;
+        let found = match err.found() {
+            Some(found) => format!("`{}`", found),
+            None => "end of input".to_string(),
+        };
+        let mut diagnostic = match err.reason() {
+            SimpleReason::Unexpected => Self::error(format!("unexpected {}", found), err.span()),
+            SimpleReason::Unclosed { span, delimiter } => {
+                Self::error(format!("unclosed delimiter `{}`", delimiter), span.clone())
+                    .with_note(format!("found {} before it was closed", found))
+            }
+            SimpleReason::Custom(message) => Self::error(message.clone(), err.span()),
+        };
+        if let Some(expected) = describe_expected(expected_labels, err.expected()) {
+            diagnostic = diagnostic.with_note(expected);
+        }
+        if let Some(label) = err.label() {
+            diagnostic = diagnostic.with_note(format!("while parsing {}", label));
+        }
+        diagnostic
+    }
+}
+
+/// Lists the expected kinds of tokens by their label, followed by the
+/// expected tokens themselves.
+fn describe_expected<'a, T: fmt::Display + 'a>(
+    labels: HashSet<&'static str>,
+    expected: impl Iterator<Item = &'a Option<T>>,
+) -> Option<String> {
+    let mut labels: Vec<String> = labels.into_iter().map(str::to_string).collect();
+    labels.sort();
+    let mut tokens: Vec<String> = expected
+        .map(|token| match token {
+            Some(token) => format!("`{}`", token),
+            None => "end of input".to_string(),
+        })
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    tokens.sort();
+    let names = [labels, tokens].concat();
+    match names.as_slice() {
+        [] => None,
+        [name] => Some(format!("expected {}", name)),
+        names => Some(format!("expected one of {}", names.join(", "))),
+    }
+}
@@ -0,0 +1,47 @@
+//! Shared front end for the command-line tools.
+
+use crate::{
+    ast,
+    diagnostic::{Diagnostic, Source},
+    resolve,
+    token::{self, Comment},
+    typeck::{self, TypeckResults},
+};
+
+/// Lexes and parses `source` into a module.
+pub fn parse(source: &Source) -> Result<ast::Module, Vec<Diagnostic>> {
+    parse_with_comments(source).map(|(module, _)| module)
+}
+
+/// Lexes and parses `source` into a module, also returning its comments.
+pub fn parse_with_comments(
+    source: &Source,
+) -> Result<(ast::Module, Vec<Comment>), Vec<Diagnostic>> {
+    let (tokens, comments) = token::lex_with_comments(&source.text).map_err(into_diagnostics)?;
+    let module = ast::parse(tokens, source.text.len()).map_err(into_diagnostics)?;
+    Ok((module, comments))
+}
+
+/// Runs the semantic checks over a parsed module.
+pub fn check(module: &ast::Module) -> Result<TypeckResults, Vec<Diagnostic>> {
+    let resolutions = resolve::resolve(module)?;
+    typeck::check(module, resolutions)
+}
+
+/// Prints `diagnostics` to standard error and exits with a failure status.
+pub fn report_and_exit(source: &Source, diagnostics: &[Diagnostic]) -> ! {
+    for diagnostic in diagnostics {
+        eprintln!("{}", diagnostic.render(source));
+    }
+    let plural = if diagnostics.len() == 1 { "" } else { "s" };
+    eprintln!(
+        "error: aborting due to {} previous error{}",
+        diagnostics.len(),
+        plural
+    );
+    std::process::exit(1)
+}
+
+fn into_diagnostics<E: Into<Diagnostic>>(errs: Vec<E>) -> Vec<Diagnostic> {
+    errs.into_iter().map(Into::into).collect()
+}
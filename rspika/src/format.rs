@@ -0,0 +1,412 @@
+//! Formats parsed modules back into Pika source.
+//!
+//! The output uses the canonical style: tabs for indentation, one blank line
+//! between items, opening braces at the end of the line that starts them,
+//! parentheses around the heads of `if` and `for` and elsewhere only where
+//! precedence needs them. Struct and enum items and struct literals have one
+//! field per line with a trailing comma, as do array literals that do not
+//! fit on one line. Single blank lines between statements are kept.
+//!
+//! Comments stay on the line before the item, statement or field they
+//! precede, or at the end of the line they follow. Comments in the middle of
+//! an expression are moved to the line after the statement or element
+//! containing it.
+
+use crate::{
+    ast::{
+        ArrayInit, Block, EnumItem, Expr, ExprKind, FnItem, Item, Iterable, Module, Statement,
+        StatementKind, StructItem, SuffixOp, Type,
+    },
+    token::{Comment, Span},
+};
+
+/// Array literals longer than this are written one element per line.
+const MAX_INLINE_ARRAY: usize = 60;
+
+/// Formats `module`, parsed from `source` with `comments` in it.
+pub fn format_module(module: &Module, source: &str, comments: &[Comment]) -> String {
+    let mut formatter = Formatter::new(source, comments);
+    for (i, item) in module.items.iter().enumerate() {
+        if i > 0 {
+            formatter.out.push('\n');
+            formatter.keep_blank = false;
+        }
+        match item {
+            Item::Fn(fnn) => formatter.fn_item(fnn),
+            Item::Struct(strukt) => formatter.struct_item(strukt),
+            Item::Enum(enumm) => formatter.enum_item(enumm),
+        }
+    }
+    formatter.comments_before(source.len(), 0);
+    formatter.out
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    /// The comments not written yet.
+    comments: &'a [Comment],
+    out: String,
+    /// End of the last item, statement or comment written, as an offset
+    /// into the source.
+    last_end: usize,
+    /// Whether a blank line in the source before the next line is kept,
+    /// which it is not at the start of a block.
+    keep_blank: bool,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(source: &'a str, comments: &'a [Comment]) -> Self {
+        Self {
+            source,
+            comments,
+            out: String::new(),
+            last_end: 0,
+            keep_blank: false,
+        }
+    }
+
+    fn fn_item(&mut self, fnn: &FnItem) {
+        self.start_line(fnn.span.start, 0);
+        self.out += &format!("fn {}(", fnn.name);
+        for (i, arg) in fnn.args.iter().enumerate() {
+            if i > 0 {
+                self.out += ", ";
+            }
+            self.out += &format!("{}: {}", arg.arg_name, type_name(&arg.arg_type));
+        }
+        self.out += &format!(") -> {} ", type_name(&fnn.return_type));
+        self.block(&fnn.body, 0);
+        self.end_line(fnn.span.end, 0);
+    }
+
+    fn struct_item(&mut self, strukt: &StructItem) {
+        self.start_line(strukt.span.start, 0);
+        self.out += &format!("struct {} {{", strukt.name);
+        let fields = strukt.fields.iter().map(|field| {
+            let text = format!("{}: {},", field.field_name, type_name(&field.field_type));
+            (text, field.span.clone())
+        });
+        self.lines(fields, strukt.span.clone(), 0);
+        self.out.push('}');
+        self.end_line(strukt.span.end, 0);
+    }
+
+    fn enum_item(&mut self, enumm: &EnumItem) {
+        self.start_line(enumm.span.start, 0);
+        self.out += &format!("enum {} {{", enumm.name);
+        let variants = enumm
+            .variants
+            .iter()
+            .map(|variant| (format!("{},", variant.name), variant.span.clone()));
+        self.lines(variants, enumm.span.clone(), 0);
+        self.out.push('}');
+        self.end_line(enumm.span.end, 0);
+    }
+
+    /// Writes a line of text for each of `lines` at `depth + 1`, after the
+    /// opening delimiter of a list spanning `span` and before its closing
+    /// one.
+    fn lines(
+        &mut self,
+        lines: impl ExactSizeIterator<Item = (String, Span)>,
+        span: Span,
+        depth: usize,
+    ) {
+        if lines.len() == 0 && !self.has_comments(span.clone()) {
+            return;
+        }
+        self.out.push('\n');
+        self.keep_blank = false;
+        for (text, span) in lines {
+            self.start_line(span.start, depth + 1);
+            self.out += &text;
+            self.end_line(span.end, depth + 1);
+        }
+        self.close(span.end, depth);
+    }
+
+    /// Writes a block starting on the current line, which is at `depth`.
+    fn block(&mut self, block: &Block, depth: usize) {
+        self.out.push('{');
+        if block.statements.is_empty()
+            && block.expr.is_none()
+            && !self.has_comments(block.span.clone())
+        {
+            self.out.push('}');
+            return;
+        }
+        self.out.push('\n');
+        self.keep_blank = false;
+        for stmt in &block.statements {
+            self.statement(stmt, depth + 1);
+        }
+        if let Some(expr) = &block.expr {
+            self.start_line(expr.span.start, depth + 1);
+            self.expr(expr, depth + 1);
+            self.end_line(expr.span.end, depth + 1);
+        }
+        self.close(block.span.end, depth);
+        self.out.push('}');
+    }
+
+    fn statement(&mut self, stmt: &Statement, depth: usize) {
+        self.start_line(stmt.span.start, depth);
+        match &stmt.kind {
+            StatementKind::Block(block) => self.block(block, depth),
+            StatementKind::Let(lett) => {
+                self.out += "let ";
+                if lett.is_mut {
+                    self.out += "mut ";
+                }
+                self.out += &lett.binding.to_string();
+                if let Some(ty) = &lett.binding_type {
+                    self.out += &format!(": {}", type_name(ty));
+                }
+                self.out += " = ";
+                self.expr(&lett.value, depth);
+                self.out.push(';');
+            }
+            StatementKind::Assign(assign) => {
+                self.expr(&assign.dest, depth);
+                self.out += " = ";
+                self.expr(&assign.src, depth);
+                self.out.push(';');
+            }
+            StatementKind::If(iff) => {
+                for (i, case) in iff.cases.iter().enumerate() {
+                    if i > 0 {
+                        self.out += " else ";
+                    }
+                    self.out += "if (";
+                    self.expr(&case.condition, depth);
+                    self.out += ") ";
+                    self.block(&case.body, depth);
+                }
+                if let Some(else_case) = &iff.else_case {
+                    self.out += " else ";
+                    self.block(else_case, depth);
+                }
+            }
+            StatementKind::For(forr) => {
+                let Iterable::Range(start, end) = &forr.iterable;
+                self.out += &format!("for ({} in {}..{}) ", forr.binding, start, end);
+                self.block(&forr.body, depth);
+            }
+            StatementKind::Return(expr) => {
+                self.out += "return ";
+                self.expr(expr, depth);
+                self.out.push(';');
+            }
+            StatementKind::Break => self.out += "break;",
+        }
+        self.end_line(stmt.span.end, depth);
+    }
+
+    /// Writes an expression starting on the current line, which is at
+    /// `depth`.
+    fn expr(&mut self, expr: &Expr, depth: usize) {
+        match &expr.kind {
+            ExprKind::Path(path) => self.out += &path.to_string(),
+            ExprKind::IntLiteral(int) => self.out += &int.to_string(),
+            ExprKind::BoolLiteral(value) => self.out += &value.to_string(),
+            ExprKind::StructInit(init) => {
+                self.out += &format!("{} {{", init.name);
+                if init.fields.is_empty() && !self.has_comments(expr.span.clone()) {
+                    self.out.push('}');
+                    return;
+                }
+                self.out.push('\n');
+                self.keep_blank = false;
+                for field in &init.fields {
+                    self.start_line(field.span.start, depth + 1);
+                    self.out += &format!("{}: ", field.name);
+                    self.expr(&field.value, depth + 1);
+                    self.out.push(',');
+                    self.end_line(field.span.end, depth + 1);
+                }
+                self.close(expr.span.end, depth);
+                self.out.push('}');
+            }
+            ExprKind::ArrayInit(ArrayInit::Fill { element, size }) => {
+                self.out.push('[');
+                self.expr(element, depth);
+                self.out += &format!("; {}]", size);
+            }
+            ExprKind::ArrayInit(ArrayInit::Elements(elements)) => {
+                if let Some(text) = self.inline_array(elements, expr.span.clone()) {
+                    self.out += &text;
+                    return;
+                }
+                self.out += "[\n";
+                self.keep_blank = false;
+                for element in elements {
+                    self.start_line(element.span.start, depth + 1);
+                    self.expr(element, depth + 1);
+                    self.out.push(',');
+                    self.end_line(element.span.end, depth + 1);
+                }
+                self.close(expr.span.end, depth);
+                self.out.push(']');
+            }
+            ExprKind::Prefix(op, operand) => {
+                self.out += &op.to_string();
+                let parenthesize = matches!(operand.kind, ExprKind::Binary(..));
+                self.operand(operand, parenthesize, depth);
+            }
+            ExprKind::Suffix(operand, op) => {
+                let parenthesize =
+                    matches!(operand.kind, ExprKind::Binary(..) | ExprKind::Prefix(..));
+                self.operand(operand, parenthesize, depth);
+                match op {
+                    SuffixOp::FieldAccess(field) => self.out += &format!(".{}", field),
+                    SuffixOp::ArrayIndex(index) => {
+                        self.out.push('[');
+                        self.expr(index, depth);
+                        self.out.push(']');
+                    }
+                }
+            }
+            ExprKind::Binary(op, left, right) => {
+                // Operators are left-associative, so a right operand of the
+                // same precedence needs parentheses.
+                let parenthesize_left = matches!(
+                    &left.kind,
+                    ExprKind::Binary(left_op, ..) if left_op.precedence() < op.precedence()
+                );
+                let parenthesize_right = matches!(
+                    &right.kind,
+                    ExprKind::Binary(right_op, ..) if right_op.precedence() <= op.precedence()
+                );
+                self.operand(left, parenthesize_left, depth);
+                self.out += &format!(" {} ", op);
+                self.operand(right, parenthesize_right, depth);
+            }
+        }
+    }
+
+    fn operand(&mut self, expr: &Expr, parenthesize: bool, depth: usize) {
+        if parenthesize {
+            self.out.push('(');
+        }
+        self.expr(expr, depth);
+        if parenthesize {
+            self.out.push(')');
+        }
+    }
+
+    /// Returns the array literal with `elements` written on one line, or
+    /// `None` if it is too long, has comments in it or contains a literal
+    /// that needs more lines.
+    fn inline_array(&self, elements: &[Expr], span: Span) -> Option<String> {
+        if self.has_comments(span) {
+            return None;
+        }
+        let mut inline = Formatter::new(self.source, &[]);
+        inline.out.push('[');
+        for (i, element) in elements.iter().enumerate() {
+            if i > 0 {
+                inline.out += ", ";
+            }
+            inline.expr(element, 0);
+        }
+        inline.out.push(']');
+        (inline.out.len() <= MAX_INLINE_ARRAY && !inline.out.contains('\n')).then_some(inline.out)
+    }
+
+    /// Starts a line at `depth` for what starts at `start` in the source,
+    /// after the comments before it.
+    fn start_line(&mut self, start: usize, depth: usize) {
+        self.comments_before(start, depth);
+        self.indent(start, depth);
+    }
+
+    /// Ends a line for what ends at `end` in the source. A comment after it
+    /// on the same line goes at the end of the line, and comments before
+    /// `end` that have not been written yet go on the lines after it.
+    fn end_line(&mut self, end: usize, depth: usize) {
+        let inside = self
+            .comments
+            .iter()
+            .take_while(|c| c.span.start < end)
+            .count();
+        let (inside, rest) = self.comments.split_at(inside);
+        let trailing = rest.first().filter(|comment| {
+            let between = &self.source[end..comment.span.start];
+            between.chars().all(|c| matches!(c, ' ' | '\t' | ',' | ';'))
+        });
+        self.last_end = end;
+        if inside.is_empty() {
+            if let Some(comment) = trailing {
+                self.out += &format!(" {}", comment.text);
+                self.last_end = comment.span.end;
+                self.comments = &rest[1..];
+            }
+            self.out.push('\n');
+            return;
+        }
+        self.out.push('\n');
+        self.comments = rest;
+        for comment in inside.iter().chain(trailing) {
+            self.out += &"\t".repeat(depth);
+            self.out += &comment.text;
+            self.out.push('\n');
+        }
+        if trailing.is_some() {
+            self.last_end = rest[0].span.end;
+            self.comments = &rest[1..];
+        }
+    }
+
+    /// Writes the comments left before `end`, the end of a list or block at
+    /// `depth` whose contents have been written, and starts the line of its
+    /// closing delimiter.
+    fn close(&mut self, end: usize, depth: usize) {
+        self.comments_before(end, depth + 1);
+        self.out += &"\t".repeat(depth);
+    }
+
+    /// Writes each comment starting before `start` on a line of its own.
+    fn comments_before(&mut self, start: usize, depth: usize) {
+        while let Some((comment, rest)) = self.comments.split_first() {
+            if comment.span.start >= start {
+                break;
+            }
+            self.indent(comment.span.start, depth);
+            self.out += &comment.text;
+            self.out.push('\n');
+            self.last_end = comment.span.end;
+            self.comments = rest;
+        }
+    }
+
+    /// Indents a new line to `depth`, after a blank line if there is one in
+    /// the source before `start`.
+    fn indent(&mut self, start: usize, depth: usize) {
+        if self.keep_blank && start > self.last_end {
+            let gap = &self.source[self.last_end..start];
+            let lines: Vec<&str> = gap.split('\n').collect();
+            if lines.len() > 2
+                && lines[1..lines.len() - 1]
+                    .iter()
+                    .any(|line| line.trim().is_empty())
+            {
+                self.out.push('\n');
+            }
+        }
+        self.keep_blank = true;
+        self.out += &"\t".repeat(depth);
+    }
+
+    fn has_comments(&self, span: Span) -> bool {
+        self.comments
+            .iter()
+            .any(|comment| span.contains(&comment.span.start))
+    }
+}
+
+fn type_name(ty: &Type) -> String {
+    match ty {
+        Type::Path(path) => path.to_string(),
+        Type::Array(array) => format!("[{}; {}]", type_name(&array.element), array.size),
+    }
+}
@@ -3,51 +3,95 @@
 //! Modeled after the intermediate language used by the QBE compiler backend.
 //! https://c9x.me/compile/doc/il.html
 
-use std::collections::HashMap;
+pub mod cfg;
+pub mod dce;
+pub mod fold;
+pub mod parse;
+pub mod print;
+pub mod ssa;
+pub mod verify;
+pub mod vm;
 
-use crate::{
-    ast::Path,
-    token::{Ident, IntLiteral},
-};
+pub use verify::verify;
+
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use crate::token::{Ident, IntLiteral, Span};
 
 #[derive(Debug)]
 pub struct Module {
     pub functions: HashMap<Ident, Function>,
 }
 
-#[derive(Debug)]
-pub struct Function {
-    pub assembly: Assembly,
-}
-
-#[derive(Debug)]
-pub struct Scope<'parent> {
-    parent: Option<&'parent Scope<'parent>>,
-    locals: HashMap<Path, Value>,
-}
+impl Module {
+    /// Returns the function called `name`, if any.
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions
+            .iter()
+            .find(|(function_name, _)| function_name.to_string() == name)
+            .map(|(_, function)| function)
+    }
 
-impl<'parent> Scope<'parent> {
-    pub fn new(parent: Option<&'parent Scope<'parent>>) -> Self {
-        Self {
-            parent,
-            locals: HashMap::new(),
-        }
+    /// Returns the functions in the order they are defined in the source.
+    pub fn functions_in_order(&self) -> Vec<(&Ident, &Function)> {
+        let mut functions: Vec<_> = self.functions.iter().collect();
+        functions.sort_by_key(|(_, function)| function.span.start);
+        functions
     }
 
-    pub fn resolve(&self, path: &Path) -> &Value {
-        self.locals
-            .get(path)
-            .or_else(|| self.parent.as_ref().map(|parent| parent.resolve(path)))
-            .unwrap()
+    /// Runs the optimization passes over every function.
+    pub fn optimize(&mut self) {
+        const PASSES: &[(&str, Pass)] = &[
+            ("mem2reg", ssa::mem2reg),
+            ("fold_constants", fold::fold_constants),
+            ("eliminate_dead_code", dce::eliminate_dead_code),
+        ];
+        for (name, pass) in PASSES {
+            for function in self.functions.values_mut() {
+                pass(function);
+            }
+            self.debug_verify(name);
+        }
     }
 
-    pub fn declare(&mut self, path: Path, value: Value) {
-        assert!(!self.locals.contains_key(&path));
-        self.locals.insert(path, value);
+    /// Panics if the module is malformed, in debug builds only. `after`
+    /// names the step that produced the module.
+    pub fn debug_verify(&self, after: &str) {
+        if cfg!(debug_assertions) {
+            if let Err(errors) = verify(self) {
+                let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+                panic!("malformed IL after {}:\n{}", after, errors.join("\n"));
+            }
+        }
     }
 }
 
+/// A transformation of a single function.
+type Pass = fn(&mut Function);
+
 #[derive(Debug)]
+pub struct Function {
+    /// The types of the arguments, in order.
+    pub params: Vec<Type>,
+    /// The type of the value returned, or `None` if the function returns
+    /// no value, in which case it returns `Nil`.
+    pub return_type: Option<Type>,
+    pub assembly: Assembly,
+    /// Where the function is defined in the source it was lowered or parsed
+    /// from.
+    pub span: Span,
+}
+
+/// How a variable is accessed.
+#[derive(Debug, Clone)]
+pub enum Variable {
+    /// The variable is held directly in a value.
+    Value(Value),
+    /// The variable is stored in memory at the given address.
+    Memory(Value),
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct Assembly {
     instructions: Vec<Instruction>,
     labels: HashMap<Label, usize>,
@@ -58,13 +102,7 @@ pub struct Assembly {
 
 impl Assembly {
     pub fn new() -> Self {
-        Self {
-            instructions: Vec::new(),
-            labels: HashMap::new(),
-            reverse_labels: HashMap::new(),
-            next_temporary: 0,
-            next_label: 0,
-        }
+        Self::default()
     }
 
     pub fn new_temporary(&mut self) -> Temporary {
@@ -86,20 +124,40 @@ impl Assembly {
         );
         let position = self.instructions.len();
         self.labels.insert(label, position);
-        self.reverse_labels
-            .entry(position)
-            .or_insert(Vec::new())
-            .push(label);
+        self.reverse_labels.entry(position).or_default().push(label);
     }
 
     pub fn push(&mut self, instr: Instruction) {
         self.instructions.push(instr)
     }
 
+    /// Inserts instructions at the start of the assembly, before any labels.
+    pub fn prepend(&mut self, instrs: impl IntoIterator<Item = Instruction>) {
+        let mut instructions: Vec<Instruction> = instrs.into_iter().collect();
+        let shift = instructions.len();
+        instructions.append(&mut self.instructions);
+        self.instructions = instructions;
+
+        for position in self.labels.values_mut() {
+            *position += shift;
+        }
+        self.reverse_labels = self
+            .reverse_labels
+            .drain()
+            .map(|(position, labels)| (position + shift, labels))
+            .collect();
+    }
+
     pub fn instructions(&self) -> &[Instruction] {
         &self.instructions
     }
 
+    /// Returns the position of the instruction `label` refers to, if it has
+    /// been set.
+    pub fn label_position(&self, label: Label) -> Option<usize> {
+        self.labels.get(&label).copied()
+    }
+
     pub fn labels_at(&self, position: usize) -> &[Label] {
         self.reverse_labels
             .get(&position)
@@ -108,7 +166,7 @@ impl Assembly {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Instruction {
     Operation(Output, Operation),
     Call(Option<Output>, Call),
@@ -116,7 +174,10 @@ pub enum Instruction {
         output: Output,
         addr: Value,
     },
+    /// Stores a value of type `ty`, accessing as many bytes as it has.
+    /// Loads access as many bytes as their output type has.
     Store {
+        ty: Type,
         addr: Value,
         value: Value,
     },
@@ -125,16 +186,92 @@ pub enum Instruction {
         size: u64,
         alignment: u64,
     },
+    /// Takes the value paired with the label of the block control arrived
+    /// from. Phis come before any other instruction in their block.
+    Phi(Output, Vec<(Label, Value)>),
     Continuation(Continuation),
 }
 
-#[derive(Debug)]
+impl Instruction {
+    /// Returns the temporary defined by the instruction, if any.
+    pub fn output(&self) -> Option<Temporary> {
+        match self {
+            Self::Operation(output, _)
+            | Self::Call(Some(output), _)
+            | Self::Load { output, .. }
+            | Self::Alloc {
+                addr_output: output,
+                ..
+            }
+            | Self::Phi(output, _) => Some(output.dest),
+            Self::Call(None, _) | Self::Store { .. } | Self::Continuation(_) => None,
+        }
+    }
+
+    /// Returns the type of the temporary defined by the instruction, if any.
+    pub fn output_type(&self) -> Option<Type> {
+        match self {
+            Self::Operation(output, _)
+            | Self::Call(Some(output), _)
+            | Self::Load { output, .. }
+            | Self::Alloc {
+                addr_output: output,
+                ..
+            }
+            | Self::Phi(output, _) => Some(output.dest_type),
+            Self::Call(None, _) | Self::Store { .. } | Self::Continuation(_) => None,
+        }
+    }
+
+    /// Returns the values used by the instruction.
+    pub fn operands(&self) -> Vec<&Value> {
+        match self {
+            Self::Operation(_, Operation::Binary(_, _, left, right)) => vec![left, right],
+            Self::Operation(_, Operation::Unary(_, _, value)) => vec![value],
+            Self::Call(_, call) => call.arguments.iter().collect(),
+            Self::Load { addr, .. } => vec![addr],
+            Self::Store { addr, value, .. } => vec![addr, value],
+            Self::Alloc { .. } => Vec::new(),
+            Self::Phi(_, sources) => sources.iter().map(|(_, value)| value).collect(),
+            Self::Continuation(
+                Continuation::BranchZero(value, _)
+                | Continuation::BranchNonZero(value, _)
+                | Continuation::Return(value),
+            ) => vec![value],
+            Self::Continuation(Continuation::Jump(_) | Continuation::Halt) => Vec::new(),
+        }
+    }
+
+    /// Returns the values used by the instruction, for rewriting them.
+    pub fn operands_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            Self::Operation(_, Operation::Binary(_, _, left, right)) => vec![left, right],
+            Self::Operation(_, Operation::Unary(_, _, value)) => vec![value],
+            Self::Call(_, call) => call.arguments.iter_mut().collect(),
+            Self::Load { addr, .. } => vec![addr],
+            Self::Store { addr, value, .. } => vec![addr, value],
+            Self::Alloc { .. } => Vec::new(),
+            Self::Phi(_, sources) => sources.iter_mut().map(|(_, value)| value).collect(),
+            Self::Continuation(
+                Continuation::BranchZero(value, _)
+                | Continuation::BranchNonZero(value, _)
+                | Continuation::Return(value),
+            ) => vec![value],
+            Self::Continuation(Continuation::Jump(_) | Continuation::Halt) => Vec::new(),
+        }
+    }
+}
+
+/// An operation on operands of the given type. Comparisons produce 0 or 1
+/// in their output type; other binary operations, negation and `Not`
+/// produce a value of the operand type.
+#[derive(Debug, Clone)]
 pub enum Operation {
-    Binary(BinaryOp, Value, Value),
-    Unary(UnaryOp, Value),
+    Binary(BinaryOp, Type, Value, Value),
+    Unary(UnaryOp, Type, Value),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum BinaryOp {
     Add,
     Sub,
@@ -154,28 +291,160 @@ pub enum BinaryOp {
     Ge,
 }
 
-#[derive(Debug)]
+impl BinaryOp {
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            Self::Eq | Self::Ne | Self::Lt | Self::Le | Self::Gt | Self::Ge
+        )
+    }
+
+    /// Evaluates the operation on the bit patterns of two operands of type
+    /// `ty`, returning the bit pattern of the result. Returns `None` for
+    /// division by zero.
+    pub fn evaluate(&self, ty: Type, left: u64, right: u64) -> Option<u64> {
+        let (left, right) = (ty.extend(left), ty.extend(right));
+        // Shift amounts are taken modulo the width, like on common hardware.
+        let shift = (right as u64 % (ty.size() * 8)) as u32;
+        let result = match self {
+            Self::Add => left + right,
+            Self::Sub => left - right,
+            Self::Mul => left.wrapping_mul(right),
+            Self::Div | Self::Rem if right == 0 => return None,
+            Self::Div => left / right,
+            Self::Rem => left % right,
+            Self::Or => left | right,
+            Self::Xor => left ^ right,
+            Self::And => left & right,
+            Self::Shr => left >> shift,
+            Self::Shl => left << shift,
+            Self::Eq => (left == right).into(),
+            Self::Ne => (left != right).into(),
+            Self::Lt => (left < right).into(),
+            Self::Le => (left <= right).into(),
+            Self::Gt => (left > right).into(),
+            Self::Ge => (left >= right).into(),
+        };
+        Some(ty.truncate(result))
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Add => "add",
+            Self::Sub => "sub",
+            Self::Mul => "mul",
+            Self::Div => "div",
+            Self::Rem => "rem",
+            Self::Or => "or",
+            Self::Xor => "xor",
+            Self::And => "and",
+            Self::Shr => "shr",
+            Self::Shl => "shl",
+            Self::Eq => "eq",
+            Self::Ne => "ne",
+            Self::Lt => "lt",
+            Self::Le => "le",
+            Self::Gt => "gt",
+            Self::Ge => "ge",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for BinaryOp {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, ()> {
+        Ok(match name {
+            "add" => Self::Add,
+            "sub" => Self::Sub,
+            "mul" => Self::Mul,
+            "div" => Self::Div,
+            "rem" => Self::Rem,
+            "or" => Self::Or,
+            "xor" => Self::Xor,
+            "and" => Self::And,
+            "shr" => Self::Shr,
+            "shl" => Self::Shl,
+            "eq" => Self::Eq,
+            "ne" => Self::Ne,
+            "lt" => Self::Lt,
+            "le" => Self::Le,
+            "gt" => Self::Gt,
+            "ge" => Self::Ge,
+            _ => return Err(()),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum UnaryOp {
     Neg,
     Not,
+    /// Converts an integer to the output type, extending it according to
+    /// the signedness of the operand or wrapping it around.
     Convert,
+    /// Reinterprets the bits of the operand as the output type, which has
+    /// the same size.
     Cast,
 }
 
-#[derive(Debug)]
+impl UnaryOp {
+    /// Evaluates the operation on the bit pattern of an operand of type
+    /// `ty`, returning the bit pattern of a result of type `dest_type`.
+    pub fn evaluate(&self, ty: Type, dest_type: Type, value: u64) -> u64 {
+        let value = ty.extend(value);
+        let result = match self {
+            Self::Neg => -value,
+            Self::Not => (value == 0).into(),
+            Self::Convert | Self::Cast => value,
+        };
+        dest_type.truncate(result)
+    }
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Neg => "neg",
+            Self::Not => "not",
+            Self::Convert => "convert",
+            Self::Cast => "cast",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for UnaryOp {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, ()> {
+        Ok(match name {
+            "neg" => Self::Neg,
+            "not" => Self::Not,
+            "convert" => Self::Convert,
+            "cast" => Self::Cast,
+            _ => return Err(()),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Call {
     // TODO function name type
     pub function_name: String,
     pub arguments: Vec<Value>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Output {
     pub dest: Temporary,
-    // pub dest_type: Type,
+    pub dest_type: Type,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Continuation {
     Jump(Label),
     BranchZero(Value, Label),
@@ -184,7 +453,7 @@ pub enum Continuation {
     Halt,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Value {
     Temporary(Temporary),
     Literal(Literal),
@@ -194,7 +463,13 @@ pub enum Value {
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Temporary(usize);
 
-#[derive(Debug, Clone)]
+impl Temporary {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Literal {
     Nil,
     Int(IntLiteral),
@@ -206,7 +481,17 @@ pub struct Argument(pub usize);
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Label(usize);
 
-#[derive(Debug)]
+impl Label {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// The type of a value in the IL.
+///
+/// Floating-point types are treated as integers of the same size for now,
+/// like in the interpreter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Type {
     I8,
     I16,
@@ -220,4 +505,104 @@ pub enum Type {
     Usize,
     F32,
     F64,
+    /// An address in memory. Aggregates are represented by their address.
+    Ptr,
+}
+
+impl Type {
+    pub fn is_integer(self) -> bool {
+        !matches!(self, Self::F32 | Self::F64)
+    }
+
+    pub fn is_signed(self) -> bool {
+        matches!(
+            self,
+            Self::I8 | Self::I16 | Self::I32 | Self::I64 | Self::Isize | Self::F32 | Self::F64
+        )
+    }
+
+    /// Size in bytes. Pointer-sized types assume a 64-bit target.
+    pub fn size(self) -> u64 {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::I64 | Self::U64 | Self::Isize | Self::Usize | Self::F64 | Self::Ptr => 8,
+        }
+    }
+
+    /// The largest value representable by an integer type.
+    pub fn max_value(self) -> u64 {
+        let bits = self.size() * 8;
+        if self.is_signed() {
+            (1 << (bits - 1)) - 1
+        } else {
+            u64::MAX >> (64 - bits)
+        }
+    }
+
+    /// Reads the bit pattern `bits` as a value of this type, ignoring the
+    /// bits past its size.
+    pub fn extend(self, bits: u64) -> i128 {
+        let size = self.size() * 8;
+        let value = bits & self.mask();
+        if self.is_signed() && (value >> (size - 1)) & 1 == 1 {
+            value as i128 - (1 << size)
+        } else {
+            value as i128
+        }
+    }
+
+    /// Truncates `value` to the bit pattern of this type, wrapping around.
+    pub fn truncate(self, value: i128) -> u64 {
+        value as u64 & self.mask()
+    }
+
+    fn mask(self) -> u64 {
+        u64::MAX >> (64 - self.size() * 8)
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::I8 => "i8",
+            Self::I16 => "i16",
+            Self::I32 => "i32",
+            Self::I64 => "i64",
+            Self::U8 => "u8",
+            Self::U16 => "u16",
+            Self::U32 => "u32",
+            Self::U64 => "u64",
+            Self::Isize => "isize",
+            Self::Usize => "usize",
+            Self::F32 => "f32",
+            Self::F64 => "f64",
+            Self::Ptr => "ptr",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Type {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, ()> {
+        Ok(match name {
+            "i8" => Self::I8,
+            "i16" => Self::I16,
+            "i32" => Self::I32,
+            "i64" => Self::I64,
+            "u8" => Self::U8,
+            "u16" => Self::U16,
+            "u32" => Self::U32,
+            "u64" => Self::U64,
+            "isize" => Self::Isize,
+            "usize" => Self::Usize,
+            "f32" => Self::F32,
+            "f64" => Self::F64,
+            "ptr" => Self::Ptr,
+            _ => return Err(()),
+        })
+    }
 }
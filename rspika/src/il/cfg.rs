@@ -0,0 +1,356 @@
+//! Control-flow graphs of IL functions.
+//!
+//! A [`Cfg`] takes apart an [`Assembly`] into basic blocks: straight-line
+//! runs of instructions that are only entered at the top and only left at
+//! the bottom. Blocks keep the order they had in the assembly, so a block
+//! that does not end in a jump falls through to the next one, and a
+//! conditional branch falls through when it is not taken.
+
+use std::{collections::HashMap, fmt::Write};
+
+use super::{Assembly, Continuation, Instruction, Label, Temporary};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(usize);
+
+impl BlockId {
+    /// The block where execution of the function starts.
+    pub const ENTRY: Self = Self(0);
+
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Block {
+    /// Labels referring to the start of the block.
+    pub labels: Vec<Label>,
+    pub instructions: Vec<Instruction>,
+}
+
+impl Block {
+    /// Returns the continuation ending the block, if any.
+    pub fn terminator(&self) -> Option<&Continuation> {
+        match self.instructions.last() {
+            Some(Instruction::Continuation(continuation)) => Some(continuation),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Cfg {
+    pub blocks: Vec<Block>,
+    successors: Vec<Vec<BlockId>>,
+    predecessors: Vec<Vec<BlockId>>,
+    next_temporary: usize,
+    next_label: usize,
+}
+
+impl Cfg {
+    pub fn new(assembly: Assembly) -> Self {
+        let mut blocks = vec![Block {
+            labels: assembly.labels_at(0).to_vec(),
+            instructions: Vec::new(),
+        }];
+        let len = assembly.instructions.len();
+        let mut terminated = false;
+        for (position, instr) in assembly.instructions.into_iter().enumerate() {
+            let labels = assembly.reverse_labels.get(&position);
+            if position > 0 && (terminated || labels.is_some()) {
+                blocks.push(Block {
+                    labels: labels.cloned().unwrap_or_default(),
+                    instructions: Vec::new(),
+                });
+            }
+            terminated = matches!(instr, Instruction::Continuation(_));
+            blocks.last_mut().unwrap().instructions.push(instr);
+        }
+        // Labels past the last instruction start an empty block.
+        if let Some(labels) = assembly.reverse_labels.get(&len).filter(|_| len > 0) {
+            blocks.push(Block {
+                labels: labels.clone(),
+                instructions: Vec::new(),
+            });
+        }
+
+        let mut cfg = Self {
+            blocks,
+            successors: Vec::new(),
+            predecessors: Vec::new(),
+            next_temporary: assembly.next_temporary,
+            next_label: assembly.next_label,
+        };
+        cfg.compute_edges();
+        cfg
+    }
+
+    /// Lays the blocks out one after another again.
+    pub fn into_assembly(mut self) -> Assembly {
+        // A labeled block that is empty would share its position with the
+        // next block, merging the two, so it jumps there explicitly instead.
+        for i in 0..self.blocks.len().saturating_sub(1) {
+            let block = &self.blocks[i];
+            if block.instructions.is_empty() && !block.labels.is_empty() {
+                let next = self.block_label(BlockId(i + 1));
+                self.blocks[i]
+                    .instructions
+                    .push(Instruction::Continuation(Continuation::Jump(next)));
+            }
+        }
+
+        let mut assembly = Assembly {
+            next_temporary: self.next_temporary,
+            next_label: self.next_label,
+            ..Assembly::default()
+        };
+        for block in self.blocks {
+            for label in block.labels {
+                assembly.set_label(label);
+            }
+            for instr in block.instructions {
+                assembly.push(instr);
+            }
+        }
+        assembly
+    }
+
+    /// Recomputes the edges between blocks after their instructions or
+    /// order have changed.
+    pub fn compute_edges(&mut self) {
+        let block_of: HashMap<Label, BlockId> = self
+            .blocks
+            .iter()
+            .enumerate()
+            .flat_map(|(i, block)| block.labels.iter().map(move |&label| (label, BlockId(i))))
+            .collect();
+        let target = |label: &Label| {
+            *block_of
+                .get(label)
+                .unwrap_or_else(|| panic!("jump to label {} which is never set", label.index()))
+        };
+
+        let count = self.blocks.len();
+        self.successors = vec![Vec::new(); count];
+        self.predecessors = vec![Vec::new(); count];
+        for (i, block) in self.blocks.iter().enumerate() {
+            let next = (i + 1 < count).then_some(BlockId(i + 1));
+            let successors = match block.terminator() {
+                Some(Continuation::Jump(label)) => vec![target(label)],
+                Some(
+                    Continuation::BranchZero(_, label) | Continuation::BranchNonZero(_, label),
+                ) => next.into_iter().chain([target(label)]).collect(),
+                Some(Continuation::Return(_) | Continuation::Halt) => Vec::new(),
+                None => next.into_iter().collect(),
+            };
+            for &successor in &successors {
+                if !self.predecessors[successor.0].contains(&BlockId(i)) {
+                    self.predecessors[successor.0].push(BlockId(i));
+                }
+            }
+            self.successors[i] = successors;
+            self.successors[i].dedup();
+        }
+    }
+
+    pub fn new_temporary(&mut self) -> Temporary {
+        let result = Temporary(self.next_temporary);
+        self.next_temporary += 1;
+        result
+    }
+
+    pub fn new_label(&mut self) -> Label {
+        let result = Label(self.next_label);
+        self.next_label += 1;
+        result
+    }
+
+    /// Returns the label of block `id`, giving it a fresh one if it has none.
+    pub fn block_label(&mut self, id: BlockId) -> Label {
+        if let Some(label) = self.blocks[id.0].labels.first() {
+            return *label;
+        }
+        let label = self.new_label();
+        self.blocks[id.0].labels.push(label);
+        label
+    }
+
+    pub fn block(&self, id: BlockId) -> &Block {
+        &self.blocks[id.0]
+    }
+
+    pub fn block_mut(&mut self, id: BlockId) -> &mut Block {
+        &mut self.blocks[id.0]
+    }
+
+    pub fn block_ids(&self) -> impl Iterator<Item = BlockId> {
+        (0..self.blocks.len()).map(BlockId)
+    }
+
+    pub fn successors(&self, id: BlockId) -> &[BlockId] {
+        &self.successors[id.0]
+    }
+
+    pub fn predecessors(&self, id: BlockId) -> &[BlockId] {
+        &self.predecessors[id.0]
+    }
+
+    /// Returns the blocks reachable from the entry, each one before its
+    /// successors except along back edges.
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut postorder = Vec::new();
+        // Each stack entry holds a block and how many of its successors
+        // have been visited so far.
+        let mut stack = vec![(BlockId::ENTRY, 0)];
+        visited[0] = true;
+        while let Some((block, next)) = stack.last_mut() {
+            match self.successors[block.0].get(*next) {
+                Some(&successor) => {
+                    *next += 1;
+                    if !visited[successor.0] {
+                        visited[successor.0] = true;
+                        stack.push((successor, 0));
+                    }
+                }
+                None => {
+                    postorder.push(*block);
+                    stack.pop();
+                }
+            }
+        }
+        postorder.reverse();
+        postorder
+    }
+
+    /// Computes the dominator tree, using the algorithm from "A Simple, Fast
+    /// Dominance Algorithm" by Cooper, Harvey and Kennedy.
+    pub fn dominators(&self) -> Dominators {
+        let order = self.reverse_postorder();
+        let mut rank = vec![usize::MAX; self.blocks.len()];
+        for (i, block) in order.iter().enumerate() {
+            rank[block.0] = i;
+        }
+
+        let mut idom: Vec<Option<BlockId>> = vec![None; self.blocks.len()];
+        idom[0] = Some(BlockId::ENTRY);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in &order[1..] {
+                let mut new_idom = None;
+                for &pred in &self.predecessors[block.0] {
+                    if idom[pred.0].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => pred,
+                        Some(other) => intersect(&idom, &rank, pred, other),
+                    });
+                }
+                if new_idom != idom[block.0] {
+                    idom[block.0] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+        Dominators { idom }
+    }
+
+    /// Computes the dominance frontier of every block: the blocks where its
+    /// dominance ends, because they can also be reached without passing
+    /// through it.
+    pub fn dominance_frontiers(&self, dominators: &Dominators) -> Vec<Vec<BlockId>> {
+        let mut frontiers = vec![Vec::new(); self.blocks.len()];
+        for block in self.block_ids() {
+            let preds = &self.predecessors[block.0];
+            let Some(idom) = dominators.idom[block.0] else {
+                continue;
+            };
+            if preds.len() < 2 {
+                continue;
+            }
+            for &pred in preds {
+                // Unreachable predecessors have no dominators.
+                let mut runner = pred;
+                while dominators.idom[runner.0].is_some() && runner != idom {
+                    if !frontiers[runner.0].contains(&block) {
+                        frontiers[runner.0].push(block);
+                    }
+                    runner = dominators.idom[runner.0].unwrap();
+                }
+            }
+        }
+        frontiers
+    }
+
+    /// Renders the graph in the Graphviz DOT language.
+    pub fn to_dot(&self, name: &str) -> String {
+        let mut out = String::new();
+        writeln!(out, "digraph \"{}\" {{", name).unwrap();
+        writeln!(out, "    node [shape=box, fontname=monospace];").unwrap();
+        for (i, block) in self.blocks.iter().enumerate() {
+            let mut text = String::new();
+            for label in &block.labels {
+                write!(text, "{}\\l", label).unwrap();
+            }
+            for instr in &block.instructions {
+                write!(text, "  {}\\l", instr).unwrap();
+            }
+            writeln!(out, "    b{} [label=\"b{}:\\l{}\"];", i, i, text).unwrap();
+        }
+        for (i, successors) in self.successors.iter().enumerate() {
+            for successor in successors {
+                writeln!(out, "    b{} -> b{};", i, successor.0).unwrap();
+            }
+        }
+        writeln!(out, "}}").unwrap();
+        out
+    }
+}
+
+/// Walks up the dominator tree from `a` and `b` to their closest common
+/// dominator.
+fn intersect(idom: &[Option<BlockId>], rank: &[usize], mut a: BlockId, mut b: BlockId) -> BlockId {
+    while a != b {
+        while rank[a.0] > rank[b.0] {
+            a = idom[a.0].unwrap();
+        }
+        while rank[b.0] > rank[a.0] {
+            b = idom[b.0].unwrap();
+        }
+    }
+    a
+}
+
+#[derive(Debug)]
+pub struct Dominators {
+    /// The immediate dominator of each block. The entry is its own immediate
+    /// dominator, and unreachable blocks have none.
+    idom: Vec<Option<BlockId>>,
+}
+
+impl Dominators {
+    /// Returns the immediate dominator of `block`, or `None` for the entry
+    /// and unreachable blocks.
+    pub fn immediate_dominator(&self, block: BlockId) -> Option<BlockId> {
+        self.idom[block.0].filter(|_| block != BlockId::ENTRY)
+    }
+
+    /// Whether every path from the entry to `b` goes through `a`.
+    pub fn dominates(&self, a: BlockId, mut b: BlockId) -> bool {
+        if self.idom[b.0].is_none() {
+            return false;
+        }
+        loop {
+            if a == b {
+                return true;
+            }
+            match self.immediate_dominator(b) {
+                Some(idom) => b = idom,
+                None => return false,
+            }
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! Dead code elimination.
+//!
+//! Removes the blocks that cannot be reached from the entry, which include
+//! any instructions following a continuation, and the instructions whose
+//! results are never used and that have no effect besides producing them.
+
+use std::collections::HashSet;
+
+use super::{
+    cfg::{BlockId, Cfg},
+    BinaryOp, Function, Instruction, Label, Literal, Operation, Value,
+};
+
+pub fn eliminate_dead_code(function: &mut Function) {
+    let mut cfg = Cfg::new(std::mem::take(&mut function.assembly));
+    remove_unreachable_blocks(&mut cfg);
+    while remove_unused_instructions(&mut cfg) {}
+    function.assembly = cfg.into_assembly();
+}
+
+fn remove_unreachable_blocks(cfg: &mut Cfg) {
+    let reachable: HashSet<usize> = cfg
+        .reverse_postorder()
+        .into_iter()
+        .map(BlockId::index)
+        .collect();
+    if reachable.len() == cfg.blocks.len() {
+        return;
+    }
+    cfg.blocks = std::mem::take(&mut cfg.blocks)
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| reachable.contains(i))
+        .map(|(_, block)| block)
+        .collect();
+
+    // Reachable blocks only jump to reachable blocks, but phis may still
+    // have sources for edges from removed ones.
+    let labels: HashSet<Label> = cfg
+        .blocks
+        .iter()
+        .flat_map(|block| block.labels.iter().copied())
+        .collect();
+    for block in &mut cfg.blocks {
+        for instr in &mut block.instructions {
+            if let Instruction::Phi(_, sources) = instr {
+                sources.retain(|(label, _)| labels.contains(label));
+            }
+        }
+    }
+    cfg.compute_edges();
+}
+
+/// Removes the instructions without side effects whose results are never
+/// used. Returns whether any were removed.
+fn remove_unused_instructions(cfg: &mut Cfg) -> bool {
+    let used: HashSet<_> = cfg
+        .blocks
+        .iter()
+        .flat_map(|block| &block.instructions)
+        .flat_map(Instruction::operands)
+        .filter_map(|value| match value {
+            Value::Temporary(temporary) => Some(*temporary),
+            _ => None,
+        })
+        .collect();
+
+    let mut changed = false;
+    for block in &mut cfg.blocks {
+        block.instructions.retain(|instr| {
+            let unused = instr.output().is_some_and(|output| !used.contains(&output));
+            let dead = unused && is_pure(instr);
+            changed |= dead;
+            !dead
+        });
+    }
+    changed
+}
+
+/// Whether removing `instr` only loses the value it produces.
+fn is_pure(instr: &Instruction) -> bool {
+    match instr {
+        // Division by zero has to be reported even if the result is unused.
+        // Divisors are truncated to the operand type, like every other
+        // operand.
+        Instruction::Operation(
+            _,
+            Operation::Binary(BinaryOp::Div | BinaryOp::Rem, ty, _, divisor),
+        ) => {
+            let Value::Literal(Literal::Int(int)) = divisor else {
+                return false;
+            };
+            int.value()
+                .is_some_and(|value| ty.truncate(value.into()) != 0)
+        }
+        Instruction::Operation(..) | Instruction::Alloc { .. } | Instruction::Phi(..) => true,
+        // Loads may access memory out of bounds, and calls may do anything.
+        Instruction::Load { .. }
+        | Instruction::Call(..)
+        | Instruction::Store { .. }
+        | Instruction::Continuation(_) => false,
+    }
+}
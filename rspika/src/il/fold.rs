@@ -0,0 +1,145 @@
+//! Constant folding and propagation.
+//!
+//! Operations whose operands are all literals are evaluated at compile time
+//! and their results substituted for the temporaries they define, which may
+//! in turn make further operations constant. Branches on a constant
+//! condition become unconditional.
+//!
+//! Operations are evaluated on the types they work on, like in the VM.
+//! Folded values are stored as the bit pattern of the result in its output
+//! type, so -1 as an `i32` becomes 4294967295.
+
+use std::collections::HashMap;
+
+use super::{
+    cfg::{BlockId, Cfg},
+    Continuation, Function, Instruction, Label, Literal, Operation, Temporary, Value,
+};
+
+pub fn fold_constants(function: &mut Function) {
+    let mut cfg = Cfg::new(std::mem::take(&mut function.assembly));
+    let mut constants = HashMap::new();
+    // Phis may only become constant once the values flowing around a loop
+    // are known, or once folding a branch removes some of their sources, so
+    // keep going until nothing changes.
+    loop {
+        while fold_block_instructions(&mut cfg, &mut constants) {}
+        if !fold_branches(&mut cfg) {
+            break;
+        }
+        cfg.compute_edges();
+        remove_stale_phi_sources(&mut cfg);
+    }
+    function.assembly = cfg.into_assembly();
+}
+
+/// Substitutes known constants into operands and removes the instructions
+/// that become constant. Returns whether anything changed.
+fn fold_block_instructions(cfg: &mut Cfg, constants: &mut HashMap<Temporary, u64>) -> bool {
+    let mut changed = false;
+    for block in &mut cfg.blocks {
+        block.instructions.retain_mut(|instr| {
+            for operand in instr.operands_mut() {
+                if let Value::Temporary(temporary) = operand {
+                    if let Some(&value) = constants.get(temporary) {
+                        *operand = literal(value);
+                        changed = true;
+                    }
+                }
+            }
+            let Some(value) = evaluate(instr) else {
+                return true;
+            };
+            constants.insert(instr.output().unwrap(), value);
+            changed = true;
+            false
+        });
+    }
+    changed
+}
+
+/// Returns the constant result of `instr`, if it has one. Floating-point
+/// operations are left alone.
+fn evaluate(instr: &Instruction) -> Option<u64> {
+    match instr {
+        Instruction::Operation(output, Operation::Binary(op, ty, left, right)) => {
+            if !ty.is_integer() {
+                return None;
+            }
+            let result = op.evaluate(*ty, constant(left)?, constant(right)?)?;
+            Some(output.dest_type.truncate(result.into()))
+        }
+        Instruction::Operation(output, Operation::Unary(op, ty, value)) => {
+            if !ty.is_integer() || !output.dest_type.is_integer() {
+                return None;
+            }
+            Some(op.evaluate(*ty, output.dest_type, constant(value)?))
+        }
+        Instruction::Phi(output, sources) => {
+            let ty = output.dest_type;
+            let (first, rest) = sources.split_first()?;
+            let value = ty.truncate(constant(&first.1)?.into());
+            rest.iter()
+                .all(|(_, source)| {
+                    constant(source).map(|source| ty.truncate(source.into())) == Some(value)
+                })
+                .then_some(value)
+        }
+        _ => None,
+    }
+}
+
+fn constant(value: &Value) -> Option<u64> {
+    match value {
+        Value::Literal(Literal::Int(int)) => int.value(),
+        Value::Literal(Literal::Nil) => Some(0),
+        Value::Temporary(_) | Value::Argument(_) => None,
+    }
+}
+
+fn literal(value: u64) -> Value {
+    Value::Literal(Literal::Int(value.into()))
+}
+
+/// Replaces branches on constants with a jump, or removes them if they are
+/// never taken. Returns whether any branch was folded.
+fn fold_branches(cfg: &mut Cfg) -> bool {
+    let mut changed = false;
+    for block in &mut cfg.blocks {
+        let (taken, label) = match block.terminator() {
+            Some(Continuation::BranchZero(value, label)) => match constant(value) {
+                Some(value) => (value == 0, *label),
+                None => continue,
+            },
+            Some(Continuation::BranchNonZero(value, label)) => match constant(value) {
+                Some(value) => (value != 0, *label),
+                None => continue,
+            },
+            _ => continue,
+        };
+        block.instructions.pop();
+        if taken {
+            block
+                .instructions
+                .push(Instruction::Continuation(Continuation::Jump(label)));
+        }
+        changed = true;
+    }
+    changed
+}
+
+/// Removes phi sources for edges that no longer exist.
+fn remove_stale_phi_sources(cfg: &mut Cfg) {
+    let block_of: HashMap<Label, BlockId> = cfg
+        .block_ids()
+        .flat_map(|id| cfg.block(id).labels.iter().map(move |&label| (label, id)))
+        .collect();
+    for id in cfg.block_ids().collect::<Vec<_>>() {
+        let predecessors = cfg.predecessors(id).to_vec();
+        for instr in &mut cfg.block_mut(id).instructions {
+            if let Instruction::Phi(_, sources) = instr {
+                sources.retain(|(label, _)| predecessors.contains(&block_of[label]));
+            }
+        }
+    }
+}
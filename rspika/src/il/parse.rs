@@ -0,0 +1,357 @@
+//! Parser for the textual form of the IL.
+//!
+//! The syntax follows QBE's, with IL types in place of QBE's classes:
+//!
+//! ```text
+//! # Comments run to the end of the line.
+//! function i32 $f(i32 %a0, ptr %a1) {
+//!     %t0 =i32 load %a1
+//!     %t1 =u8 lt i32 %t0, %a0
+//!     jz %t1, @l0
+//!     ret %t0
+//! @l0
+//!     %t2 =i32 call $g(%a0, 1)
+//!     ret %t2
+//! }
+//! ```
+//!
+//! The return type is left out for functions that return no value.
+//! Operations name the type of their operands after the mnemonic only when
+//! it differs from the output type, as for comparisons and conversions.
+//! The remaining instructions are `alloc SIZE, ALIGNMENT`, `store TYPE
+//! VALUE, ADDR`, `phi @l0 VALUE, @l1 VALUE`, `jmp @l0`, `jz VALUE, @l0`,
+//! `jnz VALUE, @l0`, `ret [VALUE]` and `hlt`. Temporaries, arguments and
+//! labels keep the numbers they are written with, so printing a parsed
+//! module gives back the same text.
+
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+use chumsky::{prelude::*, text, Stream};
+
+use super::{
+    Argument, Assembly, BinaryOp, Call, Continuation, Function, Instruction, Label, Literal,
+    Module, Operation, Output, Temporary, Type, UnaryOp, Value,
+};
+use crate::token::{Ident, Span};
+
+/// Parses a module from its textual form, with spans given as byte offsets,
+/// and verifies it.
+pub fn parse(source: &str) -> Result<Module, Vec<Simple<char>>> {
+    let eoi = source.len()..source.len();
+    let chars = source.char_indices().map(|(i, c)| (c, i..i + c.len_utf8()));
+    module().parse(Stream::from_iter(eoi, chars))
+}
+
+pub fn module() -> impl Parser<char, Module, Error = Simple<char>> {
+    whitespace()
+        .ignore_then(function().repeated())
+        .then_ignore(end())
+        .validate(|functions, _, emit| {
+            let mut module = Module {
+                functions: HashMap::new(),
+            };
+            for (name, span, function) in functions {
+                if module.functions.contains_key(&name) {
+                    let message = format!("function `{}` is defined more than once", name);
+                    emit(Simple::custom(span, message));
+                }
+                module.functions.insert(name, function);
+            }
+            // Later stages assume well-formed IL, and would panic on a jump
+            // to a label that is never set, for one.
+            if let Err(errors) = super::verify(&module) {
+                for error in errors {
+                    let function = &module.functions[&Ident::from(error.function.as_str())];
+                    emit(Simple::custom(function.span.clone(), error.to_string()));
+                }
+            }
+            module
+        })
+}
+
+/// A line of a function body.
+enum Item {
+    Label(Label, Span),
+    Instruction(Instruction),
+}
+
+fn function() -> impl Parser<char, (Ident, Span, Function), Error = Simple<char>> {
+    let param = ty()
+        .then_ignore(symbol("%a"))
+        .then(number())
+        .map_with_span(|param, span| (param, span));
+    let params = param
+        .separated_by(symbol(","))
+        .delimited_by(symbol("("), symbol(")"))
+        .validate(|params, _, emit| {
+            let mut types = Vec::new();
+            for (position, ((ty, index), span)) in params.into_iter().enumerate() {
+                if index != position as u64 {
+                    let message = format!("expected parameter %a{}", position);
+                    emit(Simple::custom(span, message));
+                }
+                types.push(ty);
+            }
+            types
+        });
+
+    let item = choice((
+        label().map_with_span(Item::Label),
+        instruction().map(Item::Instruction),
+    ));
+
+    keyword("function")
+        .ignore_then(ty().or_not())
+        .then(global())
+        .then(params)
+        .then(item.repeated().delimited_by(symbol("{"), symbol("}")))
+        .validate(
+            |(((return_type, (name, name_span)), params), items), span, emit| {
+                let mut assembly = Assembly::new();
+                for item in items {
+                    match item {
+                        Item::Label(label, span) => {
+                            if assembly.label_position(label).is_some() {
+                                let message =
+                                    format!("label @l{} is defined more than once", label.index());
+                                emit(Simple::custom(span, message));
+                            } else {
+                                assembly.set_label(label);
+                            }
+                        }
+                        Item::Instruction(instr) => assembly.push(instr),
+                    }
+                }
+                reserve_names(&mut assembly);
+                let function = Function {
+                    params,
+                    return_type,
+                    assembly,
+                    span,
+                };
+                (name, name_span, function)
+            },
+        )
+}
+
+/// Makes sure that new temporaries and labels do not clash with the ones
+/// the assembly was written with.
+fn reserve_names(assembly: &mut Assembly) {
+    let mut temporaries = HashSet::new();
+    let mut labels: HashSet<Label> = assembly.labels.keys().copied().collect();
+    for instr in &assembly.instructions {
+        temporaries.extend(instr.output());
+        for value in instr.operands() {
+            if let Value::Temporary(temporary) = value {
+                temporaries.insert(*temporary);
+            }
+        }
+        match instr {
+            Instruction::Phi(_, sources) => labels.extend(sources.iter().map(|(label, _)| *label)),
+            Instruction::Continuation(
+                Continuation::Jump(label)
+                | Continuation::BranchZero(_, label)
+                | Continuation::BranchNonZero(_, label),
+            ) => {
+                labels.insert(*label);
+            }
+            _ => {}
+        }
+    }
+    assembly.next_temporary = temporaries.iter().map(|t| t.0 + 1).max().unwrap_or(0);
+    assembly.next_label = labels.iter().map(|l| l.0 + 1).max().unwrap_or(0);
+}
+
+fn instruction() -> impl Parser<char, Instruction, Error = Simple<char>> {
+    let binary = mnemonic::<BinaryOp>()
+        .then(ty().or_not())
+        .then(value())
+        .then_ignore(symbol(","))
+        .then(value())
+        .map(|(((op, ty), left), right)| (op, ty, left, right));
+    let unary = mnemonic::<UnaryOp>()
+        .then(ty().or_not())
+        .then(value())
+        .map(|((op, ty), value)| (op, ty, value));
+
+    // Instructions defining a temporary, given its output.
+    let defining = temporary()
+        .then_ignore(symbol("="))
+        .then(ty())
+        .map(|(dest, dest_type)| Output { dest, dest_type })
+        .then(choice((
+            keyword("load").ignore_then(value()).map(Defining::Load),
+            keyword("alloc")
+                .ignore_then(number())
+                .then_ignore(symbol(","))
+                .then(number())
+                .map(|(size, alignment)| Defining::Alloc(size, alignment)),
+            keyword("phi")
+                .ignore_then(label().then(value()).separated_by(symbol(",")).at_least(1))
+                .map(Defining::Phi),
+            keyword("call").ignore_then(call()).map(Defining::Call),
+            binary.map(Defining::Binary),
+            unary.map(Defining::Unary),
+        )))
+        .map(|(output, defining)| match defining {
+            Defining::Load(addr) => Instruction::Load { output, addr },
+            Defining::Alloc(size, alignment) => Instruction::Alloc {
+                addr_output: output,
+                size,
+                alignment,
+            },
+            Defining::Phi(sources) => Instruction::Phi(output, sources),
+            Defining::Call(call) => Instruction::Call(Some(output), call),
+            Defining::Binary((op, ty, left, right)) => {
+                let ty = ty.unwrap_or(output.dest_type);
+                Instruction::Operation(output, Operation::Binary(op, ty, left, right))
+            }
+            Defining::Unary((op, ty, value)) => {
+                let ty = ty.unwrap_or(output.dest_type);
+                Instruction::Operation(output, Operation::Unary(op, ty, value))
+            }
+        });
+
+    let store = keyword("store")
+        .ignore_then(ty())
+        .then(value())
+        .then_ignore(symbol(","))
+        .then(value())
+        .map(|((ty, value), addr)| Instruction::Store { ty, addr, value });
+
+    let continuation = choice((
+        keyword("jmp").ignore_then(label()).map(Continuation::Jump),
+        keyword("jz")
+            .ignore_then(value())
+            .then_ignore(symbol(","))
+            .then(label())
+            .map(|(value, target)| Continuation::BranchZero(value, target)),
+        keyword("jnz")
+            .ignore_then(value())
+            .then_ignore(symbol(","))
+            .then(label())
+            .map(|(value, target)| Continuation::BranchNonZero(value, target)),
+        // A bare `ret` may be followed by an instruction defining a
+        // temporary, which is not its value.
+        keyword("ret")
+            .ignore_then(value().then_ignore(just('=').not().rewind()).or_not())
+            .map(|value| Continuation::Return(value.unwrap_or(Value::Literal(Literal::Nil)))),
+        keyword("hlt").to(Continuation::Halt),
+    ));
+
+    choice((
+        defining,
+        store,
+        keyword("call")
+            .ignore_then(call())
+            .map(|call| Instruction::Call(None, call)),
+        continuation.map(Instruction::Continuation),
+    ))
+}
+
+/// The part of an instruction defining a temporary after its output.
+enum Defining {
+    Load(Value),
+    Alloc(u64, u64),
+    Phi(Vec<(Label, Value)>),
+    Call(Call),
+    Binary((BinaryOp, Option<Type>, Value, Value)),
+    Unary((UnaryOp, Option<Type>, Value)),
+}
+
+fn call() -> impl Parser<char, Call, Error = Simple<char>> {
+    global()
+        .then(
+            value()
+                .separated_by(symbol(","))
+                .delimited_by(symbol("("), symbol(")")),
+        )
+        .map(|((name, _), arguments)| Call {
+            function_name: name.to_string(),
+            arguments,
+        })
+}
+
+fn value() -> impl Parser<char, Value, Error = Simple<char>> {
+    choice((
+        temporary().map(Value::Temporary),
+        symbol("%a")
+            .ignore_then(number())
+            .map(|index| Value::Argument(Argument(index as usize))),
+        number().map(|int| Value::Literal(Literal::Int(int.into()))),
+        keyword("nil").to(Value::Literal(Literal::Nil)),
+    ))
+    .labelled("value")
+}
+
+fn temporary() -> impl Parser<char, Temporary, Error = Simple<char>> {
+    just("%t")
+        .ignore_then(number())
+        .map(|index| Temporary(index as usize))
+}
+
+fn label() -> impl Parser<char, Label, Error = Simple<char>> {
+    just("@l")
+        .ignore_then(number())
+        .map(|index| Label(index as usize))
+        .labelled("label")
+}
+
+/// A function name, with its span.
+fn global() -> impl Parser<char, (Ident, Span), Error = Simple<char>> {
+    just('$')
+        .ignore_then(text::ident())
+        .map_with_span(|name: String, span| (name.as_str().into(), span))
+        .then_ignore(whitespace())
+}
+
+/// An operation, named by its mnemonic.
+#[allow(clippy::result_large_err)]
+fn mnemonic<T: FromStr>() -> impl Parser<char, T, Error = Simple<char>> {
+    text::ident()
+        .try_map(|name: String, span| {
+            name.parse()
+                .map_err(|_| Simple::custom(span, format!("unknown operation `{}`", name)))
+        })
+        .then_ignore(whitespace())
+}
+
+#[allow(clippy::result_large_err)]
+fn ty() -> impl Parser<char, Type, Error = Simple<char>> {
+    text::ident()
+        .try_map(|name: String, span| {
+            name.parse()
+                .map_err(|()| Simple::custom(span, format!("unknown type `{}`", name)))
+        })
+        .then_ignore(whitespace())
+        .labelled("type")
+}
+
+#[allow(clippy::result_large_err)]
+fn number() -> impl Parser<char, u64, Error = Simple<char>> {
+    text::int(10)
+        .try_map(|digits: String, span| {
+            digits
+                .parse()
+                .map_err(|_| Simple::custom(span, "integer does not fit in 64 bits"))
+        })
+        .then_ignore(whitespace())
+}
+
+fn keyword(name: &'static str) -> impl Parser<char, (), Error = Simple<char>> + Clone {
+    text::keyword(name).then_ignore(whitespace())
+}
+
+fn symbol(text: &'static str) -> impl Parser<char, (), Error = Simple<char>> + Clone {
+    just(text).ignored().then_ignore(whitespace())
+}
+
+fn whitespace() -> impl Parser<char, (), Error = Simple<char>> + Clone {
+    let comment = just('#').then(filter(|c| *c != '\n').repeated()).ignored();
+    choice((text::whitespace().at_least(1).ignored(), comment))
+        .repeated()
+        .ignored()
+}
@@ -0,0 +1,151 @@
+//! Printing of the IL in the textual form read by [`parse`](super::parse).
+//!
+//! Functions are printed in the order they are defined in the source, so
+//! that the output of two compiler versions can be compared line by line.
+
+use std::fmt;
+
+use super::{
+    Argument, Call, Continuation, Function, Instruction, Label, Literal, Module, Operation, Output,
+    Temporary, Value,
+};
+use crate::token::Ident;
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, function)) in self.functions_in_order().into_iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write_function(f, name, function)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_function(f: &mut fmt::Formatter<'_>, name: &Ident, function: &Function) -> fmt::Result {
+    write!(f, "function ")?;
+    if let Some(ty) = function.return_type {
+        write!(f, "{} ", ty)?;
+    }
+    write!(f, "${}(", name)?;
+    for (i, ty) in function.params.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{} {}", ty, Argument(i))?;
+    }
+    writeln!(f, ") {{")?;
+
+    let assembly = &function.assembly;
+    let instructions = assembly.instructions();
+    for position in 0..=instructions.len() {
+        for label in assembly.labels_at(position) {
+            writeln!(f, "{}", label)?;
+        }
+        if let Some(instr) = instructions.get(position) {
+            writeln!(f, "\t{}", instr)?;
+        }
+    }
+    writeln!(f, "}}")
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Operation(output, Operation::Binary(op, ty, left, right)) => {
+                write!(f, "{} {}", output, op)?;
+                if *ty != output.dest_type {
+                    write!(f, " {}", ty)?;
+                }
+                write!(f, " {}, {}", left, right)
+            }
+            Self::Operation(output, Operation::Unary(op, ty, value)) => {
+                write!(f, "{} {}", output, op)?;
+                if *ty != output.dest_type {
+                    write!(f, " {}", ty)?;
+                }
+                write!(f, " {}", value)
+            }
+            Self::Call(Some(output), call) => write!(f, "{} {}", output, call),
+            Self::Call(None, call) => write!(f, "{}", call),
+            Self::Load { output, addr } => write!(f, "{} load {}", output, addr),
+            Self::Store { ty, addr, value } => write!(f, "store {} {}, {}", ty, value, addr),
+            Self::Alloc {
+                addr_output,
+                size,
+                alignment,
+            } => write!(f, "{} alloc {}, {}", addr_output, size, alignment),
+            Self::Phi(output, sources) => {
+                write!(f, "{} phi", output)?;
+                for (i, (label, value)) in sources.iter().enumerate() {
+                    let separator = if i == 0 { " " } else { ", " };
+                    write!(f, "{}{} {}", separator, label, value)?;
+                }
+                Ok(())
+            }
+            Self::Continuation(continuation) => write!(f, "{}", continuation),
+        }
+    }
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ={}", self.dest, self.dest_type)
+    }
+}
+
+impl fmt::Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "call ${}(", self.function_name)?;
+        for (i, argument) in self.arguments.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", argument)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl fmt::Display for Continuation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Jump(target) => write!(f, "jmp {}", target),
+            Self::BranchZero(value, target) => write!(f, "jz {}, {}", value, target),
+            Self::BranchNonZero(value, target) => write!(f, "jnz {}, {}", value, target),
+            Self::Return(Value::Literal(Literal::Nil)) => write!(f, "ret"),
+            Self::Return(value) => write!(f, "ret {}", value),
+            Self::Halt => write!(f, "hlt"),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Temporary(temporary) => write!(f, "{}", temporary),
+            Self::Literal(Literal::Int(int)) => write!(f, "{}", int),
+            Self::Literal(Literal::Nil) => write!(f, "nil"),
+            Self::Argument(argument) => write!(f, "{}", argument),
+        }
+    }
+}
+
+impl fmt::Display for Temporary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "%t{}", self.0)
+    }
+}
+
+impl fmt::Display for Argument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "%a{}", self.0)
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "@l{}", self.0)
+    }
+}
@@ -0,0 +1,276 @@
+//! Construction of static single assignment form.
+//!
+//! Lowering keeps mutable variables in stack slots. [`mem2reg`] promotes the
+//! slots whose address is only ever loaded from and stored to into
+//! temporaries, inserting phis where control flow merges different values,
+//! following "Efficiently Computing Static Single Assignment Form and the
+//! Control Dependence Graph" by Cytron et al.
+
+use std::collections::{HashMap, HashSet};
+
+use super::{
+    cfg::{BlockId, Cfg},
+    Function, Instruction, Label, Literal, Output, Temporary, Type, Value,
+};
+
+/// Promotes stack slots of scalars whose address does not escape into
+/// temporaries.
+pub fn mem2reg(function: &mut Function) {
+    let mut cfg = Cfg::new(std::mem::take(&mut function.assembly));
+    let slots = promotable_slots(&cfg);
+    if !slots.is_empty() {
+        Promoter::new(&mut cfg, slots).run();
+    }
+    function.assembly = cfg.into_assembly();
+}
+
+/// Returns the stack slots that are only used as the address of loads and
+/// stores of a single type filling the whole slot, with that type.
+fn promotable_slots(cfg: &Cfg) -> HashMap<Temporary, Type> {
+    let mut sizes = HashMap::new();
+    for block in &cfg.blocks {
+        for instr in &block.instructions {
+            if let Instruction::Alloc {
+                addr_output, size, ..
+            } = instr
+            {
+                sizes.insert(addr_output.dest, *size);
+            }
+        }
+    }
+    let mut slots = HashMap::new();
+    let mut rejected = HashSet::new();
+    for block in &cfg.blocks {
+        for instr in &block.instructions {
+            let (access, escaping) = match instr {
+                Instruction::Load {
+                    output,
+                    addr: Value::Temporary(slot),
+                } => (Some((*slot, output.dest_type)), Vec::new()),
+                Instruction::Store {
+                    ty,
+                    addr: Value::Temporary(slot),
+                    value,
+                } => (Some((*slot, *ty)), vec![value]),
+                Instruction::Load { .. } => (None, Vec::new()),
+                Instruction::Store { value, .. } => (None, vec![value]),
+                instr => (None, instr.operands()),
+            };
+            if let Some((slot, ty)) = access {
+                if sizes.get(&slot) != Some(&ty.size()) || *slots.entry(slot).or_insert(ty) != ty {
+                    rejected.insert(slot);
+                }
+            }
+            for value in escaping {
+                if let Value::Temporary(temporary) = value {
+                    rejected.insert(*temporary);
+                }
+            }
+        }
+    }
+    slots.retain(|slot, _| !rejected.contains(slot));
+    slots
+}
+
+/// Returns the slot accessed by a load or store of a promoted slot.
+fn accessed_slot(instr: &Instruction, slots: &HashMap<Temporary, Type>) -> Option<Temporary> {
+    match instr {
+        Instruction::Load {
+            addr: Value::Temporary(slot),
+            ..
+        }
+        | Instruction::Store {
+            addr: Value::Temporary(slot),
+            ..
+        } if slots.contains_key(slot) => Some(*slot),
+        Instruction::Alloc { addr_output, .. } if slots.contains_key(&addr_output.dest) => {
+            Some(addr_output.dest)
+        }
+        _ => None,
+    }
+}
+
+struct Promoter<'a> {
+    cfg: &'a mut Cfg,
+    /// The promoted slots and the type of the values stored in them.
+    slots: HashMap<Temporary, Type>,
+    /// The phis to insert at the start of each block, as the slot they
+    /// merge and the temporary they define.
+    phis: HashMap<BlockId, Vec<(Temporary, Temporary)>>,
+    phi_sources: HashMap<Temporary, Vec<(Label, Value)>>,
+    /// The value each slot holds at the point being renamed, innermost
+    /// definition last.
+    stacks: HashMap<Temporary, Vec<Value>>,
+    /// Values replacing the results of removed loads.
+    replacements: HashMap<Temporary, Value>,
+    children: Vec<Vec<BlockId>>,
+}
+
+impl<'a> Promoter<'a> {
+    fn new(cfg: &'a mut Cfg, slots: HashMap<Temporary, Type>) -> Self {
+        Self {
+            cfg,
+            slots,
+            phis: HashMap::new(),
+            phi_sources: HashMap::new(),
+            stacks: HashMap::new(),
+            replacements: HashMap::new(),
+            children: Vec::new(),
+        }
+    }
+
+    fn run(mut self) {
+        let dominators = self.cfg.dominators();
+        self.place_phis(&self.cfg.dominance_frontiers(&dominators));
+
+        self.children = vec![Vec::new(); self.cfg.blocks.len()];
+        for block in self.cfg.block_ids() {
+            if let Some(idom) = dominators.immediate_dominator(block) {
+                self.children[idom.index()].push(block);
+            }
+        }
+        self.rename(BlockId::ENTRY);
+        self.finish();
+    }
+
+    /// Places a phi for each slot wherever stores to it in different blocks
+    /// may meet.
+    fn place_phis(&mut self, frontiers: &[Vec<BlockId>]) {
+        let mut slots: Vec<Temporary> = self.slots.keys().copied().collect();
+        slots.sort_by_key(|slot| slot.index());
+        for slot in slots {
+            let mut worklist: Vec<BlockId> = self
+                .cfg
+                .block_ids()
+                .filter(|&block| {
+                    self.cfg.block(block).instructions.iter().any(|instr| {
+                        matches!(instr, Instruction::Store { .. })
+                            && accessed_slot(instr, &self.slots) == Some(slot)
+                    })
+                })
+                .collect();
+            let mut has_phi = HashSet::new();
+            let mut queued: HashSet<BlockId> = worklist.iter().copied().collect();
+            while let Some(block) = worklist.pop() {
+                for &frontier in &frontiers[block.index()] {
+                    if !has_phi.insert(frontier) {
+                        continue;
+                    }
+                    let dest = self.cfg.new_temporary();
+                    self.phis.entry(frontier).or_default().push((slot, dest));
+                    self.phi_sources.insert(dest, Vec::new());
+                    if queued.insert(frontier) {
+                        worklist.push(frontier);
+                    }
+                }
+            }
+        }
+    }
+
+    fn current_value(&self, slot: Temporary) -> Value {
+        self.stacks
+            .get(&slot)
+            .and_then(|stack| stack.last())
+            .cloned()
+            // Reading a slot before storing to it is undefined; zero is as
+            // good a value as any.
+            .unwrap_or(Value::Literal(Literal::Int(0.into())))
+    }
+
+    fn rename(&mut self, block: BlockId) {
+        let mut pushed = Vec::new();
+        for &(slot, dest) in self.phis.get(&block).into_iter().flatten() {
+            self.stacks
+                .entry(slot)
+                .or_default()
+                .push(Value::Temporary(dest));
+            pushed.push(slot);
+        }
+
+        let instructions = std::mem::take(&mut self.cfg.block_mut(block).instructions);
+        let mut kept = Vec::with_capacity(instructions.len());
+        for mut instr in instructions {
+            for operand in instr.operands_mut() {
+                if let Value::Temporary(temporary) = operand {
+                    if let Some(value) = self.replacements.get(temporary) {
+                        *operand = value.clone();
+                    }
+                }
+            }
+            let Some(slot) = accessed_slot(&instr, &self.slots) else {
+                kept.push(instr);
+                continue;
+            };
+            match instr {
+                Instruction::Load { output, .. } => {
+                    let value = self.current_value(slot);
+                    self.replacements.insert(output.dest, value);
+                }
+                Instruction::Store { value, .. } => {
+                    self.stacks.entry(slot).or_default().push(value);
+                    pushed.push(slot);
+                }
+                _ => {}
+            }
+        }
+        self.cfg.block_mut(block).instructions = kept;
+
+        let successors = self.cfg.successors(block).to_vec();
+        if successors.iter().any(|succ| self.phis.contains_key(succ)) {
+            let label = self.cfg.block_label(block);
+            for succ in successors {
+                for &(slot, dest) in self.phis.get(&succ).into_iter().flatten() {
+                    let value = self.current_value(slot);
+                    self.phi_sources
+                        .get_mut(&dest)
+                        .unwrap()
+                        .push((label, value));
+                }
+            }
+        }
+
+        for child in self.children[block.index()].clone() {
+            self.rename(child);
+        }
+        for slot in pushed {
+            self.stacks.get_mut(&slot).unwrap().pop();
+        }
+    }
+
+    /// Inserts the phis, and removes accesses to the promoted slots from
+    /// unreachable blocks, which renaming does not visit.
+    fn finish(mut self) {
+        for block in self.cfg.block_ids().collect::<Vec<_>>() {
+            let instructions = &mut self.cfg.block_mut(block).instructions;
+            instructions.retain(|instr| match instr {
+                Instruction::Load { output, .. } if accessed_slot(instr, &self.slots).is_some() => {
+                    let zero = Value::Literal(Literal::Int(0.into()));
+                    self.replacements.insert(output.dest, zero);
+                    false
+                }
+                instr => accessed_slot(instr, &self.slots).is_none(),
+            });
+        }
+
+        for block in self.cfg.block_ids().collect::<Vec<_>>() {
+            let phis = self.phis.remove(&block).unwrap_or_default();
+            let instructions = &mut self.cfg.block_mut(block).instructions;
+            for instr in instructions.iter_mut() {
+                for operand in instr.operands_mut() {
+                    if let Value::Temporary(temporary) = operand {
+                        if let Some(value) = self.replacements.get(temporary) {
+                            *operand = value.clone();
+                        }
+                    }
+                }
+            }
+            let phis = phis.into_iter().map(|(slot, dest)| {
+                let sources = self.phi_sources.remove(&dest).unwrap();
+                let dest_type = self.slots[&slot];
+                Instruction::Phi(Output { dest, dest_type }, sources)
+            });
+            instructions.splice(0..0, phis);
+        }
+        self.cfg.compute_edges();
+    }
+}
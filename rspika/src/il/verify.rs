@@ -0,0 +1,425 @@
+//! Well-formedness checks for IL modules.
+//!
+//! Lowering and the optimization passes are expected to produce modules
+//! that pass [`verify`]; in debug builds they are checked after each step,
+//! so that a broken pass is caught where it goes wrong rather than in a
+//! backend much later.
+
+use std::{collections::HashMap, fmt};
+
+use super::{
+    cfg::{BlockId, Cfg, Dominators},
+    Continuation, Function, Instruction, Label, Module, Operation, Output, Temporary, Type,
+    UnaryOp, Value,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyError {
+    pub function: String,
+    /// Position of the offending instruction in the function's assembly.
+    pub position: usize,
+    pub kind: VerifyErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyErrorKind {
+    UndefinedLabel(Label),
+    UndefinedTemporary(Temporary),
+    DefinedTwice(Temporary),
+    /// A temporary is used at a point that its definition does not
+    /// dominate, so it may be read before it is assigned.
+    UseBeforeDefinition(Temporary),
+    ArgumentOutOfRange(usize),
+    UnknownFunction(String),
+    WrongArgumentCount {
+        function: String,
+        expected: usize,
+        found: usize,
+    },
+    /// A phi follows a non-phi instruction in its block.
+    MisplacedPhi,
+    /// A phi is in the entry block, which is also entered from outside the
+    /// function, where no phi source can name the edge.
+    EntryPhi,
+    /// A phi has no value for an edge into its block, or a value for a
+    /// block that is not a predecessor.
+    PhiSources,
+    /// Execution can reach the end of the function without a continuation.
+    FallsOffEnd,
+    /// A value or output does not have the type the instruction works on.
+    TypeMismatch {
+        expected: Type,
+        found: Type,
+    },
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "in `{}` at {}: ", self.function, self.position)?;
+        match &self.kind {
+            VerifyErrorKind::UndefinedLabel(label) => {
+                write!(f, "label @l{} is never set", label.index())
+            }
+            VerifyErrorKind::UndefinedTemporary(temporary) => {
+                write!(f, "temporary %t{} is never defined", temporary.index())
+            }
+            VerifyErrorKind::DefinedTwice(temporary) => {
+                write!(
+                    f,
+                    "temporary %t{} is defined more than once",
+                    temporary.index()
+                )
+            }
+            VerifyErrorKind::UseBeforeDefinition(temporary) => write!(
+                f,
+                "temporary %t{} is used where its definition does not dominate",
+                temporary.index()
+            ),
+            VerifyErrorKind::ArgumentOutOfRange(index) => {
+                write!(f, "argument %a{} is out of range", index)
+            }
+            VerifyErrorKind::UnknownFunction(name) => {
+                write!(f, "call to unknown function `{}`", name)
+            }
+            VerifyErrorKind::WrongArgumentCount {
+                function,
+                expected,
+                found,
+            } => write!(
+                f,
+                "`{}` takes {} argument(s) but {} were given",
+                function, expected, found
+            ),
+            VerifyErrorKind::MisplacedPhi => write!(f, "phi after the start of its block"),
+            VerifyErrorKind::EntryPhi => write!(f, "phi in the entry block"),
+            VerifyErrorKind::PhiSources => {
+                write!(f, "phi sources do not match the predecessors of its block")
+            }
+            VerifyErrorKind::FallsOffEnd => write!(f, "control falls off the end"),
+            VerifyErrorKind::TypeMismatch { expected, found } => write!(
+                f,
+                "expected a value of type `{}` but found `{}`",
+                expected, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Checks every function of `module`, returning all the problems found.
+pub fn verify(module: &Module) -> Result<(), Vec<VerifyError>> {
+    let signatures: HashMap<String, &Function> = module
+        .functions
+        .iter()
+        .map(|(name, function)| (name.to_string(), function))
+        .collect();
+    let mut errors = Vec::new();
+    let mut names: Vec<_> = module.functions.keys().collect();
+    names.sort_by_key(|name| name.to_string());
+    for name in names {
+        let mut verifier = Verifier {
+            name: name.to_string(),
+            function: &module.functions[name],
+            signatures: &signatures,
+            errors: &mut errors,
+        };
+        verifier.run();
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+struct Verifier<'a> {
+    name: String,
+    function: &'a Function,
+    signatures: &'a HashMap<String, &'a Function>,
+    errors: &'a mut Vec<VerifyError>,
+}
+
+impl Verifier<'_> {
+    fn error(&mut self, position: usize, kind: VerifyErrorKind) {
+        self.errors.push(VerifyError {
+            function: self.name.clone(),
+            position,
+            kind,
+        });
+    }
+
+    fn run(&mut self) {
+        let assembly = &self.function.assembly;
+        let mut definitions = HashMap::new();
+        let mut types = HashMap::new();
+        let mut labels_set = true;
+        for (position, instr) in assembly.instructions().iter().enumerate() {
+            if let Some(output) = instr.output() {
+                if definitions.insert(output, position).is_some() {
+                    self.error(position, VerifyErrorKind::DefinedTwice(output));
+                }
+                types.insert(output, instr.output_type().unwrap());
+            }
+            for operand in instr.operands() {
+                if let Value::Argument(argument) = operand {
+                    if argument.0 >= self.function.params.len() {
+                        self.error(position, VerifyErrorKind::ArgumentOutOfRange(argument.0));
+                    }
+                }
+            }
+            for label in labels_used(instr) {
+                if assembly.label_position(label).is_none() {
+                    self.error(position, VerifyErrorKind::UndefinedLabel(label));
+                    labels_set = false;
+                }
+            }
+            if let Instruction::Call(_, call) = instr {
+                match self.signatures.get(&call.function_name) {
+                    None => self.error(
+                        position,
+                        VerifyErrorKind::UnknownFunction(call.function_name.clone()),
+                    ),
+                    Some(callee) if callee.params.len() != call.arguments.len() => self.error(
+                        position,
+                        VerifyErrorKind::WrongArgumentCount {
+                            function: call.function_name.clone(),
+                            expected: callee.params.len(),
+                            found: call.arguments.len(),
+                        },
+                    ),
+                    Some(_) => {}
+                }
+            }
+        }
+        for (position, instr) in assembly.instructions().iter().enumerate() {
+            for operand in instr.operands() {
+                if let Value::Temporary(temporary) = operand {
+                    if !definitions.contains_key(temporary) {
+                        self.error(position, VerifyErrorKind::UndefinedTemporary(*temporary));
+                    }
+                }
+            }
+            self.check_types(&types, position, instr);
+        }
+
+        // The control-flow graph cannot be built with jumps going nowhere.
+        if labels_set {
+            self.check_control_flow();
+        }
+    }
+
+    /// Checks that the operands and output of an instruction have the types
+    /// it works on. Literals take whatever type is expected of them.
+    fn check_types(
+        &mut self,
+        types: &HashMap<Temporary, Type>,
+        position: usize,
+        instr: &Instruction,
+    ) {
+        match instr {
+            Instruction::Operation(output, Operation::Binary(op, ty, left, right)) => {
+                self.expect_value(types, position, left, *ty);
+                self.expect_value(types, position, right, *ty);
+                if !op.is_comparison() {
+                    self.expect_output(position, output, *ty);
+                }
+            }
+            Instruction::Operation(output, Operation::Unary(op, ty, value)) => {
+                self.expect_value(types, position, value, *ty);
+                match op {
+                    UnaryOp::Neg | UnaryOp::Not => self.expect_output(position, output, *ty),
+                    UnaryOp::Cast if output.dest_type.size() != ty.size() => self.error(
+                        position,
+                        VerifyErrorKind::TypeMismatch {
+                            expected: *ty,
+                            found: output.dest_type,
+                        },
+                    ),
+                    UnaryOp::Cast | UnaryOp::Convert => {}
+                }
+            }
+            Instruction::Call(output, call) => {
+                // Unknown callees and wrong arities have already been reported.
+                let Some(callee) = self.signatures.get(&call.function_name).copied() else {
+                    return;
+                };
+                if callee.params.len() == call.arguments.len() {
+                    for (argument, ty) in call.arguments.iter().zip(&callee.params) {
+                        self.expect_value(types, position, argument, *ty);
+                    }
+                }
+                if let (Some(output), Some(ty)) = (output, callee.return_type) {
+                    self.expect_output(position, output, ty);
+                }
+            }
+            Instruction::Load { addr, .. } => {
+                self.expect_value(types, position, addr, Type::Ptr);
+            }
+            Instruction::Store { ty, addr, value } => {
+                self.expect_value(types, position, addr, Type::Ptr);
+                self.expect_value(types, position, value, *ty);
+            }
+            Instruction::Alloc { addr_output, .. } => {
+                self.expect_output(position, addr_output, Type::Ptr);
+            }
+            Instruction::Phi(output, sources) => {
+                for (_, value) in sources {
+                    self.expect_value(types, position, value, output.dest_type);
+                }
+            }
+            Instruction::Continuation(Continuation::Return(value)) => {
+                if let Some(ty) = self.function.return_type {
+                    self.expect_value(types, position, value, ty);
+                }
+            }
+            Instruction::Continuation(_) => {}
+        }
+    }
+
+    fn expect_value(
+        &mut self,
+        types: &HashMap<Temporary, Type>,
+        position: usize,
+        value: &Value,
+        expected: Type,
+    ) {
+        let found = match value {
+            Value::Temporary(temporary) => types.get(temporary).copied(),
+            Value::Argument(argument) => self.function.params.get(argument.0).copied(),
+            Value::Literal(_) => None,
+        };
+        // Undefined temporaries and arguments have already been reported.
+        if let Some(found) = found {
+            if found != expected {
+                self.error(position, VerifyErrorKind::TypeMismatch { expected, found });
+            }
+        }
+    }
+
+    fn expect_output(&mut self, position: usize, output: &Output, expected: Type) {
+        if output.dest_type != expected {
+            self.error(
+                position,
+                VerifyErrorKind::TypeMismatch {
+                    expected,
+                    found: output.dest_type,
+                },
+            );
+        }
+    }
+
+    /// Checks that phis match the edges into their block, that uses are
+    /// dominated by their definitions, and that control does not fall off
+    /// the end.
+    fn check_control_flow(&mut self) {
+        let assembly = &self.function.assembly;
+        let cfg = Cfg::new(assembly.clone());
+        let dominators = cfg.dominators();
+
+        // Blocks keep their order, so positions can be recovered by counting.
+        let mut starts = Vec::with_capacity(cfg.blocks.len());
+        let mut position = 0;
+        let mut block_of_label = HashMap::new();
+        let mut definitions = HashMap::new();
+        for id in cfg.block_ids() {
+            let block = cfg.block(id);
+            starts.push(position);
+            for &label in &block.labels {
+                block_of_label.insert(label, id);
+            }
+            for (i, instr) in block.instructions.iter().enumerate() {
+                if let Some(output) = instr.output() {
+                    definitions.entry(output).or_insert((id, i));
+                }
+            }
+            position += block.instructions.len();
+        }
+
+        let reachable = cfg.reverse_postorder();
+        for &id in &reachable {
+            let block = cfg.block(id);
+            let mut in_phis = true;
+            for (i, instr) in block.instructions.iter().enumerate() {
+                let position = starts[id.index()] + i;
+                if let Instruction::Phi(_, sources) = instr {
+                    if !in_phis {
+                        self.error(position, VerifyErrorKind::MisplacedPhi);
+                    }
+                    if id == BlockId::ENTRY {
+                        self.error(position, VerifyErrorKind::EntryPhi);
+                    }
+                    let preds = cfg.predecessors(id);
+                    let matches = sources.len() == preds.len()
+                        && sources
+                            .iter()
+                            .all(|(label, _)| preds.contains(&block_of_label[label]))
+                        && preds.iter().all(|pred| {
+                            sources
+                                .iter()
+                                .any(|(label, _)| block_of_label[label] == *pred)
+                        });
+                    if !matches {
+                        self.error(position, VerifyErrorKind::PhiSources);
+                    }
+                    // A phi source is read at the end of its predecessor.
+                    for (label, value) in sources {
+                        let pred = block_of_label[label];
+                        let end = cfg.block(pred).instructions.len();
+                        self.check_use(&definitions, &dominators, value, (pred, end), position);
+                    }
+                } else {
+                    in_phis = false;
+                    for value in instr.operands() {
+                        self.check_use(&definitions, &dominators, value, (id, i), position);
+                    }
+                }
+            }
+        }
+
+        let last = cfg.block_ids().last().unwrap();
+        if reachable.contains(&last) && cfg.block(last).terminator().is_none() {
+            self.error(assembly.instructions().len(), VerifyErrorKind::FallsOffEnd);
+        }
+    }
+
+    /// Checks that the definition of `value` comes before `at`, a block and
+    /// an index into it, on every path from the entry.
+    fn check_use(
+        &mut self,
+        definitions: &HashMap<Temporary, (BlockId, usize)>,
+        dominators: &Dominators,
+        value: &Value,
+        (block, index): (BlockId, usize),
+        position: usize,
+    ) {
+        let Value::Temporary(temporary) = value else {
+            return;
+        };
+        // Undefined temporaries have already been reported.
+        let Some(&(def_block, def_index)) = definitions.get(temporary) else {
+            return;
+        };
+        let dominated = if def_block == block {
+            def_index < index
+        } else {
+            dominators.dominates(def_block, block)
+        };
+        if !dominated {
+            self.error(position, VerifyErrorKind::UseBeforeDefinition(*temporary));
+        }
+    }
+}
+
+/// Returns the labels an instruction refers to.
+fn labels_used(instr: &Instruction) -> Vec<Label> {
+    match instr {
+        Instruction::Continuation(
+            Continuation::Jump(label)
+            | Continuation::BranchZero(_, label)
+            | Continuation::BranchNonZero(_, label),
+        ) => vec![*label],
+        Instruction::Phi(_, sources) => sources.iter().map(|(label, _)| *label).collect(),
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,320 @@
+//! Virtual machine executing IL modules.
+//!
+//! Values are held as bit patterns of their IL type: every result is
+//! truncated to the size of its output type, and operations read their
+//! operands according to the type they work on. Loads and stores access as
+//! many bytes of little-endian memory as their type has. Stack allocations
+//! are carved out of a single byte array and released when the function
+//! that made them returns.
+
+use std::{collections::HashMap, fmt};
+
+use super::{
+    Continuation, Function, Instruction, Literal, Module, Operation, Temporary, Type, Value,
+};
+
+/// Size in bytes of the memory available to a program.
+const MEMORY_SIZE: usize = 1 << 20;
+
+/// Calls nested deeper than this are reported as a stack overflow.
+const MAX_CALL_DEPTH: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    UnknownFunction(String),
+    WrongArgumentCount {
+        function: String,
+        expected: usize,
+        found: usize,
+    },
+    UndefinedTemporary(usize),
+    UndefinedArgument(usize),
+    UndefinedLabel(usize),
+    /// A phi has no value for the block control arrived from.
+    MissingPhiSource(usize),
+    OutOfBounds(i64),
+    DivisionByZero,
+    StackOverflow,
+    Halt,
+    /// Execution reached the end of a function without a return.
+    FellOffEnd(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownFunction(name) => write!(f, "call to unknown function `{}`", name),
+            Self::WrongArgumentCount {
+                function,
+                expected,
+                found,
+            } => write!(
+                f,
+                "`{}` takes {} argument(s) but {} were given",
+                function, expected, found
+            ),
+            Self::UndefinedTemporary(index) => write!(f, "use of undefined temporary %t{}", index),
+            Self::UndefinedArgument(index) => write!(f, "use of undefined argument %a{}", index),
+            Self::UndefinedLabel(index) => write!(f, "jump to undefined label @l{}", index),
+            Self::MissingPhiSource(index) => {
+                write!(f, "phi %t{} has no value for the incoming edge", index)
+            }
+            Self::OutOfBounds(addr) => write!(f, "memory access out of bounds at {:#x}", addr),
+            Self::DivisionByZero => write!(f, "division by zero"),
+            Self::StackOverflow => write!(f, "stack overflow"),
+            Self::Halt => write!(f, "reached a halt instruction"),
+            Self::FellOffEnd(name) => write!(f, "reached the end of `{}` without returning", name),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub struct Vm<'a> {
+    functions: HashMap<String, &'a Function>,
+    memory: Vec<u8>,
+    /// Address of the first free byte of the stack.
+    stack_pointer: usize,
+    depth: usize,
+}
+
+impl<'a> Vm<'a> {
+    pub fn new(module: &'a Module) -> Self {
+        Self {
+            functions: module
+                .functions
+                .iter()
+                .map(|(name, function)| (name.to_string(), function))
+                .collect(),
+            memory: vec![0; MEMORY_SIZE],
+            // Keep address zero unused, so that it never refers to an
+            // allocation.
+            stack_pointer: 16,
+            depth: 0,
+        }
+    }
+
+    /// Allocates memory that lives until the machine is dropped, e.g. for
+    /// passing aggregate arguments.
+    pub fn alloc(&mut self, size: u64, alignment: u64) -> Result<i64, Error> {
+        let start = (self.stack_pointer as u64).next_multiple_of(alignment.max(1));
+        let end = start + size;
+        if end > self.memory.len() as u64 {
+            return Err(Error::StackOverflow);
+        }
+        self.stack_pointer = end as usize;
+        Ok(start as i64)
+    }
+
+    /// Reads a value of type `ty` stored at `addr`.
+    pub fn load(&self, addr: i64, ty: Type) -> Result<i64, Error> {
+        Ok(ty.extend(self.read(addr as u64, ty)?) as i64)
+    }
+
+    /// Stores `value` at `addr` as a value of type `ty`, wrapping it around.
+    pub fn store(&mut self, addr: i64, ty: Type, value: i64) -> Result<(), Error> {
+        self.write(addr as u64, ty, ty.truncate(value.into()))
+    }
+
+    fn read(&self, addr: u64, ty: Type) -> Result<u64, Error> {
+        let range = self.range(addr, ty)?;
+        let mut bytes = [0; 8];
+        bytes[..range.len()].copy_from_slice(&self.memory[range]);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn write(&mut self, addr: u64, ty: Type, bits: u64) -> Result<(), Error> {
+        let range = self.range(addr, ty)?;
+        let len = range.len();
+        self.memory[range].copy_from_slice(&bits.to_le_bytes()[..len]);
+        Ok(())
+    }
+
+    fn range(&self, addr: u64, ty: Type) -> Result<std::ops::Range<usize>, Error> {
+        let size = ty.size() as usize;
+        usize::try_from(addr)
+            .ok()
+            .filter(|&start| start != 0 && start.saturating_add(size) <= self.memory.len())
+            .map(|start| start..start + size)
+            .ok_or(Error::OutOfBounds(addr as i64))
+    }
+
+    /// Calls function `name`, returning its result. Arguments wrap around
+    /// to the types of the parameters, and functions returning no value
+    /// return zero.
+    pub fn call(&mut self, name: &str, args: &[i64]) -> Result<i64, Error> {
+        let function = self.function(name, args.len())?;
+        let args: Vec<u64> = args
+            .iter()
+            .zip(&function.params)
+            .map(|(&arg, ty)| ty.truncate(arg.into()))
+            .collect();
+        let result = self.invoke(name, function, &args)?;
+        Ok(function
+            .return_type
+            .map_or(0, |ty| ty.extend(result) as i64))
+    }
+
+    /// Looks up function `name`, checking that it takes `arguments`
+    /// arguments.
+    fn function(&self, name: &str, arguments: usize) -> Result<&'a Function, Error> {
+        let function = *self
+            .functions
+            .get(name)
+            .ok_or_else(|| Error::UnknownFunction(name.to_string()))?;
+        if arguments != function.params.len() {
+            return Err(Error::WrongArgumentCount {
+                function: name.to_string(),
+                expected: function.params.len(),
+                found: arguments,
+            });
+        }
+        Ok(function)
+    }
+
+    fn invoke(&mut self, name: &str, function: &Function, args: &[u64]) -> Result<u64, Error> {
+        if self.depth == MAX_CALL_DEPTH {
+            return Err(Error::StackOverflow);
+        }
+
+        self.depth += 1;
+        let stack_pointer = self.stack_pointer;
+        let result = self.run(name, function, args);
+        self.stack_pointer = stack_pointer;
+        self.depth -= 1;
+        result
+    }
+
+    fn run(&mut self, name: &str, function: &Function, args: &[u64]) -> Result<u64, Error> {
+        let assembly = &function.assembly;
+        let instructions = assembly.instructions();
+        let mut frame = Frame {
+            args,
+            temporaries: HashMap::new(),
+        };
+
+        // Phis need the label of the block control arrived from.
+        let mut block = assembly.labels_at(0).first().copied();
+        let mut previous_block = None;
+        let mut terminated = false;
+
+        let mut position = 0;
+        while let Some(instr) = instructions.get(position) {
+            if position > 0 && (terminated || !assembly.labels_at(position).is_empty()) {
+                previous_block = block;
+                block = assembly.labels_at(position).first().copied();
+            }
+            terminated = matches!(instr, Instruction::Continuation(_));
+            position += 1;
+            match instr {
+                Instruction::Operation(output, operation) => {
+                    let result = match operation {
+                        Operation::Binary(op, ty, left, right) => op
+                            .evaluate(*ty, frame.value(left, *ty)?, frame.value(right, *ty)?)
+                            .ok_or(Error::DivisionByZero)?,
+                        Operation::Unary(op, ty, value) => {
+                            op.evaluate(*ty, output.dest_type, frame.value(value, *ty)?)
+                        }
+                    };
+                    let result = output.dest_type.truncate(result.into());
+                    frame.temporaries.insert(output.dest, result);
+                }
+                Instruction::Call(output, call) => {
+                    let callee = self.function(&call.function_name, call.arguments.len())?;
+                    let args = call
+                        .arguments
+                        .iter()
+                        .zip(&callee.params)
+                        .map(|(arg, ty)| frame.value(arg, *ty))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let result = self.invoke(&call.function_name, callee, &args)?;
+                    if let Some(output) = output {
+                        frame.temporaries.insert(output.dest, result);
+                    }
+                }
+                Instruction::Load { output, addr } => {
+                    let value = self.read(frame.value(addr, Type::Ptr)?, output.dest_type)?;
+                    frame.temporaries.insert(output.dest, value);
+                }
+                Instruction::Store { ty, addr, value } => {
+                    let addr = frame.value(addr, Type::Ptr)?;
+                    self.write(addr, *ty, frame.value(value, *ty)?)?;
+                }
+                Instruction::Alloc {
+                    addr_output,
+                    size,
+                    alignment,
+                } => {
+                    let addr = self.alloc(*size, *alignment)?;
+                    frame.temporaries.insert(addr_output.dest, addr as u64);
+                }
+                Instruction::Phi(..) => {
+                    // The phis at the start of a block all read their
+                    // sources before any of them is assigned.
+                    let mut values = Vec::new();
+                    let mut next = position - 1;
+                    while let Some(Instruction::Phi(output, sources)) = instructions.get(next) {
+                        let (_, value) = sources
+                            .iter()
+                            .find(|(label, _)| Some(*label) == previous_block)
+                            .ok_or(Error::MissingPhiSource(output.dest.index()))?;
+                        values.push((output.dest, frame.value(value, output.dest_type)?));
+                        next += 1;
+                    }
+                    position = next;
+                    frame.temporaries.extend(values);
+                }
+                Instruction::Continuation(continuation) => {
+                    let target = match continuation {
+                        Continuation::Jump(target) => Some(target),
+                        Continuation::BranchZero(value, target) => {
+                            (frame.value(value, Type::U64)? == 0).then_some(target)
+                        }
+                        Continuation::BranchNonZero(value, target) => {
+                            (frame.value(value, Type::U64)? != 0).then_some(target)
+                        }
+                        Continuation::Return(value) => {
+                            let ty = function.return_type.unwrap_or(Type::U64);
+                            return frame.value(value, ty);
+                        }
+                        Continuation::Halt => return Err(Error::Halt),
+                    };
+                    if let Some(&target) = target {
+                        position = assembly
+                            .label_position(target)
+                            .ok_or(Error::UndefinedLabel(target.index()))?;
+                    }
+                }
+            }
+        }
+        Err(Error::FellOffEnd(name.to_string()))
+    }
+}
+
+struct Frame<'a> {
+    args: &'a [u64],
+    temporaries: HashMap<Temporary, u64>,
+}
+
+impl Frame<'_> {
+    /// Returns the bit pattern of `value`, which is used as a value of type
+    /// `ty`.
+    fn value(&self, value: &Value, ty: Type) -> Result<u64, Error> {
+        match value {
+            Value::Temporary(temporary) => self
+                .temporaries
+                .get(temporary)
+                .copied()
+                .ok_or(Error::UndefinedTemporary(temporary.index())),
+            Value::Argument(argument) => self
+                .args
+                .get(argument.0)
+                .copied()
+                .ok_or(Error::UndefinedArgument(argument.0)),
+            Value::Literal(Literal::Int(int)) => {
+                Ok(ty.truncate(int.value().expect("literal does not fit in 64 bits").into()))
+            }
+            Value::Literal(Literal::Nil) => Ok(0),
+        }
+    }
+}
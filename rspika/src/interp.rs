@@ -0,0 +1,363 @@
+//! Tree-walking interpreter for checked modules.
+//!
+//! The interpreter evaluates the AST directly, using the type checker's
+//! results for the width of integer arithmetic, which wraps around like the
+//! compiled code does. It serves as the reference semantics that lowering
+//! and the backends can be tested against.
+
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    ast,
+    diagnostic::Diagnostic,
+    il,
+    resolve::{LocalId, Res},
+    token::{Ident, IntLiteral, Span},
+    typeck::{self, TypeckResults},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Int(i128),
+    Bool(bool),
+    /// A variant of an enum, by enum and variant name.
+    Enum(Ident, Ident),
+    /// A struct value with its fields in declaration order.
+    Struct(Ident, Vec<(Ident, Value)>),
+    Array(Vec<Value>),
+    Unit,
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int(value) => write!(f, "{}", value),
+            Self::Bool(value) => write!(f, "{}", value),
+            Self::Enum(name, variant) => write!(f, "{}::{}", name, variant),
+            Self::Struct(name, fields) => {
+                write!(f, "{} {{", name)?;
+                for (i, (field, value)) in fields.iter().enumerate() {
+                    let sep = if i > 0 { "," } else { "" };
+                    write!(f, "{} {}: {}", sep, field, value)?;
+                }
+                write!(f, " }}")
+            }
+            Self::Array(elements) => {
+                write!(f, "[")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, "]")
+            }
+            Self::Unit => write!(f, "()"),
+        }
+    }
+}
+
+/// How evaluation of a statement or block completed.
+enum Flow {
+    /// Control continues normally, with the value of a block's tail
+    /// expression.
+    Normal(Value),
+    Break,
+    Return(Value),
+}
+
+pub struct Interpreter<'a> {
+    types: &'a TypeckResults,
+    functions: HashMap<&'a Ident, &'a ast::FnItem>,
+    frame: Frame,
+}
+
+/// The variables of the function being run.
+#[derive(Default)]
+struct Frame {
+    arguments: Vec<Value>,
+    locals: HashMap<LocalId, Value>,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(module: &'a ast::Module, types: &'a TypeckResults) -> Self {
+        let functions = module
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                ast::Item::Fn(fnn) => Some((&fnn.name, fnn)),
+                _ => None,
+            })
+            .collect();
+        Self {
+            types,
+            functions,
+            frame: Frame::default(),
+        }
+    }
+
+    /// Calls function `name` with `args`, which must match its signature.
+    pub fn call(&mut self, name: &Ident, args: Vec<Value>) -> Result<Value, Diagnostic> {
+        let fnn = self.functions[name];
+        assert_eq!(
+            fnn.args.len(),
+            args.len(),
+            "wrong number of arguments to `{}`",
+            name
+        );
+        let frame = Frame {
+            arguments: args,
+            locals: HashMap::new(),
+        };
+
+        // The callee cannot see the caller's variables.
+        let caller_frame = std::mem::replace(&mut self.frame, frame);
+        let result = self.eval_block(&fnn.body);
+        self.frame = caller_frame;
+
+        match result? {
+            Flow::Normal(value) | Flow::Return(value) => Ok(value),
+            Flow::Break => {
+                unreachable!("`break` outside of a loop is rejected by the type checker")
+            }
+        }
+    }
+
+    /// Returns the variable `path` refers to.
+    fn lookup(&mut self, path: &ast::Path) -> &mut Value {
+        match self.types.resolutions.path(path) {
+            Res::Local(id) => self
+                .frame
+                .locals
+                .get_mut(id)
+                .expect("locals are declared before they are used"),
+            Res::Argument(index) => &mut self.frame.arguments[*index],
+            res => unreachable!("path resolved to {:?}, which is not a variable", res),
+        }
+    }
+
+    /// Declares the local introduced by `stmt`.
+    fn declare(&mut self, stmt: &ast::Statement, value: Value) {
+        let id = self.types.resolutions.binding(stmt);
+        self.frame.locals.insert(id, value);
+    }
+
+    fn eval_block(&mut self, block: &ast::Block) -> Result<Flow, Diagnostic> {
+        for stmt in &block.statements {
+            match self.exec_statement(stmt)? {
+                Flow::Normal(_) => {}
+                flow => return Ok(flow),
+            }
+        }
+        let value = match &block.expr {
+            Some(expr) => self.eval_expr(expr)?,
+            None => Value::Unit,
+        };
+        Ok(Flow::Normal(value))
+    }
+
+    fn exec_statement(&mut self, stmt: &ast::Statement) -> Result<Flow, Diagnostic> {
+        match &stmt.kind {
+            ast::StatementKind::Block(block) => self.eval_block(block),
+            ast::StatementKind::Let(lett) => {
+                let value = self.eval_expr(&lett.value)?;
+                self.declare(stmt, value);
+                Ok(Flow::Normal(Value::Unit))
+            }
+            ast::StatementKind::Assign(assign) => {
+                let value = self.eval_expr(&assign.src)?;
+                *self.eval_place(&assign.dest)? = value;
+                Ok(Flow::Normal(Value::Unit))
+            }
+            ast::StatementKind::If(iff) => {
+                for case in &iff.cases {
+                    if self.eval_expr(&case.condition)? == Value::Bool(true) {
+                        return self.eval_block(&case.body);
+                    }
+                }
+                match &iff.else_case {
+                    Some(else_case) => self.eval_block(else_case),
+                    None => Ok(Flow::Normal(Value::Unit)),
+                }
+            }
+            ast::StatementKind::For(forr) => {
+                let ast::Iterable::Range(start, end) = &forr.iterable;
+                let (start, end) = (literal_value(start), literal_value(end));
+                for i in start..end {
+                    self.declare(stmt, Value::Int(i));
+                    match self.eval_block(&forr.body)? {
+                        Flow::Normal(_) => {}
+                        Flow::Break => break,
+                        flow @ Flow::Return(_) => return Ok(flow),
+                    }
+                }
+                Ok(Flow::Normal(Value::Unit))
+            }
+            ast::StatementKind::Return(expr) => Ok(Flow::Return(self.eval_expr(expr)?)),
+            ast::StatementKind::Break => Ok(Flow::Break),
+        }
+    }
+
+    /// Evaluates the destination of an assignment, returning the value it
+    /// refers to.
+    fn eval_place(&mut self, expr: &ast::Expr) -> Result<&mut Value, Diagnostic> {
+        // Indices are evaluated before borrowing the variable being assigned.
+        let mut projections = Vec::new();
+        let mut base = expr;
+        while let ast::ExprKind::Suffix(inner, suffix) = &base.kind {
+            let projection = match suffix {
+                ast::SuffixOp::FieldAccess(field) => Projection::Field(field),
+                ast::SuffixOp::ArrayIndex(index) => {
+                    Projection::Index(self.eval_expr(index)?, index.span.clone())
+                }
+            };
+            projections.push(projection);
+            base = inner;
+        }
+        let ast::ExprKind::Path(path) = &base.kind else {
+            unreachable!("assignment destinations are checked by the type checker");
+        };
+
+        let mut place = self.lookup(path);
+        for projection in projections.into_iter().rev() {
+            place = match projection {
+                Projection::Field(field) => field_mut(place, field),
+                Projection::Index(index, span) => index_mut(place, &index, span)?,
+            };
+        }
+        Ok(place)
+    }
+
+    fn eval_expr(&mut self, expr: &ast::Expr) -> Result<Value, Diagnostic> {
+        let value = match &expr.kind {
+            ast::ExprKind::Path(path) => match self.types.resolutions.path(path) {
+                Res::Variant(name, _) => Value::Enum(name.clone(), path.elements[1].clone()),
+                _ => self.lookup(path).clone(),
+            },
+            ast::ExprKind::IntLiteral(int) => Value::Int(literal_value(int)),
+            ast::ExprKind::BoolLiteral(b) => Value::Bool(*b),
+            ast::ExprKind::StructInit(init) => {
+                let typeck::Type::Struct(name) = self.types.expr_type(expr) else {
+                    unreachable!("struct initializer of a non-struct type");
+                };
+                let mut values: HashMap<&Ident, Value> = HashMap::new();
+                for field in &init.fields {
+                    values.insert(&field.name, self.eval_expr(&field.value)?);
+                }
+                let fields = self.types.structs[name]
+                    .fields
+                    .iter()
+                    .map(|(field, _)| (field.clone(), values.remove(field).unwrap()))
+                    .collect();
+                Value::Struct(name.clone(), fields)
+            }
+            ast::ExprKind::ArrayInit(ast::ArrayInit::Elements(elements)) => Value::Array(
+                elements
+                    .iter()
+                    .map(|element| self.eval_expr(element))
+                    .collect::<Result<_, _>>()?,
+            ),
+            ast::ExprKind::ArrayInit(ast::ArrayInit::Fill { element, size }) => {
+                let element = self.eval_expr(element)?;
+                Value::Array(vec![element; literal_value(size) as usize])
+            }
+            ast::ExprKind::Prefix(ast::PrefixOp::Not, operand) => {
+                let Value::Bool(b) = self.eval_expr(operand)? else {
+                    unreachable!("`!` on a non-bool value");
+                };
+                Value::Bool(!b)
+            }
+            ast::ExprKind::Suffix(base, ast::SuffixOp::FieldAccess(field)) => {
+                let mut base = self.eval_expr(base)?;
+                std::mem::replace(field_mut(&mut base, field), Value::Unit)
+            }
+            ast::ExprKind::Suffix(base, ast::SuffixOp::ArrayIndex(index)) => {
+                let mut base = self.eval_expr(base)?;
+                let index_value = self.eval_expr(index)?;
+                let element = index_mut(&mut base, &index_value, index.span.clone())?;
+                std::mem::replace(element, Value::Unit)
+            }
+            ast::ExprKind::Binary(ast::BinaryOp::LogicAnd, left, right) => {
+                match self.eval_expr(left)? {
+                    Value::Bool(true) => self.eval_expr(right)?,
+                    _ => Value::Bool(false),
+                }
+            }
+            ast::ExprKind::Binary(ast::BinaryOp::CmpEq, left, right) => {
+                Value::Bool(self.eval_expr(left)? == self.eval_expr(right)?)
+            }
+            ast::ExprKind::Binary(op, left, right) => {
+                let (Value::Int(left), Value::Int(right)) =
+                    (self.eval_expr(left)?, self.eval_expr(right)?)
+                else {
+                    unreachable!("arithmetic on non-integer values");
+                };
+                let result = match op {
+                    ast::BinaryOp::Plus => left + right,
+                    ast::BinaryOp::Minus => left - right,
+                    ast::BinaryOp::CmpEq | ast::BinaryOp::LogicAnd => unreachable!(),
+                };
+                match self.types.expr_type(expr) {
+                    typeck::Type::Primitive(ty) => Value::Int(wrap(result, *ty)),
+                    ty => unreachable!("arithmetic producing a `{}`", ty),
+                }
+            }
+        };
+        Ok(value)
+    }
+}
+
+enum Projection<'e> {
+    Field(&'e Ident),
+    Index(Value, Span),
+}
+
+fn field_mut<'v>(value: &'v mut Value, field: &Ident) -> &'v mut Value {
+    let Value::Struct(_, fields) = value else {
+        unreachable!("field access on a non-struct value");
+    };
+    fields
+        .iter_mut()
+        .find_map(|(name, value)| (name == field).then_some(value))
+        .expect("fields are checked by the type checker")
+}
+
+fn index_mut<'v>(
+    value: &'v mut Value,
+    index: &Value,
+    span: Span,
+) -> Result<&'v mut Value, Diagnostic> {
+    let (Value::Array(elements), Value::Int(index)) = (value, index) else {
+        unreachable!("indexing checked by the type checker");
+    };
+    let len = elements.len();
+    usize::try_from(*index)
+        .ok()
+        .and_then(|index| elements.get_mut(index))
+        .ok_or_else(|| {
+            Diagnostic::error(
+                format!(
+                    "index out of bounds: the len is {} but the index is {}",
+                    len, index
+                ),
+                span,
+            )
+        })
+}
+
+fn literal_value(int: &IntLiteral) -> i128 {
+    int.value()
+        .expect("literal ranges are checked by the type checker")
+        .into()
+}
+
+/// Wraps `value` around to the range of integer type `ty`.
+pub fn wrap(value: i128, ty: il::Type) -> i128 {
+    let modulus = 1i128 << (ty.size() * 8);
+    let value = value.rem_euclid(modulus);
+    if value > ty.max_value().into() {
+        value - modulus
+    } else {
+        value
+    }
+}
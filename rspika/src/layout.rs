@@ -0,0 +1,134 @@
+//! Memory layout of types.
+//!
+//! Scalars are stored in the natural size of their IL type, aligned to it.
+//! Structs place their fields in declaration order, padding each one to its
+//! alignment, and arrays place their elements back-to-back.
+
+use std::collections::HashMap;
+
+use crate::{
+    il,
+    token::Ident,
+    typeck::{self, TypeckResults},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub size: u64,
+    pub alignment: u64,
+}
+
+impl Layout {
+    pub fn scalar(ty: il::Type) -> Self {
+        Self {
+            size: ty.size(),
+            alignment: ty.size(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StructLayout {
+    pub layout: Layout,
+    /// Offsets of the fields from the start of the struct, in declaration
+    /// order.
+    pub offsets: Vec<u64>,
+}
+
+/// Layouts of all the structs in a checked module.
+#[derive(Debug)]
+pub struct Layouts<'a> {
+    types: &'a TypeckResults,
+    structs: HashMap<Ident, StructLayout>,
+}
+
+impl<'a> Layouts<'a> {
+    pub fn new(types: &'a TypeckResults) -> Self {
+        let mut layouts = Self {
+            types,
+            structs: HashMap::new(),
+        };
+        for name in types.structs.keys() {
+            layouts.compute_struct(name);
+        }
+        layouts
+    }
+
+    /// Returns the layout of values of type `ty`.
+    pub fn of(&self, ty: &typeck::Type) -> Layout {
+        match ty {
+            typeck::Type::Primitive(ty) => Layout::scalar(*ty),
+            typeck::Type::Bool => Layout::scalar(il::Type::U8),
+            typeck::Type::Enum(name) => Layout::scalar(self.types.enums[name].repr()),
+            typeck::Type::Struct(name) => self.structs[name].layout,
+            typeck::Type::Array(element, len) => {
+                let element = self.of(element);
+                Layout {
+                    size: element.size * len,
+                    alignment: element.alignment,
+                }
+            }
+            typeck::Type::Unit | typeck::Type::Error => Layout {
+                size: 0,
+                alignment: 1,
+            },
+        }
+    }
+
+    pub fn struct_layout(&self, name: &Ident) -> &StructLayout {
+        &self.structs[name]
+    }
+
+    /// Returns the offset of `field` from the start of struct `name`.
+    pub fn field_offset(&self, name: &Ident, field: &Ident) -> u64 {
+        let (index, _) = self.types.structs[name]
+            .field(field)
+            .unwrap_or_else(|| panic!("no field `{}` in struct `{}`", field, name));
+        self.structs[name].offsets[index]
+    }
+
+    fn compute_struct(&mut self, name: &Ident) {
+        if self.structs.contains_key(name) {
+            return;
+        }
+        let fields = &self.types.structs[name].fields;
+        // Struct fields have to be laid out before the struct containing
+        // them. The type checker rejects structs that contain themselves, so
+        // this terminates.
+        for (_, field_type) in fields {
+            if let Some(inner) = innermost_struct(field_type) {
+                self.compute_struct(inner);
+            }
+        }
+
+        let mut size: u64 = 0;
+        let mut alignment = 1;
+        let mut offsets = Vec::with_capacity(fields.len());
+        for (_, field_type) in fields {
+            let field = self.of(field_type);
+            size = size.next_multiple_of(field.alignment);
+            offsets.push(size);
+            size += field.size;
+            alignment = alignment.max(field.alignment);
+        }
+        self.structs.insert(
+            name.clone(),
+            StructLayout {
+                layout: Layout {
+                    size: size.next_multiple_of(alignment),
+                    alignment,
+                },
+                offsets,
+            },
+        );
+    }
+}
+
+/// Returns the struct stored inline in a value of type `ty`, if any.
+fn innermost_struct(ty: &typeck::Type) -> Option<&Ident> {
+    match ty {
+        typeck::Type::Struct(name) => Some(name),
+        typeck::Type::Array(element, _) => innermost_struct(element),
+        _ => None,
+    }
+}
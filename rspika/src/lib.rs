@@ -1,22 +1,175 @@
 pub mod ast;
+pub mod backend;
+pub mod diagnostic;
+pub mod driver;
+pub mod format;
 pub mod il;
+pub mod interp;
+pub mod layout;
+pub mod resolve;
 pub mod token;
+pub mod typeck;
 
 #[cfg(test)]
 mod tests {
-    use crate::ast::module;
-    use crate::token::tokenize;
-    use chumsky::Parser;
+    use crate::ast;
+    use crate::diagnostic::{Diagnostic, Source};
+    use crate::driver;
+    use crate::format;
+    use crate::il;
+    use crate::il::cfg::Cfg;
+    use crate::il::vm::Vm;
+    use crate::interp::{Interpreter, Value};
+    use crate::layout;
+    use crate::resolve;
+    use crate::token;
+    use crate::typeck;
 
-    fn parse_module(source: &str) {
-        let tokens = tokenize().parse(source).unwrap();
-        module().parse(tokens).unwrap();
+    fn parse_module(source: &str) -> ast::Module {
+        let tokens = token::lex(source).unwrap();
+        ast::parse(tokens, source.len()).unwrap()
     }
 
-    fn il_module(source: &str) {
-        let tokens = tokenize().parse(source).unwrap();
-        let ast = module().parse(tokens).unwrap();
-        ast.visit_il();
+    fn check_module(source: &str) -> typeck::TypeckResults {
+        driver::check(&parse_module(source)).unwrap()
+    }
+
+    fn il_module(source: &str) -> crate::il::Module {
+        let module = parse_module(source);
+        let types = driver::check(&module).unwrap();
+        module.visit_il(&types)
+    }
+
+    /// Links the x86-64 assembly `asm` with the C program `main` and returns
+    /// what the program prints, or `None` if the host cannot run it.
+    fn run_x86_64(asm: &str, main: &str) -> Option<String> {
+        if !cfg!(all(target_arch = "x86_64", target_os = "linux")) {
+            return None;
+        }
+        let dir = std::env::temp_dir().join(format!(
+            "rspika-x86_64-{}-{:?}",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("module.s"), asm).unwrap();
+        std::fs::write(dir.join("main.c"), main).unwrap();
+        let exe = dir.join("main");
+        let Ok(status) = std::process::Command::new("cc")
+            .arg("-o")
+            .arg(&exe)
+            .arg(dir.join("main.c"))
+            .arg(dir.join("module.s"))
+            .status()
+        else {
+            // There is no C compiler to assemble and link with.
+            return None;
+        };
+        assert!(status.success(), "cannot assemble:\n{}", asm);
+        let output = std::process::Command::new(&exe).output().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(output.status.success());
+        Some(String::from_utf8(output.stdout).unwrap())
+    }
+
+    /// Returns the instructions of the function `name` in the x86-64 assembly
+    /// `asm`.
+    fn x86_64_function<'a>(asm: &'a str, name: &str) -> Vec<&'a str> {
+        asm.lines()
+            .skip_while(|line| *line != format!("{}:", name))
+            .skip(1)
+            .take_while(|line| !line.starts_with("\t.size"))
+            .map(str::trim)
+            .collect()
+    }
+
+    /// Instantiates the WebAssembly module `wasm` in Node.js, runs `script`
+    /// with its exports in `exports` and returns what it prints, or `None` if
+    /// Node.js is not installed.
+    fn run_wasm(wasm: &[u8], script: &str) -> Option<String> {
+        let path = std::env::temp_dir().join(format!(
+            "rspika-{}-{:?}.wasm",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        std::fs::write(&path, wasm).unwrap();
+        let script = format!(
+            "const bytes = require('fs').readFileSync(process.argv[1]);\n\
+             const exports = new WebAssembly.Instance(new WebAssembly.Module(bytes)).exports;\n\
+             {}",
+            script
+        );
+        let output = std::process::Command::new("node")
+            .arg("-e")
+            .arg(script)
+            .arg(&path)
+            .output();
+        std::fs::remove_file(&path).unwrap();
+        let output = output.ok()?;
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        Some(String::from_utf8(output.stdout).unwrap())
+    }
+
+    /// Inputs for the bluetooth example, listing the discriminant of every
+    /// tooth in the order of the nested arrays.
+    fn bluetooth_cases() -> Vec<[u8; 32]> {
+        let mut blue = [0; 32];
+        blue[3] = 1;
+        let mut missing = [2; 32];
+        missing[16..].fill(1);
+        missing[16] = 0;
+        let mut chewing = [0; 32];
+        chewing[..16].fill(1);
+        vec![[0; 32], blue, missing, chewing]
+    }
+
+    /// Runs the bluetooth example on each of `cases` in the IL virtual
+    /// machine, returning the results one per line.
+    fn run_bluetooth_vm(il: &il::Module, cases: &[[u8; 32]]) -> String {
+        let mut vm = Vm::new(il);
+        cases
+            .iter()
+            .map(|teeth| {
+                let addr = vm.alloc(32, 1).unwrap();
+                for (i, &tooth) in teeth.iter().enumerate() {
+                    vm.store(addr + i as i64, il::Type::U8, tooth.into())
+                        .unwrap();
+                }
+                format!("{}\n", vm.call("bluetooth", &[addr]).unwrap())
+            })
+            .collect()
+    }
+
+    /// Asserts that `instructions` set up and tear down a frame, saving and
+    /// restoring the callee-saved registers they use.
+    fn assert_x86_64_frame(instructions: &[&str]) {
+        assert_eq!(instructions[..2], ["pushq %rbp", "movq %rsp, %rbp"]);
+        let saved: Vec<&str> = instructions[2..]
+            .iter()
+            .map_while(|instruction| instruction.strip_prefix("pushq %"))
+            .collect();
+        let epilogue: Vec<String> = if saved.is_empty() {
+            vec!["leave".to_string(), "ret".to_string()]
+        } else {
+            saved
+                .iter()
+                .rev()
+                .map(|register| format!("popq %{}", register))
+                .chain(["popq %rbp".to_string(), "ret".to_string()])
+                .collect()
+        };
+        assert!(
+            instructions.ends_with(&epilogue.iter().map(String::as_str).collect::<Vec<_>>()),
+            "{:#?}",
+            instructions
+        );
+        for register in saved {
+            assert!(["rbx", "r12", "r13", "r14", "r15"].contains(&register));
+        }
     }
 
     #[test]
@@ -24,13 +177,1385 @@ mod tests {
         il_module(include_str!("examples/add_two.pika"));
     }
 
+    #[test]
+    fn add_two_qbe() {
+        let il = il_module(include_str!("examples/add_two.pika"));
+        assert_eq!(
+            crate::backend::qbe::emit_module(&il),
+            "export function w $add_two(w %a0) {\n\
+             @start\n\
+             \t%t0 =w add %a0, 2\n\
+             \tret %t0\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn add_two_x86_64() {
+        let il = il_module(include_str!("examples/add_two.pika"));
+        assert_eq!(
+            crate::backend::x86_64::emit_module(&il),
+            "\t.text\n\
+             \n\
+             \t.globl add_two\n\
+             \t.type add_two, @function\n\
+             add_two:\n\
+             \tpushq %rbp\n\
+             \tmovq %rsp, %rbp\n\
+             \tmovq %rdi, %rax\n\
+             \tcltq\n\
+             \tmovq %rax, %r10\n\
+             \tmovq %r10, %rax\n\
+             \taddq $2, %rax\n\
+             \tcltq\n\
+             \tmovq %rax, %r10\n\
+             \tmovq %r10, %rax\n\
+             \tleave\n\
+             \tret\n\
+             \t.size add_two, .-add_two\n\
+             \n\
+             \t.section .note.GNU-stack,\"\",@progbits\n"
+        );
+    }
+
+    #[test]
+    fn not_x86_64() {
+        let il = il::parse::parse(
+            "function i32 $f(i32 %a0) {\n\
+             \t%t0 =i32 not %a0\n\
+             \tret %t0\n\
+             }\n\
+             \n\
+             function i64 $g(i64 %a0) {\n\
+             \t%t0 =i64 not %a0\n\
+             \tret %t0\n\
+             }\n",
+        )
+        .unwrap();
+        let asm = crate::backend::x86_64::emit_module(&il);
+        for name in ["f", "g"] {
+            let instructions = x86_64_function(&asm, name);
+            assert_x86_64_frame(&instructions);
+            // `sete` only writes the low byte, so the rest of the result is
+            // cleared before it is used.
+            let sete = instructions
+                .iter()
+                .position(|instruction| *instruction == "sete %al")
+                .unwrap();
+            assert_eq!(instructions[sete + 1], "movzbl %al, %eax");
+        }
+
+        let args = [0, 1, 256, -1, 1 << 40];
+        let main = format!(
+            "#include <stdint.h>\n\
+             #include <stdio.h>\n\
+             int32_t f(int32_t);\n\
+             int64_t g(int64_t);\n\
+             int main(void) {{\n\
+             \tint64_t args[] = {{{}}};\n\
+             \tfor (int i = 0; i < {}; i++)\n\
+             \t\tprintf(\"%d %lld\\n\", f((int32_t)args[i]), (long long)g(args[i]));\n\
+             }}\n",
+            args.map(|arg| arg.to_string()).join(", "),
+            args.len()
+        );
+        if let Some(output) = run_x86_64(&asm, &main) {
+            let mut vm = Vm::new(&il);
+            let expected: String = args
+                .iter()
+                .map(|&arg| {
+                    let f = vm.call("f", &[arg as i32 as i64]).unwrap();
+                    let g = vm.call("g", &[arg]).unwrap();
+                    format!("{} {}\n", f, g)
+                })
+                .collect();
+            assert_eq!(output, expected);
+        }
+    }
+
+    #[test]
+    fn calls_x86_64() {
+        // Eight arguments, two of them passed on the stack, and more values
+        // live across the call than there are callee-saved registers.
+        let il = il::parse::parse(
+            "function i64 $g(i64 %a0, i64 %a1, i64 %a2, i64 %a3, i64 %a4, i64 %a5, i64 %a6, i64 %a7) {\n\
+             \t%t0 =i64 sub %a0, %a7\n\
+             \tret %t0\n\
+             }\n\
+             \n\
+             function i64 $f(i64 %a0) {\n\
+             \t%t0 =i64 add %a0, 1\n\
+             \t%t1 =i64 add %a0, 2\n\
+             \t%t2 =i64 add %a0, 3\n\
+             \t%t3 =i64 add %a0, 4\n\
+             \t%t4 =i64 add %a0, 5\n\
+             \t%t5 =i64 add %a0, 6\n\
+             \t%t6 =i64 add %a0, 7\n\
+             \t%t7 =i64 add %a0, 8\n\
+             \t%t8 =i64 call $g(%t0, %t1, %t2, %t3, %t4, %t5, %t6, %t7)\n\
+             \t%t9 =i64 add %t8, %t0\n\
+             \t%t10 =i64 add %t9, %t1\n\
+             \t%t11 =i64 add %t10, %t2\n\
+             \t%t12 =i64 add %t11, %t3\n\
+             \t%t13 =i64 add %t12, %t4\n\
+             \t%t14 =i64 add %t13, %t5\n\
+             \t%t15 =i64 add %t14, %t6\n\
+             \t%t16 =i64 add %t15, %t7\n\
+             \tret %t16\n\
+             }\n",
+        )
+        .unwrap();
+        assert_eq!(il::verify(&il), Ok(()));
+        let asm = crate::backend::x86_64::emit_module(&il);
+        let f = x86_64_function(&asm, "f");
+        assert_x86_64_frame(&f);
+        // Every callee-saved register holds a value that is live across the
+        // call, and the rest are spilled to the frame.
+        assert_eq!(
+            f[2..7],
+            [
+                "pushq %rbx",
+                "pushq %r12",
+                "pushq %r13",
+                "pushq %r14",
+                "pushq %r15"
+            ]
+        );
+        assert!(f.iter().any(|instruction| instruction.ends_with("(%rbp)")));
+        // The last two arguments are pushed and popped around the call.
+        let call = f
+            .iter()
+            .position(|instruction| *instruction == "call g")
+            .unwrap();
+        let pushes = f[..call]
+            .iter()
+            .filter(|instruction| instruction.starts_with("pushq -"))
+            .count();
+        assert_eq!(pushes, 2);
+        assert_eq!(f[call + 1], "addq $16, %rsp");
+        let g = x86_64_function(&asm, "g");
+        assert_x86_64_frame(&g);
+        assert!(g.contains(&"movq 24(%rbp), %rax"));
+
+        let args = [0, 5, -40, i64::MAX];
+        let main = format!(
+            "#include <stdint.h>\n\
+             #include <stdio.h>\n\
+             int64_t f(int64_t);\n\
+             int main(void) {{\n\
+             \tint64_t args[] = {{{}}};\n\
+             \tfor (int i = 0; i < {}; i++)\n\
+             \t\tprintf(\"%lld\\n\", (long long)f(args[i]));\n\
+             }}\n",
+            args.map(|arg| format!("{}LL", arg)).join(", "),
+            args.len()
+        );
+        if let Some(output) = run_x86_64(&asm, &main) {
+            let mut vm = Vm::new(&il);
+            let expected: String = args
+                .iter()
+                .map(|&arg| format!("{}\n", vm.call("f", &[arg]).unwrap()))
+                .collect();
+            assert_eq!(output, expected);
+        }
+    }
+
+    #[test]
+    fn bluetooth_x86_64() {
+        let mut il = il_module(include_str!("examples/kattis/bluetooth.pika"));
+        il.optimize();
+        let asm = crate::backend::x86_64::emit_module(&il);
+        assert_x86_64_frame(&x86_64_function(&asm, "bluetooth"));
+
+        let cases = bluetooth_cases();
+
+        let main = format!(
+            "#include <stdint.h>\n\
+             #include <stdio.h>\n\
+             int32_t bluetooth(uint8_t *);\n\
+             int main(void) {{\n\
+             \tuint8_t cases[][32] = {{{}}};\n\
+             \tfor (int i = 0; i < {}; i++)\n\
+             \t\tprintf(\"%d\\n\", bluetooth(cases[i]));\n\
+             }}\n",
+            cases
+                .iter()
+                .map(|teeth| format!("{{{}}}", teeth.map(|tooth| tooth.to_string()).join(", ")))
+                .collect::<Vec<_>>()
+                .join(", "),
+            cases.len()
+        );
+        if let Some(output) = run_x86_64(&asm, &main) {
+            assert_eq!(output, run_bluetooth_vm(&il, &cases));
+        }
+    }
+
+    #[test]
+    fn arrays_and_enums_c() {
+        let source = "enum Tooth { Healthy, Blue }\n\
+             fn f(teeth: [Tooth; 2], int: u8) -> u8 {\n\
+                 let mut seen = [false; 2];\n\
+                 for (i in 0..2) {\n\
+                     let i = i;\n\
+                     seen[i] = teeth[i] == Tooth::Blue;\n\
+                 }\n\
+                 int - 1\n\
+             }";
+        let module = parse_module(source);
+        let types = driver::check(&module).unwrap();
+        assert_eq!(
+            crate::backend::c::emit_module(&module, &types),
+            "#include <stdbool.h>\n\
+             #include <stdint.h>\n\
+             #include <stdlib.h>\n\
+             \n\
+             enum p_Tooth {\n\
+             \tv_Tooth_Healthy,\n\
+             \tv_Tooth_Blue,\n\
+             };\n\
+             \n\
+             typedef struct {\n\
+             \tenum p_Tooth e[2];\n\
+             } Array_2_p_Tooth;\n\
+             \n\
+             typedef struct {\n\
+             \tbool e[2];\n\
+             } Array_2_bool;\n\
+             \n\
+             static Array_2_bool Array_2_bool_fill(bool element) {\n\
+             \tArray_2_bool array;\n\
+             \tfor (size_t i = 0; i < 2; i++) {\n\
+             \t\tarray.e[i] = element;\n\
+             \t}\n\
+             \treturn array;\n\
+             }\n\
+             \n\
+             uint8_t p_f(Array_2_p_Tooth p_teeth, uint8_t p_int) {\n\
+             \tArray_2_bool p_seen = Array_2_bool_fill(false);\n\
+             \tfor (int32_t p_i = 0; p_i < 2; p_i++) {\n\
+             \t\tint32_t p_i_1 = p_i;\n\
+             \t\tp_seen.e[p_i_1] = (p_teeth.e[p_i_1] == v_Tooth_Blue);\n\
+             \t}\n\
+             \treturn (uint8_t)((uint8_t)p_int - (uint8_t)1);\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn header_names_c() {
+        let source = "enum Tooth { Blue }\n\
+             struct size_t { NULL: i32 }\n\
+             fn Tooth_Blue(uint8_t: u8) -> u8 { uint8_t }\n\
+             fn Array_1_bool_fill(INT32_MAX: size_t) -> i32 {\n\
+                 let int32_t = INT32_MAX.NULL;\n\
+                 let seen = [Tooth::Blue; 1];\n\
+                 let filled = [false; 1];\n\
+                 int32_t\n\
+             }";
+        let module = parse_module(source);
+        let types = driver::check(&module).unwrap();
+        let c = crate::backend::c::emit_module(&module, &types);
+        assert!(c.contains("\tint32_t p_int32_t = p_INT32_MAX.p_NULL;\n"));
+        assert!(c.contains("uint8_t p_Tooth_Blue(uint8_t p_uint8_t) {\n"));
+        assert!(c.contains("int32_t p_Array_1_bool_fill(struct p_size_t p_INT32_MAX) {\n"));
+        assert!(c.contains("static Array_1_bool Array_1_bool_fill(bool element) {\n"));
+        assert!(c.contains("\tArray_1_p_Tooth p_seen = Array_1_p_Tooth_fill(v_Tooth_Blue);\n"));
+
+        let path = std::env::temp_dir().join(format!(
+            "rspika-c-{}-{:?}.c",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        std::fs::write(&path, &c).unwrap();
+        let Ok(status) = std::process::Command::new("cc")
+            .args(["-std=c99", "-pedantic", "-Werror", "-fsyntax-only"])
+            .arg(&path)
+            .status()
+        else {
+            // There is no C compiler to check the output with.
+            return;
+        };
+        std::fs::remove_file(&path).unwrap();
+        assert!(status.success(), "cannot compile:\n{}", c);
+    }
+
+    #[test]
+    fn add_two_wasm() {
+        let il = il_module(include_str!("examples/add_two.pika"));
+        assert_eq!(
+            crate::backend::wasm::emit_wat(&il),
+            "(module\n\
+             \x20 (memory (export \"memory\") 16)\n\
+             \x20 (global $sp (mut i32) (i32.const 1048576))\n\
+             \x20 (func $add_two (export \"add_two\") (param i32) (result i32) (local i32 i32)\n\
+             \x20   local.get 0\n\
+             \x20   i32.const 2\n\
+             \x20   i32.add\n\
+             \x20   local.set 2\n\
+             \x20   local.get 2\n\
+             \x20   return\n\
+             \x20   unreachable\n\
+             \x20 )\n\
+             )\n"
+        );
+        let code = [
+            0x0a, 0x11, 0x01, 0x0f, 0x01, 0x02, 0x7f, 0x20, 0x00, 0x41, 0x02, 0x6a, 0x21, 0x02,
+            0x20, 0x02, 0x0f, 0x00, 0x0b,
+        ];
+        assert!(crate::backend::wasm::emit_module(&il).ends_with(&code));
+    }
+
+    #[test]
+    fn loop_wasm() {
+        let mut il = il_module(
+            "fn f(n: i32) -> i32 {\n\
+                 let mut x = 0;\n\
+                 for (i in 0..10) {\n\
+                     if (x == n) { break; }\n\
+                     x = x + 1;\n\
+                 }\n\
+                 x\n\
+             }\n",
+        );
+        il.optimize();
+        let wat = crate::backend::wasm::emit_wat(&il);
+
+        // The loop header holds the phis of `x` and `i`, so the back edge
+        // must assign both of their locals before branching to the loop.
+        let mut enclosing = Vec::new();
+        let mut back_edges = 0;
+        let instructions: Vec<&str> = wat.lines().map(str::trim).collect();
+        for (i, instruction) in instructions.iter().enumerate() {
+            match instruction.split(' ').collect::<Vec<_>>()[..] {
+                ["loop"] | ["block"] | ["if"] => enclosing.push(*instruction),
+                ["end"] => {
+                    enclosing.pop();
+                }
+                ["br", depth] => {
+                    let depth: usize = depth.parse().unwrap();
+                    if enclosing[enclosing.len() - 1 - depth] == "loop" {
+                        back_edges += 1;
+                        assert!(instructions[i - 2].starts_with("local.set"));
+                        assert!(instructions[i - 1].starts_with("local.set"));
+                    }
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(back_edges, 1, "{}", wat);
+
+        let args = [-1, 0, 3, 10, 20];
+        let script = format!(
+            "for (const n of [{}]) console.log(exports.f(n));",
+            args.map(|arg| arg.to_string()).join(", ")
+        );
+        let wasm = crate::backend::wasm::emit_module(&il);
+        if let Some(output) = run_wasm(&wasm, &script) {
+            let mut vm = Vm::new(&il);
+            let expected: String = args
+                .iter()
+                .map(|&arg| format!("{}\n", vm.call("f", &[arg]).unwrap()))
+                .collect();
+            assert_eq!(output, expected);
+        }
+    }
+
+    #[test]
+    fn bluetooth_wasm() {
+        let mut il = il_module(include_str!("examples/kattis/bluetooth.pika"));
+        il.optimize();
+        let wat = crate::backend::wasm::emit_wat(&il);
+        // The nested for loops are structured as nested wasm loops.
+        let loops = wat.lines().filter(|line| line.trim() == "loop").count();
+        assert_eq!(loops, 4, "{}", wat);
+
+        let cases = bluetooth_cases();
+
+        let script = format!(
+            "const memory = new Uint8Array(exports.memory.buffer);\n\
+             for (const teeth of [{}]) {{\n\
+             \tmemory.set(teeth, 1024);\n\
+             \tconsole.log(exports.bluetooth(1024));\n\
+             }}\n",
+            cases
+                .iter()
+                .map(|teeth| format!("[{}]", teeth.map(|tooth| tooth.to_string()).join(", ")))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let wasm = crate::backend::wasm::emit_module(&il);
+        if let Some(output) = run_wasm(&wasm, &script) {
+            assert_eq!(output, run_bluetooth_vm(&il, &cases));
+        }
+    }
+
+    #[test]
+    fn let_bindings_qbe() {
+        let il = il_module(
+            "fn f(x: i32) -> i32 {\n\
+                 let y = x + 1;\n\
+                 let mut z = y;\n\
+                 { let y = 5; };\n\
+                 z + y\n\
+             }\n",
+        );
+        assert_eq!(
+            crate::backend::qbe::emit_module(&il),
+            "export function w $f(w %a0) {\n\
+             @start\n\
+             \t%t1 =l alloc4 4\n\
+             \t%t0 =w add %a0, 1\n\
+             \tstorew %t0, %t1\n\
+             \t%t2 =w loadw %t1\n\
+             \t%t3 =w add %t2, %t0\n\
+             \tret %t3\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn assign_qbe() {
+        let il = il_module(
+            "fn f(x: i32) -> i32 {\n\
+                 let mut y = x;\n\
+                 y = y + 2;\n\
+                 y\n\
+             }\n",
+        );
+        assert_eq!(
+            crate::backend::qbe::emit_module(&il),
+            "export function w $f(w %a0) {\n\
+             @start\n\
+             \t%t0 =l alloc4 4\n\
+             \tstorew %a0, %t0\n\
+             \t%t1 =w loadw %t0\n\
+             \t%t2 =w add %t1, 2\n\
+             \tstorew %t2, %t0\n\
+             \t%t3 =w loadw %t0\n\
+             \tret %t3\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn for_break_qbe() {
+        let il = il_module(
+            "fn f(n: i32) -> i32 {\n\
+                 let mut sum = 0;\n\
+                 for (i in 0..10) {\n\
+                     if (i == n) { break; };\n\
+                     sum = sum + i;\n\
+                 }\n\
+                 sum\n\
+             }\n",
+        );
+        assert_eq!(
+            crate::backend::qbe::emit_module(&il),
+            "export function w $f(w %a0) {\n\
+             @start\n\
+             \t%t0 =l alloc4 4\n\
+             \t%t1 =l alloc4 4\n\
+             \tstorew 0, %t0\n\
+             \tstorew 0, %t1\n\
+             @l0\n\
+             \t%t2 =w loadw %t1\n\
+             \t%t3 =w csltw %t2, 10\n\
+             \tjnz %t3, @l3, @l1\n\
+             @l3\n\
+             \t%t4 =w ceqw %t2, %a0\n\
+             \tjnz %t4, @l4, @l2\n\
+             @l4\n\
+             \tjmp @l1\n\
+             @l2\n\
+             \t%t5 =w loadw %t0\n\
+             \t%t6 =w add %t5, %t2\n\
+             \tstorew %t6, %t0\n\
+             \t%t7 =w add %t2, 1\n\
+             \tstorew %t7, %t1\n\
+             \tjmp @l0\n\
+             @l1\n\
+             \t%t8 =w loadw %t0\n\
+             \tret %t8\n\
+             }\n"
+        );
+    }
+
     #[test]
     fn bijele() {
-        parse_module(include_str!("examples/kattis/bijele.pika"));
+        il_module(include_str!("examples/kattis/bijele.pika"));
+    }
+
+    #[test]
+    fn struct_layout() {
+        let types = check_module(
+            "struct A { x: u8, y: i64, z: bool }\n\
+             struct B { a: [A; 2], w: i32 }\n",
+        );
+        let layouts = layout::Layouts::new(&types);
+        let struct_layout = |name: &str| {
+            let name = types.structs.keys().find(|n| n.to_string() == name);
+            layouts.struct_layout(name.unwrap())
+        };
+        let a = struct_layout("A");
+        assert_eq!(a.offsets, [0, 8, 16]);
+        assert_eq!(
+            a.layout,
+            layout::Layout {
+                size: 24,
+                alignment: 8
+            }
+        );
+        let b = struct_layout("B");
+        assert_eq!(b.offsets, [0, 48]);
+        assert_eq!(b.layout.size, 56);
+    }
+
+    #[test]
+    fn struct_qbe() {
+        let il = il_module(
+            "struct P { x: i32, y: i32 }\n\
+             fn f(p: P) -> P {\n\
+                 let q = P { x: p.y, y: 1 };\n\
+                 q\n\
+             }\n",
+        );
+        assert_eq!(
+            crate::backend::qbe::emit_module(&il),
+            "export function l $f(l %a0, l %a1) {\n\
+             @start\n\
+             \t%t0 =l alloc4 8\n\
+             \t%t1 =l add %a1, 4\n\
+             \t%t2 =w loadw %t1\n\
+             \tstorew %t2, %t0\n\
+             \t%t3 =l add %t0, 4\n\
+             \tstorew 1, %t3\n\
+             \t%t4 =w loadw %t0\n\
+             \tstorew %t4, %a0\n\
+             \t%t5 =l add %t0, 4\n\
+             \t%t6 =w loadw %t5\n\
+             \t%t7 =l add %a0, 4\n\
+             \tstorew %t6, %t7\n\
+             \tret %a0\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn array_qbe() {
+        let il = il_module(
+            "fn f(i: i32) -> bool {\n\
+                 let mut a = [[false; 2]; 2];\n\
+                 a[i][1] = true;\n\
+                 a[1][i]\n\
+             }\n",
+        );
+        assert_eq!(
+            crate::backend::qbe::emit_module(&il),
+            "export function w $f(w %a0) {\n\
+             @start\n\
+             \t%t0 =l alloc4 4\n\
+             \t%t1 =l alloc4 2\n\
+             \tstoreb 0, %t1\n\
+             \t%t2 =l add %t1, 1\n\
+             \tstoreb 0, %t2\n\
+             \t%t3 =w loadub %t1\n\
+             \tstoreb %t3, %t0\n\
+             \t%t4 =l add %t1, 1\n\
+             \t%t5 =w loadub %t4\n\
+             \t%t6 =l add %t0, 1\n\
+             \tstoreb %t5, %t6\n\
+             \t%t7 =l add %t0, 2\n\
+             \t%t8 =w loadub %t1\n\
+             \tstoreb %t8, %t7\n\
+             \t%t9 =l add %t1, 1\n\
+             \t%t10 =w loadub %t9\n\
+             \t%t11 =l add %t7, 1\n\
+             \tstoreb %t10, %t11\n\
+             \t%t12 =l extsw %a0\n\
+             \t%t13 =l mul %t12, 2\n\
+             \t%t14 =l add %t0, %t13\n\
+             \t%t15 =l extsw 1\n\
+             \t%t16 =l mul %t15, 1\n\
+             \t%t17 =l add %t14, %t16\n\
+             \tstoreb 1, %t17\n\
+             \t%t18 =l extsw 1\n\
+             \t%t19 =l mul %t18, 2\n\
+             \t%t20 =l add %t0, %t19\n\
+             \t%t21 =l extsw %a0\n\
+             \t%t22 =l mul %t21, 1\n\
+             \t%t23 =l add %t20, %t22\n\
+             \t%t24 =w loadub %t23\n\
+             \tret %t24\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn interp_bijele() {
+        let module = parse_module(include_str!("examples/kattis/bijele.pika"));
+        let types = driver::check(&module).unwrap();
+        let pieces = |counts: [i128; 6]| {
+            let names = ["kings", "queens", "rooks", "bishops", "knights", "pawns"];
+            let fields = names
+                .into_iter()
+                .zip(counts)
+                .map(|(name, count)| (name.into(), Value::Int(count)))
+                .collect();
+            Value::Struct("PieceSet".into(), fields)
+        };
+        let result = Interpreter::new(&module, &types)
+            .call(&"bijele".into(), vec![pieces([0, 1, 2, 2, 2, 7])])
+            .unwrap();
+        assert_eq!(result, pieces([1, 0, 0, 0, 0, 1]));
+    }
+
+    #[test]
+    fn interp_bluetooth() {
+        let module = parse_module(include_str!("examples/kattis/bluetooth.pika"));
+        let types = driver::check(&module).unwrap();
+        let tooth = |variant: &str| Value::Enum("Tooth".into(), variant.into());
+        let jaw = |teeth: Vec<Value>| Value::Array(teeth);
+        let healthy = || jaw(vec![tooth("Healthy"); 8]);
+        let mut blue = vec![tooth("Healthy"); 8];
+        blue[3] = tooth("Blue");
+        let missing = jaw(vec![tooth("Missing"); 8]);
+
+        // The left side has a blue tooth and the right side is missing its
+        // whole upper jaw, so neither side can chew.
+        let teeth = jaw(vec![
+            jaw(vec![jaw(blue), healthy()]),
+            jaw(vec![healthy(), missing]),
+        ]);
+        let result = Interpreter::new(&module, &types)
+            .call(&"bluetooth".into(), vec![teeth.clone()])
+            .unwrap();
+        assert_eq!(result, Value::Int(2));
+
+        let Value::Array(mut sides) = teeth else {
+            unreachable!()
+        };
+        sides[1] = jaw(vec![healthy(), healthy()]);
+        let result = Interpreter::new(&module, &types)
+            .call(&"bluetooth".into(), vec![Value::Array(sides)])
+            .unwrap();
+        assert_eq!(result, Value::Int(1));
+    }
+
+    #[test]
+    fn vm_matches_interp() {
+        let source = "fn f(n: i32) -> i32 {\n\
+                          let mut sum = 0;\n\
+                          let mut seen = [false; 12];\n\
+                          for (i in 0..10) {\n\
+                              if (i == n) { break; };\n\
+                              seen[i + 2] = true;\n\
+                              sum = sum + i - 2147483647;\n\
+                          }\n\
+                          if (seen[n + 1]) { return sum; };\n\
+                          n\n\
+                      }\n";
+        let module = parse_module(source);
+        let types = driver::check(&module).unwrap();
+        let il = module.visit_il(&types);
+        let mut optimized = module.visit_il(&types);
+        optimized.optimize();
+        for n in [-1, 0, 3, 9] {
+            let expected = Interpreter::new(&module, &types)
+                .call(&"f".into(), vec![Value::Int(n.into())])
+                .unwrap();
+            let result = Vm::new(&il).call("f", &[n]).unwrap();
+            assert_eq!(Value::Int(result.into()), expected, "f({})", n);
+            let result = Vm::new(&optimized).call("f", &[n]).unwrap();
+            assert_eq!(Value::Int(result.into()), expected, "optimized f({})", n);
+        }
+    }
+
+    #[test]
+    fn vm_wraps_to_types() {
+        let source = "fn a(x: u8, y: u8) -> u8 { x + y }\n\
+                      fn b(x: i8) -> i8 { 0 - x }\n\
+                      fn c(x: u64) -> bool { x + 1 == 0 }\n\
+                      fn d(x: i16) -> i16 { x + 30000 }\n";
+        let module = parse_module(source);
+        let types = driver::check(&module).unwrap();
+        let il = module.visit_il(&types);
+        let mut optimized = module.visit_il(&types);
+        optimized.optimize();
+        let calls: [(&str, &[i64]); 6] = [
+            ("a", &[200, 100]),
+            ("b", &[-128]),
+            ("b", &[5]),
+            ("c", &[-1]),
+            ("c", &[7]),
+            ("d", &[10000]),
+        ];
+        for (name, args) in calls {
+            let values = args.iter().map(|&arg| Value::Int(arg.into())).collect();
+            let expected = Interpreter::new(&module, &types)
+                .call(&name.into(), values)
+                .unwrap();
+            let expected = match expected {
+                Value::Bool(value) => Value::Int(value.into()),
+                value => value,
+            };
+            let result = Vm::new(&il).call(name, args).unwrap();
+            assert_eq!(Value::Int(result.into()), expected, "{}{:?}", name, args);
+            let result = Vm::new(&optimized).call(name, args).unwrap();
+            assert_eq!(
+                Value::Int(result.into()),
+                expected,
+                "optimized {}{:?}",
+                name,
+                args
+            );
+        }
+    }
+
+    #[test]
+    fn vm_bijele() {
+        let il = il_module(include_str!("examples/kattis/bijele.pika"));
+        let mut vm = Vm::new(&il);
+        let pieces = vm.alloc(24, 4).unwrap();
+        let difference = vm.alloc(24, 4).unwrap();
+        for (i, count) in [0, 1, 2, 2, 2, 7].into_iter().enumerate() {
+            vm.store(pieces + 4 * i as i64, il::Type::I32, count)
+                .unwrap();
+        }
+        assert_eq!(vm.call("bijele", &[difference, pieces]), Ok(difference));
+        let counts: Vec<i64> = (0..6)
+            .map(|i| vm.load(difference + 4 * i, il::Type::I32).unwrap())
+            .collect();
+        assert_eq!(counts, [1, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn cfg_for_break() {
+        let mut il = il_module(
+            "fn f(n: i32) -> i32 {\n\
+                 let mut sum = 0;\n\
+                 for (i in 0..10) {\n\
+                     if (i == n) { break; };\n\
+                     sum = sum + i;\n\
+                 }\n\
+                 sum\n\
+             }\n",
+        );
+        let expected = crate::backend::qbe::emit_module(&il);
+        let function = il.functions.values_mut().next().unwrap();
+        let cfg = Cfg::new(std::mem::take(&mut function.assembly));
+
+        let edges: Vec<Vec<usize>> = cfg
+            .block_ids()
+            .map(|id| cfg.successors(id).iter().map(|s| s.index()).collect())
+            .collect();
+        assert_eq!(
+            edges,
+            [vec![1], vec![2, 5], vec![3, 4], vec![5], vec![1], vec![]]
+        );
+        let order: Vec<usize> = cfg.reverse_postorder().iter().map(|b| b.index()).collect();
+        assert_eq!(order, [0, 1, 2, 4, 3, 5]);
+
+        let dominators = cfg.dominators();
+        let idoms: Vec<Option<usize>> = cfg
+            .block_ids()
+            .map(|id| dominators.immediate_dominator(id).map(|b| b.index()))
+            .collect();
+        assert_eq!(idoms, [None, Some(0), Some(1), Some(2), Some(2), Some(1)]);
+
+        function.assembly = cfg.into_assembly();
+        assert_eq!(crate::backend::qbe::emit_module(&il), expected);
+    }
+
+    #[test]
+    fn mem2reg_qbe() {
+        let mut il = il_module(
+            "fn f(n: i32) -> i32 {\n\
+                 let mut sum = 0;\n\
+                 for (i in 0..10) {\n\
+                     if (i == n) { break; };\n\
+                     sum = sum + i;\n\
+                 }\n\
+                 sum\n\
+             }\n",
+        );
+        il.optimize();
+        assert_eq!(il::verify(&il), Ok(()));
+        assert_eq!(
+            crate::backend::qbe::emit_module(&il),
+            "export function w $f(w %a0) {\n\
+             @start\n\
+             \tjmp @l0\n\
+             @l0\n\
+             \t%t9 =w phi @start 0, @l2 %t6\n\
+             \t%t10 =w phi @start 0, @l2 %t7\n\
+             \t%t3 =w csltw %t10, 10\n\
+             \tjnz %t3, @l4, @l1\n\
+             @l4\n\
+             \t%t4 =w ceqw %t10, %a0\n\
+             \tjnz %t4, @l5, @l2\n\
+             @l5\n\
+             \tjmp @l1\n\
+             @l2\n\
+             \t%t6 =w add %t9, %t10\n\
+             \t%t7 =w add %t10, 1\n\
+             \tjmp @l0\n\
+             @l1\n\
+             \tret %t9\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn verify_errors() {
+        use il::verify::{VerifyError, VerifyErrorKind};
+        use il::{Assembly, BinaryOp, Continuation, Instruction, Literal, Operation, Output, Type};
+
+        let one = || il::Value::Literal(Literal::Int(1.into()));
+        let add = |dest, ty, left| {
+            Instruction::Operation(
+                Output {
+                    dest,
+                    dest_type: ty,
+                },
+                Operation::Binary(BinaryOp::Add, ty, left, one()),
+            )
+        };
+
+        let mut f = Assembly::new();
+        let (t0, missing) = (f.new_temporary(), f.new_label());
+        f.push(add(t0, Type::I32, il::Value::Argument(il::Argument(1))));
+        f.push(Instruction::Call(
+            None,
+            il::Call {
+                function_name: "g".to_string(),
+                arguments: vec![il::Value::Temporary(t0)],
+            },
+        ));
+        f.push(Instruction::Continuation(Continuation::Jump(missing)));
+
+        let mut h = Assembly::new();
+        let (t0, t1, label) = (h.new_temporary(), h.new_temporary(), h.new_label());
+        h.push(Instruction::Continuation(Continuation::BranchNonZero(
+            one(),
+            label,
+        )));
+        h.push(add(t0, Type::I32, one()));
+        h.set_label(label);
+        h.push(add(t1, Type::I64, il::Value::Temporary(t0)));
+
+        let mut e = Assembly::new();
+        let (t0, t1, entry) = (e.new_temporary(), e.new_temporary(), e.new_label());
+        e.set_label(entry);
+        e.push(Instruction::Phi(
+            Output {
+                dest: t0,
+                dest_type: Type::I32,
+            },
+            vec![(entry, il::Value::Temporary(t1))],
+        ));
+        e.push(add(t1, Type::I32, il::Value::Temporary(t0)));
+        e.push(Instruction::Continuation(Continuation::Jump(entry)));
+
+        let module = il::Module {
+            functions: [
+                ("e", vec![Type::I32], e),
+                ("f", vec![Type::I32], f),
+                ("h", vec![], h),
+            ]
+            .into_iter()
+            .map(|(name, params, assembly)| {
+                let function = il::Function {
+                    params,
+                    return_type: None,
+                    assembly,
+                    span: 0..0,
+                };
+                (name.into(), function)
+            })
+            .collect(),
+        };
+        let error = |function: &str, position, kind| VerifyError {
+            function: function.to_string(),
+            position,
+            kind,
+        };
+        assert_eq!(
+            il::verify(&module),
+            Err(vec![
+                error("e", 0, VerifyErrorKind::EntryPhi),
+                error("f", 0, VerifyErrorKind::ArgumentOutOfRange(1)),
+                error("f", 1, VerifyErrorKind::UnknownFunction("g".to_string())),
+                error("f", 2, VerifyErrorKind::UndefinedLabel(missing)),
+                error(
+                    "h",
+                    2,
+                    VerifyErrorKind::TypeMismatch {
+                        expected: Type::I64,
+                        found: Type::I32,
+                    }
+                ),
+                error("h", 2, VerifyErrorKind::UseBeforeDefinition(t0)),
+                error("h", 3, VerifyErrorKind::FallsOffEnd),
+            ])
+        );
+    }
+
+    #[test]
+    fn fold_constants_qbe() {
+        let mut il = il_module(
+            "fn f(x: i32) -> i32 {\n\
+                 let mut y = 1 - 2;\n\
+                 if (y == 0 - 1) {\n\
+                     y = x + y + 3;\n\
+                 };\n\
+                 y\n\
+             }\n",
+        );
+        il.optimize();
+        assert_eq!(
+            crate::backend::qbe::emit_module(&il),
+            "export function w $f(w %a0) {\n\
+             @start\n\
+             \tjmp @l2\n\
+             @l2\n\
+             \t%t6 =w add %a0, -1\n\
+             \t%t7 =w add %t6, 3\n\
+             @l0\n\
+             \t%t9 =w phi @l2 %t7\n\
+             \tret %t9\n\
+             }\n"
+        );
+        assert_eq!(Vm::new(&il).call("f", &[5]), Ok(7));
+    }
+
+    #[test]
+    fn dead_code_qbe() {
+        let mut il = il_module(
+            "fn f(x: i32) -> i32 {\n\
+                 let y = x + 1;\n\
+                 if (x == 0) { return 1; } else { return 2; };\n\
+                 y\n\
+             }\n",
+        );
+        il.optimize();
+        assert_eq!(
+            crate::backend::qbe::emit_module(&il),
+            "export function w $f(w %a0) {\n\
+             @start\n\
+             \t%t1 =w ceqw %a0, 0\n\
+             \tjnz %t1, @l2, @l1\n\
+             @l2\n\
+             \tret 1\n\
+             @l1\n\
+             \tret 2\n\
+             }\n"
+        );
+    }
+
+    const PARSED_IL: &str = "# 2 * x + 1, or 2 for zero\n\
+         function i32 $f(i32 %a0) {\n\
+         \t%t0 =ptr alloc 4, 4\n\
+         \tstore i32 1, %t0\n\
+         \t%t1 =u8 eq i32 %a0, 0\n\
+         \tjnz %t1, @l0\n\
+         \t%t2 =i32 mul %a0, 2\n\
+         \tstore i32 %t2, %t0\n\
+         @l0\n\
+         \t%t3 =i32 load %t0\n\
+         \t%t4 =i32 add %t3, 1\n\
+         \tret %t4\n\
+         }\n";
+
+    #[test]
+    fn parse_il_qbe() {
+        let mut il = il::parse::parse(PARSED_IL).unwrap();
+        assert_eq!(il::verify(&il), Ok(()));
+        il.optimize();
+        assert_eq!(
+            crate::backend::qbe::emit_module(&il),
+            "export function w $f(w %a0) {\n\
+             @start\n\
+             \t%t1 =w ceqw %a0, 0\n\
+             \tjnz %t1, @l0, @l2\n\
+             @l2\n\
+             \t%t2 =w mul %a0, 2\n\
+             @l0\n\
+             \t%t5 =w phi @start 1, @l2 %t2\n\
+             \t%t4 =w add %t5, 1\n\
+             \tret %t4\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn branch_on_long_qbe() {
+        let il = il::parse::parse(
+            "function i32 $f(i64 %a0) {\n\
+             \tjnz %a0, @l0\n\
+             \tret 1\n\
+             @l0\n\
+             \tret 2\n\
+             }\n",
+        )
+        .unwrap();
+        assert_eq!(
+            crate::backend::qbe::emit_module(&il),
+            "export function w $f(l %a0) {\n\
+             @start\n\
+             \t%c0 =w cnel %a0, 0\n\
+             \tjnz %c0, @l0, @l1\n\
+             @l1\n\
+             \tret 1\n\
+             @l0\n\
+             \tret 2\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn parse_il_dot() {
+        let il = il::parse::parse(PARSED_IL).unwrap();
+        let cfg = Cfg::new(il.functions[&"f".into()].assembly.clone());
+        assert_eq!(
+            cfg.to_dot("f"),
+            "digraph \"f\" {\n    \
+             node [shape=box, fontname=monospace];\n    \
+             b0 [label=\"b0:\\l  \
+             %t0 =ptr alloc 4, 4\\l  \
+             store i32 1, %t0\\l  \
+             %t1 =u8 eq i32 %a0, 0\\l  \
+             jnz %t1, @l0\\l\"];\n    \
+             b1 [label=\"b1:\\l  \
+             %t2 =i32 mul %a0, 2\\l  \
+             store i32 %t2, %t0\\l\"];\n    \
+             b2 [label=\"b2:\\l\
+             @l0\\l  \
+             %t3 =i32 load %t0\\l  \
+             %t4 =i32 add %t3, 1\\l  \
+             ret %t4\\l\"];\n    \
+             b0 -> b1;\n    \
+             b0 -> b2;\n    \
+             b1 -> b2;\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn parse_il_vm() {
+        let il = il::parse::parse(PARSED_IL).unwrap();
+        let mut vm = Vm::new(&il);
+        assert_eq!(vm.call("f", &[0]), Ok(2));
+        assert_eq!(vm.call("f", &[3]), Ok(7));
+    }
+
+    #[test]
+    fn print_il() {
+        let il = il_module(
+            "fn g(x: i32) -> bool { !(x == 0) }\n\
+             fn f(x: i32) -> i32 {\n\
+                 if (x == 1) { return 1; };\n\
+                 x\n\
+             }\n\
+             fn a(x: u8) -> u8 { x }\n",
+        );
+        assert_eq!(
+            il.to_string(),
+            "function u8 $g(i32 %a0) {\n\
+             \t%t0 =u8 eq i32 %a0, 0\n\
+             \t%t1 =u8 not %t0\n\
+             \tret %t1\n\
+             }\n\
+             \n\
+             function i32 $f(i32 %a0) {\n\
+             \t%t0 =u8 eq i32 %a0, 1\n\
+             \tjz %t0, @l0\n\
+             \tret 1\n\
+             @l0\n\
+             \tret %a0\n\
+             }\n\
+             \n\
+             function u8 $a(u8 %a0) {\n\
+             \tret %a0\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn print_il_round_trip() {
+        let sources = [
+            include_str!("examples/add_two.pika"),
+            include_str!("examples/kattis/bijele.pika"),
+            include_str!("examples/kattis/bluetooth.pika"),
+        ];
+        for source in sources {
+            let mut il = il_module(source);
+            for _ in 0..2 {
+                let text = il.to_string();
+                assert_eq!(il::parse::parse(&text).unwrap().to_string(), text);
+                il.optimize();
+            }
+        }
+    }
+
+    #[test]
+    fn parse_il_errors() {
+        let source = Source::new(
+            "bad.il",
+            "function $g(i32 %a1) {\n\
+             @l0\n\
+             @l0\n\
+             \tret\n\
+             }\n\
+             function $g() {\n\
+             \thlt\n\
+             }\n",
+        );
+        let messages: Vec<String> = il::parse::parse(&source.text)
+            .unwrap_err()
+            .into_iter()
+            .map(|err| Diagnostic::from(err).message)
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "expected parameter %a0",
+                "label @l0 is defined more than once",
+                "function `g` is defined more than once",
+            ]
+        );
+
+        let source = "function $f() {\n\tjmp @l5\n}\n";
+        let errors = il::parse::parse(source).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            Diagnostic::from(errors[0].clone()).message,
+            "in `f` at 0: label @l5 is never set"
+        );
+    }
+
+    #[test]
+    fn add_two_spans() {
+        let source = include_str!("examples/add_two.pika");
+        let module = parse_module(source);
+        let ast::Item::Fn(fnn) = &module.items[0] else {
+            panic!("expected a function");
+        };
+        assert!(source[fnn.span.clone()].starts_with("fn add_two"));
+        assert_eq!(&source[fnn.args[0].span.clone()], "x: i32");
+        assert_eq!(
+            &source[fnn.body.expr.as_ref().unwrap().span.clone()],
+            "x + 2"
+        );
+    }
+
+    #[test]
+    fn parse_error_diagnostic() {
+        let source = Source::new("bad.pika", "fn f(x: i32) -> i32 {\n\tx +\n}\n");
+        let diagnostics = driver::parse(&source).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].render(&source),
+            "error: unexpected `}`\n \
+             --> bad.pika:3:1\n  \
+             |\n\
+             3 | }\n  \
+             | ^\n  \
+             = note: expected one of identifier, integer literal, `!`, `(`, `[`, `false`, `true`\n"
+        );
+
+        let source = Source::new("bad.pika", "fn 1() -> i32 { 0 }\n");
+        let diagnostics = driver::parse(&source).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "unexpected `1`");
+        assert_eq!(diagnostics[0].notes, ["expected identifier"]);
     }
 
     #[test]
     fn bluetooth() {
-        parse_module(include_str!("examples/kattis/bluetooth.pika"));
+        il_module(include_str!("examples/kattis/bluetooth.pika"));
+    }
+
+    #[test]
+    fn enum_qbe() {
+        let il = il_module(
+            "enum E { A, B }\n\
+             fn f(e: E) -> bool { e == E::B }\n",
+        );
+        assert_eq!(
+            crate::backend::qbe::emit_module(&il),
+            "export function w $f(w %a0) {\n\
+             @start\n\
+             \t%t0 =w ceqw %a0, 1\n\
+             \tret %t0\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn type_errors() {
+        let source = "struct P { x: i32 }\n\
+                      struct R { p: P, r: [R; 2] }\n\
+                      fn f(p: P, n: u8) -> bool {\n\
+                          let a = P { x: 1, y: 2 };\n\
+                          n = 300;\n\
+                          return p.x + true;\n\
+                      }\n";
+        let messages: Vec<String> = driver::check(&parse_module(source))
+            .unwrap_err()
+            .into_iter()
+            .map(|diagnostic| diagnostic.message)
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "recursive type `R` has infinite size",
+                "struct `P` has no field named `y`",
+                "cannot assign to immutable variable `n`",
+                "literal out of range for `u8`",
+                "cannot apply `+` to `i32` and `bool`",
+            ]
+        );
+    }
+
+    #[test]
+    fn range_bound_too_large() {
+        let source = "fn f() -> i32 {\n\
+                          for (i in 0..3000000000) { return 7; }\n\
+                          for (i in 0..2147483647) { return 7; }\n\
+                          1\n\
+                      }\n";
+        let diagnostics = driver::check(&parse_module(source)).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "range bound is too large");
+        assert_eq!(
+            &source[diagnostics[0].span.clone()],
+            "for (i in 0..3000000000) { return 7; }"
+        );
+    }
+
+    #[test]
+    fn resolve_errors() {
+        let source = "enum E { A, A }\n\
+                      fn f(x: i32, x: i32) -> Q {\n\
+                          let y = y;\n\
+                          let x = E::B;\n\
+                          { let z = 1; };\n\
+                          z\n\
+                      }\n\
+                      fn f() -> i32 { 0 }\n";
+        let messages: Vec<String> = resolve::resolve(&parse_module(source))
+            .unwrap_err()
+            .into_iter()
+            .map(|diagnostic| diagnostic.message)
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "the name `f` is defined multiple times",
+                "variant `A` is defined multiple times",
+                "argument `x` is defined multiple times",
+                "cannot find type `Q`",
+                "cannot find value `y` in this scope",
+                "no variant `B` in enum `E`",
+                "cannot find value `z` in this scope",
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_shadowing() {
+        let source = "fn f(x: i32) -> i32 {\n\
+                          let x = x + 1;\n\
+                          let x = x + 1;\n\
+                          for (x in 0..2) { let x = x; }\n\
+                          x\n\
+                      }\n";
+        let module = parse_module(source);
+        let resolutions = resolve::resolve(&module).unwrap();
+        let ast::Item::Fn(fnn) = &module.items[0] else {
+            panic!("expected a function");
+        };
+        let ast::ExprKind::Path(tail) = &fnn.body.expr.as_ref().unwrap().kind else {
+            panic!("expected a path");
+        };
+        let resolve::Res::Local(local) = resolutions.path(tail) else {
+            panic!("expected a local");
+        };
+        assert_eq!(
+            &source[resolutions.local(*local).span.clone()],
+            "let x = x + 1;"
+        );
+        assert!(source[..resolutions.local(*local).span.start].contains("let x"));
+    }
+
+    fn format_source(source: &str) -> String {
+        let (tokens, comments) = token::lex_with_comments(source).unwrap();
+        let module = ast::parse(tokens, source.len()).unwrap();
+        format::format_module(&module, source, &comments)
+    }
+
+    #[test]
+    fn format_examples() {
+        let sources = [
+            include_str!("examples/add_two.pika"),
+            include_str!("examples/kattis/bijele.pika"),
+            include_str!("examples/kattis/bluetooth.pika"),
+        ];
+        for source in sources {
+            let formatted = format_source(source);
+            assert_eq!(format_source(&formatted), formatted);
+            assert_eq!(
+                crate::backend::qbe::emit_module(&il_module(&formatted)),
+                crate::backend::qbe::emit_module(&il_module(source))
+            );
+        }
+    }
+
+    #[test]
+    fn format_comments() {
+        let source = "// header\n\
+                      struct P { x: i32, // the x\n\
+                      y: i32 }\n\
+                      enum E {}\n\
+                      fn f(p: P) -> P\n\
+                      {\n\
+                          // leading\n\
+                          let b = !(p.x == 1) && (1 - (2 - 3) == 4);\n\
+                      \n\
+                      \n\
+                          if (b) { return P { x: 1, y: 2 }; };\n\
+                          for i in 0..3 { let a = [1, 2 + /* two */ 3]; }\n\
+                          p // tail\n\
+                      }";
+        assert_eq!(
+            format_source(source),
+            "// header\n\
+             struct P {\n\
+             \tx: i32, // the x\n\
+             \ty: i32,\n\
+             }\n\
+             \n\
+             enum E {}\n\
+             \n\
+             fn f(p: P) -> P {\n\
+             \t// leading\n\
+             \tlet b = !(p.x == 1) && 1 - (2 - 3) == 4;\n\
+             \n\
+             \tif (b) {\n\
+             \t\treturn P {\n\
+             \t\t\tx: 1,\n\
+             \t\t\ty: 2,\n\
+             \t\t};\n\
+             \t}\n\
+             \tfor (i in 0..3) {\n\
+             \t\tlet a = [\n\
+             \t\t\t1,\n\
+             \t\t\t2 + 3,\n\
+             \t\t\t/* two */\n\
+             \t\t];\n\
+             \t}\n\
+             \tp // tail\n\
+             }\n"
+        );
     }
 }
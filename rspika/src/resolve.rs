@@ -0,0 +1,336 @@
+//! Name resolution for parsed modules.
+//!
+//! Resolution checks that every path in the module refers to something that
+//! exists, and records what it refers to in a [`Resolutions`] side table keyed
+//! by the span of the path. Later passes look variables up through it rather
+//! than by name, so they need not track scopes and shadowing themselves.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    ast,
+    diagnostic::Diagnostic,
+    token::{Ident, Span},
+};
+
+const PRIMITIVE_TYPES: &[&str] = &[
+    "i8", "i16", "i32", "i64", "u8", "u16", "u32", "u64", "isize", "usize", "f32", "f64", "bool",
+];
+
+/// A local variable introduced by a `let` statement or `for` loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LocalId(usize);
+
+#[derive(Debug, Clone)]
+pub struct Local {
+    pub name: Ident,
+    pub is_mut: bool,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Res {
+    Local(LocalId),
+    Argument(usize),
+    Struct(Ident),
+    Enum(Ident),
+    Variant(Ident, usize),
+    Primitive(Ident),
+}
+
+#[derive(Debug, Default)]
+pub struct Resolutions {
+    paths: HashMap<Span, Res>,
+    locals: Vec<Local>,
+    /// The local introduced by each `let` statement and `for` loop, keyed by
+    /// the span of the statement.
+    bindings: HashMap<Span, LocalId>,
+}
+
+impl Resolutions {
+    /// Returns what a resolved path refers to.
+    pub fn path(&self, path: &ast::Path) -> &Res {
+        self.paths.get(&path.span).expect("path was not resolved")
+    }
+
+    pub fn local(&self, id: LocalId) -> &Local {
+        &self.locals[id.0]
+    }
+
+    /// Returns the local introduced by a `let` statement or `for` loop.
+    pub fn binding(&self, stmt: &ast::Statement) -> LocalId {
+        *self
+            .bindings
+            .get(&stmt.span)
+            .expect("statement does not introduce a local")
+    }
+}
+
+pub fn resolve(module: &ast::Module) -> Result<Resolutions, Vec<Diagnostic>> {
+    let mut resolver = Resolver {
+        resolutions: Resolutions::default(),
+        errors: Vec::new(),
+        items: HashMap::new(),
+        scopes: Vec::new(),
+    };
+    resolver.collect_items(module);
+    for item in &module.items {
+        resolver.resolve_item(item);
+    }
+    if resolver.errors.is_empty() {
+        Ok(resolver.resolutions)
+    } else {
+        Err(resolver.errors)
+    }
+}
+
+enum ItemDef<'a> {
+    Fn,
+    Struct,
+    Enum(&'a ast::EnumItem),
+}
+
+struct Resolver<'a> {
+    resolutions: Resolutions,
+    errors: Vec<Diagnostic>,
+    items: HashMap<Ident, ItemDef<'a>>,
+    scopes: Vec<HashMap<Ident, Res>>,
+}
+
+impl<'a> Resolver<'a> {
+    fn error(&mut self, message: impl Into<String>, span: Span) {
+        self.errors.push(Diagnostic::error(message, span));
+    }
+
+    fn check_unique<'b>(&mut self, what: &str, names: impl IntoIterator<Item = (&'b Ident, Span)>) {
+        let mut seen = HashSet::new();
+        for (name, span) in names {
+            if !seen.insert(name) {
+                self.error(
+                    format!("{} `{}` is defined multiple times", what, name),
+                    span,
+                );
+            }
+        }
+    }
+
+    fn collect_items(&mut self, module: &'a ast::Module) {
+        for item in &module.items {
+            let (name, def) = match item {
+                ast::Item::Fn(fnn) => (&fnn.name, ItemDef::Fn),
+                ast::Item::Struct(strukt) => (&strukt.name, ItemDef::Struct),
+                ast::Item::Enum(enumm) => (&enumm.name, ItemDef::Enum(enumm)),
+            };
+            if self.items.insert(name.clone(), def).is_some() {
+                self.error(
+                    format!("the name `{}` is defined multiple times", name),
+                    item.span(),
+                );
+            }
+        }
+    }
+
+    fn resolve_item(&mut self, item: &ast::Item) {
+        match item {
+            ast::Item::Fn(fnn) => {
+                self.check_unique(
+                    "argument",
+                    fnn.args.iter().map(|arg| (&arg.arg_name, arg.span.clone())),
+                );
+                let mut scope = HashMap::new();
+                for (i, arg) in fnn.args.iter().enumerate() {
+                    self.resolve_type(&arg.arg_type);
+                    scope.insert(arg.arg_name.clone(), Res::Argument(i));
+                }
+                self.resolve_type(&fnn.return_type);
+                self.scopes.push(scope);
+                self.resolve_block(&fnn.body);
+                self.scopes.pop();
+            }
+            ast::Item::Struct(strukt) => {
+                self.check_unique(
+                    "field",
+                    strukt
+                        .fields
+                        .iter()
+                        .map(|field| (&field.field_name, field.span.clone())),
+                );
+                for field in &strukt.fields {
+                    self.resolve_type(&field.field_type);
+                }
+            }
+            ast::Item::Enum(enumm) => {
+                self.check_unique(
+                    "variant",
+                    enumm
+                        .variants
+                        .iter()
+                        .map(|variant| (&variant.name, variant.span.clone())),
+                );
+            }
+        }
+    }
+
+    fn resolve_type(&mut self, ty: &ast::Type) {
+        match ty {
+            ast::Type::Path(path) => {
+                let res = match path.elements.as_slice() {
+                    [name] => match self.items.get(name) {
+                        Some(ItemDef::Struct) => Some(Res::Struct(name.clone())),
+                        Some(ItemDef::Enum(_)) => Some(Res::Enum(name.clone())),
+                        Some(ItemDef::Fn) => None,
+                        None => PRIMITIVE_TYPES
+                            .contains(&name.to_string().as_str())
+                            .then(|| Res::Primitive(name.clone())),
+                    },
+                    _ => None,
+                };
+                match res {
+                    Some(res) => self.record(path, res),
+                    None => self.error(format!("cannot find type `{}`", path), path.span.clone()),
+                }
+            }
+            ast::Type::Array(array) => self.resolve_type(&array.element),
+        }
+    }
+
+    fn record(&mut self, path: &ast::Path, res: Res) {
+        self.resolutions.paths.insert(path.span.clone(), res);
+    }
+
+    fn declare_local(&mut self, name: &Ident, is_mut: bool, span: Span) {
+        let id = LocalId(self.resolutions.locals.len());
+        self.resolutions.bindings.insert(span.clone(), id);
+        self.resolutions.locals.push(Local {
+            name: name.clone(),
+            is_mut,
+            span,
+        });
+        // Inserting over an existing binding shadows it for the rest of the
+        // scope.
+        self.scopes
+            .last_mut()
+            .expect("no scope to declare in")
+            .insert(name.clone(), Res::Local(id));
+    }
+
+    fn resolve_block(&mut self, block: &ast::Block) {
+        self.scopes.push(HashMap::new());
+        for stmt in &block.statements {
+            self.resolve_statement(stmt);
+        }
+        if let Some(expr) = &block.expr {
+            self.resolve_expr(expr);
+        }
+        self.scopes.pop();
+    }
+
+    fn resolve_statement(&mut self, stmt: &ast::Statement) {
+        match &stmt.kind {
+            ast::StatementKind::Block(block) => self.resolve_block(block),
+            ast::StatementKind::Let(lett) => {
+                if let Some(ty) = &lett.binding_type {
+                    self.resolve_type(ty);
+                }
+                // The binding is not in scope in its own initializer.
+                self.resolve_expr(&lett.value);
+                self.declare_local(&lett.binding, lett.is_mut, stmt.span.clone());
+            }
+            ast::StatementKind::Assign(assign) => {
+                self.resolve_expr(&assign.dest);
+                self.resolve_expr(&assign.src);
+            }
+            ast::StatementKind::If(iff) => {
+                for case in &iff.cases {
+                    self.resolve_expr(&case.condition);
+                    self.resolve_block(&case.body);
+                }
+                if let Some(else_case) = &iff.else_case {
+                    self.resolve_block(else_case);
+                }
+            }
+            ast::StatementKind::For(forr) => {
+                self.scopes.push(HashMap::new());
+                self.declare_local(&forr.binding, false, stmt.span.clone());
+                self.resolve_block(&forr.body);
+                self.scopes.pop();
+            }
+            ast::StatementKind::Return(expr) => self.resolve_expr(expr),
+            ast::StatementKind::Break => {}
+        }
+    }
+
+    fn resolve_expr(&mut self, expr: &ast::Expr) {
+        match &expr.kind {
+            ast::ExprKind::Path(path) => self.resolve_value(path),
+            ast::ExprKind::IntLiteral(_) | ast::ExprKind::BoolLiteral(_) => {}
+            ast::ExprKind::StructInit(init) => {
+                match init.name.elements.as_slice() {
+                    [name] if matches!(self.items.get(name), Some(ItemDef::Struct)) => {
+                        self.record(&init.name, Res::Struct(name.clone()));
+                    }
+                    _ => self.error(
+                        format!("cannot find struct `{}`", init.name),
+                        init.name.span.clone(),
+                    ),
+                }
+                for field in &init.fields {
+                    self.resolve_expr(&field.value);
+                }
+            }
+            ast::ExprKind::ArrayInit(ast::ArrayInit::Elements(elements)) => {
+                for element in elements {
+                    self.resolve_expr(element);
+                }
+            }
+            ast::ExprKind::ArrayInit(ast::ArrayInit::Fill { element, .. }) => {
+                self.resolve_expr(element);
+            }
+            ast::ExprKind::Prefix(_, operand) => self.resolve_expr(operand),
+            ast::ExprKind::Suffix(base, suffix) => {
+                self.resolve_expr(base);
+                if let ast::SuffixOp::ArrayIndex(index) = suffix {
+                    self.resolve_expr(index);
+                }
+            }
+            ast::ExprKind::Binary(_, left, right) => {
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            }
+        }
+    }
+
+    fn resolve_value(&mut self, path: &ast::Path) {
+        let res = match path.elements.as_slice() {
+            [name] => self
+                .scopes
+                .iter()
+                .rev()
+                .find_map(|scope| scope.get(name))
+                .cloned(),
+            [enum_name, variant] => match self.items.get(enum_name) {
+                Some(ItemDef::Enum(enumm)) => {
+                    match enumm.variants.iter().position(|v| &v.name == variant) {
+                        Some(index) => Some(Res::Variant(enum_name.clone(), index)),
+                        None => {
+                            self.error(
+                                format!("no variant `{}` in enum `{}`", variant, enum_name),
+                                path.span.clone(),
+                            );
+                            return;
+                        }
+                    }
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        match res {
+            Some(res) => self.record(path, res),
+            None => self.error(
+                format!("cannot find value `{}` in this scope", path),
+                path.span.clone(),
+            ),
+        }
+    }
+}
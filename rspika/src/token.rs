@@ -1,13 +1,33 @@
 use core::fmt;
+use std::ops::Range;
 
-use chumsky::{prelude::*, text};
+use chumsky::{prelude::*, text, Stream};
 
-pub fn tokenize() -> impl Parser<char, Vec<Token>, Error = Simple<char>> {
+/// A range of byte offsets into the source text.
+pub type Span = Range<usize>;
+
+/// Tokenizes `source`, with spans given as byte offsets.
+pub fn lex(source: &str) -> Result<Vec<(Token, Span)>, Vec<Simple<char>>> {
+    lex_with_comments(source).map(|(tokens, _)| tokens)
+}
+
+/// Tokenizes `source` like [`lex`], also returning the comments in it.
+#[allow(clippy::type_complexity)]
+pub fn lex_with_comments(
+    source: &str,
+) -> Result<(Vec<(Token, Span)>, Vec<Comment>), Vec<Simple<char>>> {
+    let eoi = source.len()..source.len();
+    let chars = source.char_indices().map(|(i, c)| (c, i..i + c.len_utf8()));
+    tokenize().parse(Stream::from_iter(eoi, chars))
+}
+
+pub fn tokenize() -> impl Parser<char, (Vec<(Token, Span)>, Vec<Comment>), Error = Simple<char>> {
     let token = choice((
         choice([
             text::keyword("break").to(Token::Break),
             text::keyword("else").to(Token::Else),
             text::keyword("enum").to(Token::Enum),
+            text::keyword("false").to(Token::False),
             text::keyword("fn").to(Token::Fn),
             text::keyword("for").to(Token::For),
             text::keyword("if").to(Token::If),
@@ -16,6 +36,7 @@ pub fn tokenize() -> impl Parser<char, Vec<Token>, Error = Simple<char>> {
             text::keyword("mut").to(Token::Mut),
             text::keyword("return").to(Token::Return),
             text::keyword("struct").to(Token::Struct),
+            text::keyword("true").to(Token::True),
         ]),
         choice([
             just("->").to(Token::RightArrow),
@@ -40,22 +61,49 @@ pub fn tokenize() -> impl Parser<char, Vec<Token>, Error = Simple<char>> {
         ]),
         text::ident().map(|s| Token::Ident(Ident(s))),
         text::int(10).map(|s| Token::IntLiteral(IntLiteral(s))),
-    ));
+    ))
+    .map_with_span(|token, span| (token, span));
 
-    let line_comment = just("//").then(take_until(text::newline())).ignored();
-    let block_comment = just("/*").then(take_until(just("*/"))).ignored();
+    // Line comments end before the newline, which may be missing at the end
+    // of the source.
+    let line_comment = just("//")
+        .ignore_then(filter(|c| *c != '\n').repeated().collect::<String>())
+        .map(|body| format!("//{}", body));
+    let block_comment = just("/*")
+        .ignore_then(take_until(just("*/")))
+        .map(|(body, _)| format!("/*{}*/", body.into_iter().collect::<String>()));
+    let comment = choice((line_comment, block_comment)).map_with_span(|text, span| Comment {
+        text: text.trim_end().to_string(),
+        span,
+    });
 
-    let whitespace = choice((
-        text::whitespace().at_least(1).ignored(),
-        line_comment,
-        block_comment,
-    ))
-    .repeated();
+    let whitespace = choice((text::whitespace().at_least(1).to(None), comment.map(Some)))
+        .repeated()
+        .flatten();
 
-    (whitespace.ignore_then(token))
+    (whitespace.clone().then(token))
         .repeated()
-        .then_ignore(whitespace)
+        .then(whitespace)
         .then_ignore(end())
+        .map(|(tokens, trailing)| {
+            let mut comments = Vec::new();
+            let tokens = tokens
+                .into_iter()
+                .map(|(before, token)| {
+                    comments.extend(before);
+                    token
+                })
+                .collect();
+            comments.extend(trailing);
+            (tokens, comments)
+        })
+}
+
+/// A `//` or `/* */` comment, with the delimiters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comment {
+    pub text: String,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -65,6 +113,7 @@ pub enum Token {
     Break,
     Else,
     Enum,
+    False,
     Fn,
     For,
     If,
@@ -73,6 +122,7 @@ pub enum Token {
     Mut,
     Return,
     Struct,
+    True,
     RightArrow,
     Plus,
     Minus,
@@ -102,6 +152,7 @@ impl fmt::Display for Token {
             Self::Break => &"break",
             Self::Else => &"else",
             Self::Enum => &"enum",
+            Self::False => &"false",
             Self::Fn => &"fn",
             Self::For => &"for",
             Self::If => &"if",
@@ -110,6 +161,7 @@ impl fmt::Display for Token {
             Self::Mut => &"mut",
             Self::Return => &"return",
             Self::Struct => &"struct",
+            Self::True => &"true",
             Self::RightArrow => &"->",
             Self::Plus => &"+",
             Self::Minus => &"-",
@@ -143,9 +195,29 @@ impl fmt::Display for Ident {
     }
 }
 
+impl From<&str> for Ident {
+    fn from(name: &str) -> Self {
+        Self(name.to_string())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct IntLiteral(String);
 
+impl IntLiteral {
+    /// Returns the value of the literal, or `None` if it does not fit in a
+    /// `u64`.
+    pub fn value(&self) -> Option<u64> {
+        self.0.parse().ok()
+    }
+}
+
+impl From<u64> for IntLiteral {
+    fn from(value: u64) -> Self {
+        Self(value.to_string())
+    }
+}
+
 impl fmt::Display for IntLiteral {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.0)
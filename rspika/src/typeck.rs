@@ -0,0 +1,688 @@
+//! Type checking for parsed modules.
+//!
+//! Checking produces a [`TypeckResults`] side table that records the type of
+//! every expression (keyed by its span) along with the resolved signatures of
+//! all items in the module. It also keeps the [`Resolutions`] it was given,
+//! through which later passes look up what paths refer to.
+
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    ast,
+    diagnostic::Diagnostic,
+    il,
+    resolve::{LocalId, Res, Resolutions},
+    token::{Ident, IntLiteral, Span},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Type {
+    Primitive(il::Type),
+    Bool,
+    Struct(Ident),
+    Enum(Ident),
+    Array(Box<Type>, u64),
+    Unit,
+    /// Placeholder for an expression whose type could not be determined.
+    /// Compatible with every other type, so that one mistake is only
+    /// reported once.
+    Error,
+}
+
+impl Type {
+    /// The type of integer literals that are not constrained by their context.
+    pub const DEFAULT_INT: Self = Self::Primitive(il::Type::I32);
+
+    pub fn is_integer(&self) -> bool {
+        matches!(self, Self::Primitive(ty) if ty.is_integer())
+    }
+
+    /// Whether values of this type live in memory and are represented in the
+    /// IL by their address.
+    pub fn is_aggregate(&self) -> bool {
+        matches!(self, Self::Struct(_) | Self::Array(..))
+    }
+
+    fn compatible(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Error, _) | (_, Self::Error) => true,
+            (Self::Array(a, n), Self::Array(b, m)) => n == m && a.compatible(b),
+            (a, b) => a == b,
+        }
+    }
+
+    fn primitive_name(name: &str) -> Option<Self> {
+        let ty = match name {
+            "i8" => il::Type::I8,
+            "i16" => il::Type::I16,
+            "i32" => il::Type::I32,
+            "i64" => il::Type::I64,
+            "u8" => il::Type::U8,
+            "u16" => il::Type::U16,
+            "u32" => il::Type::U32,
+            "u64" => il::Type::U64,
+            "isize" => il::Type::Isize,
+            "usize" => il::Type::Usize,
+            "f32" => il::Type::F32,
+            "f64" => il::Type::F64,
+            "bool" => return Some(Self::Bool),
+            _ => return None,
+        };
+        Some(Self::Primitive(ty))
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Primitive(ty) => write!(f, "{}", ty),
+            Self::Bool => write!(f, "bool"),
+            Self::Struct(name) | Self::Enum(name) => write!(f, "{}", name),
+            Self::Array(element, size) => write!(f, "[{}; {}]", element, size),
+            Self::Unit => write!(f, "()"),
+            Self::Error => write!(f, "{{unknown}}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StructDef {
+    pub fields: Vec<(Ident, Type)>,
+}
+
+impl StructDef {
+    pub fn field(&self, name: &Ident) -> Option<(usize, &Type)> {
+        self.fields
+            .iter()
+            .enumerate()
+            .find(|(_, (field_name, _))| field_name == name)
+            .map(|(i, (_, ty))| (i, ty))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EnumDef {
+    pub variants: Vec<Ident>,
+}
+
+impl EnumDef {
+    pub fn variant(&self, name: &Ident) -> Option<usize> {
+        self.variants.iter().position(|variant| variant == name)
+    }
+
+    /// The integer type used to represent values of the enum. Variants are
+    /// numbered from zero in declaration order.
+    pub fn repr(&self) -> il::Type {
+        match self.variants.len() {
+            0..=0x100 => il::Type::U8,
+            0x101..=0x1_0000 => il::Type::U16,
+            _ => il::Type::U32,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FnSig {
+    pub params: Vec<Type>,
+    pub return_type: Type,
+}
+
+#[derive(Debug, Default)]
+pub struct TypeckResults {
+    pub structs: HashMap<Ident, StructDef>,
+    pub enums: HashMap<Ident, EnumDef>,
+    pub functions: HashMap<Ident, FnSig>,
+    pub resolutions: Resolutions,
+    exprs: HashMap<Span, Type>,
+}
+
+impl TypeckResults {
+    /// Returns the type of a checked expression.
+    pub fn expr_type(&self, expr: &ast::Expr) -> &Type {
+        self.exprs
+            .get(&expr.span)
+            .expect("expression was not type checked")
+    }
+}
+
+/// Checks a module whose names have been resolved to `resolutions`.
+pub fn check(
+    module: &ast::Module,
+    resolutions: Resolutions,
+) -> Result<TypeckResults, Vec<Diagnostic>> {
+    let mut checker = Checker {
+        results: TypeckResults {
+            resolutions,
+            ..TypeckResults::default()
+        },
+        errors: Vec::new(),
+        locals: HashMap::new(),
+        arguments: Vec::new(),
+        return_type: Type::Unit,
+        loop_depth: 0,
+    };
+    checker.collect_items(module);
+    for item in &module.items {
+        if let ast::Item::Fn(fnn) = item {
+            checker.check_fn(fnn);
+        }
+    }
+    if checker.errors.is_empty() {
+        Ok(checker.results)
+    } else {
+        Err(checker.errors)
+    }
+}
+
+struct Binding {
+    ty: Type,
+    is_mut: bool,
+}
+
+struct Checker {
+    results: TypeckResults,
+    errors: Vec<Diagnostic>,
+    locals: HashMap<LocalId, Binding>,
+    arguments: Vec<Binding>,
+    return_type: Type,
+    loop_depth: usize,
+}
+
+impl Checker {
+    fn error(&mut self, message: impl Into<String>, span: Span) {
+        self.errors.push(Diagnostic::error(message, span));
+    }
+
+    fn expect_type(&mut self, expected: &Type, found: &Type, span: Span) {
+        if !expected.compatible(found) {
+            self.error(
+                format!(
+                    "mismatched types: expected `{}`, found `{}`",
+                    expected, found
+                ),
+                span,
+            );
+        }
+    }
+
+    fn collect_items(&mut self, module: &ast::Module) {
+        // Names are registered before any types are resolved, so that items
+        // may refer to each other regardless of declaration order.
+        for item in &module.items {
+            match item {
+                ast::Item::Struct(strukt) => {
+                    self.results
+                        .structs
+                        .insert(strukt.name.clone(), StructDef { fields: Vec::new() });
+                }
+                ast::Item::Enum(enumm) => {
+                    let variants = enumm.variants.iter().map(|v| v.name.clone()).collect();
+                    self.results
+                        .enums
+                        .insert(enumm.name.clone(), EnumDef { variants });
+                }
+                ast::Item::Fn(_) => {}
+            }
+        }
+        for item in &module.items {
+            match item {
+                ast::Item::Struct(strukt) => {
+                    let fields = strukt
+                        .fields
+                        .iter()
+                        .map(|field| {
+                            (
+                                field.field_name.clone(),
+                                self.resolve_type(&field.field_type),
+                            )
+                        })
+                        .collect();
+                    self.results.structs.get_mut(&strukt.name).unwrap().fields = fields;
+                }
+                ast::Item::Fn(fnn) => {
+                    let sig = FnSig {
+                        params: fnn
+                            .args
+                            .iter()
+                            .map(|arg| self.resolve_type(&arg.arg_type))
+                            .collect(),
+                        return_type: self.resolve_type(&fnn.return_type),
+                    };
+                    self.results.functions.insert(fnn.name.clone(), sig);
+                }
+                ast::Item::Enum(_) => {}
+            }
+        }
+        for item in &module.items {
+            if let ast::Item::Struct(strukt) = item {
+                let fields = &self.results.structs[&strukt.name].fields;
+                let mut visited = Vec::new();
+                if fields
+                    .iter()
+                    .any(|(_, ty)| self.contains_inline(ty, &strukt.name, &mut visited))
+                {
+                    self.error(
+                        format!("recursive type `{}` has infinite size", strukt.name),
+                        strukt.span.clone(),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Whether a value of type `ty` stores a value of struct `target` inside
+    /// it, directly or within the fields of another struct.
+    fn contains_inline<'a>(
+        &'a self,
+        ty: &'a Type,
+        target: &Ident,
+        visited: &mut Vec<&'a Ident>,
+    ) -> bool {
+        match ty {
+            Type::Array(element, _) => self.contains_inline(element, target, visited),
+            Type::Struct(name) if name == target => true,
+            Type::Struct(name) if !visited.contains(&name) => {
+                visited.push(name);
+                self.results.structs[name]
+                    .fields
+                    .iter()
+                    .any(|(_, field)| self.contains_inline(field, target, visited))
+            }
+            _ => false,
+        }
+    }
+
+    fn resolve_type(&mut self, ty: &ast::Type) -> Type {
+        match ty {
+            ast::Type::Path(path) => match self.results.resolutions.path(path) {
+                Res::Struct(name) => Type::Struct(name.clone()),
+                Res::Enum(name) => Type::Enum(name.clone()),
+                Res::Primitive(name) => Type::primitive_name(&name.to_string())
+                    .expect("primitive types are the ones known to name resolution"),
+                _ => unreachable!("types resolve to structs, enums or primitives"),
+            },
+            ast::Type::Array(array) => {
+                let element = self.resolve_type(&array.element);
+                match array.size.value() {
+                    Some(size) => Type::Array(Box::new(element), size),
+                    None => {
+                        self.error("array size is too large", array.span.clone());
+                        Type::Error
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the variable a path refers to, if it is one.
+    fn lookup(&self, path: &ast::Path) -> Option<&Binding> {
+        match self.results.resolutions.path(path) {
+            Res::Local(id) => self.locals.get(id),
+            Res::Argument(index) => self.arguments.get(*index),
+            _ => None,
+        }
+    }
+
+    /// Declares the local introduced by `stmt`.
+    fn declare(&mut self, stmt: &ast::Statement, ty: Type, is_mut: bool) {
+        let id = self.results.resolutions.binding(stmt);
+        self.locals.insert(id, Binding { ty, is_mut });
+    }
+
+    fn check_fn(&mut self, fnn: &ast::FnItem) {
+        let sig = self.results.functions[&fnn.name].clone();
+        self.return_type = sig.return_type.clone();
+        self.arguments = sig
+            .params
+            .iter()
+            .map(|ty| Binding {
+                ty: ty.clone(),
+                is_mut: false,
+            })
+            .collect();
+        let (ty, diverges) = self.check_block(&fnn.body, Some(&sig.return_type));
+        if !diverges {
+            let span = fnn
+                .body
+                .expr
+                .as_ref()
+                .map(|expr| expr.span.clone())
+                .unwrap_or_else(|| fnn.body.span.clone());
+            self.expect_type(&sig.return_type, &ty, span);
+        }
+    }
+
+    /// Checks a block, returning the type of its tail expression and whether
+    /// control flow always leaves the block through a `return`.
+    fn check_block(&mut self, block: &ast::Block, expected: Option<&Type>) -> (Type, bool) {
+        let mut diverges = false;
+        for stmt in &block.statements {
+            diverges |= self.check_statement(stmt);
+        }
+        let ty = match &block.expr {
+            Some(expr) => self.check_expr(expr, expected),
+            None => Type::Unit,
+        };
+        (ty, diverges)
+    }
+
+    /// Checks a statement, returning whether it always diverges.
+    fn check_statement(&mut self, stmt: &ast::Statement) -> bool {
+        match &stmt.kind {
+            ast::StatementKind::Block(block) => self.check_block(block, None).1,
+            ast::StatementKind::Let(lett) => {
+                let annotation = lett.binding_type.as_ref().map(|ty| self.resolve_type(ty));
+                let value = self.check_expr(&lett.value, annotation.as_ref());
+                let ty = match annotation {
+                    Some(annotation) => {
+                        self.expect_type(&annotation, &value, lett.value.span.clone());
+                        annotation
+                    }
+                    None => value,
+                };
+                self.declare(stmt, ty, lett.is_mut);
+                false
+            }
+            ast::StatementKind::Assign(assign) => {
+                let dest = self.check_place(&assign.dest);
+                let src = self.check_expr(&assign.src, Some(&dest));
+                self.expect_type(&dest, &src, assign.src.span.clone());
+                false
+            }
+            ast::StatementKind::If(iff) => {
+                let mut diverges = true;
+                for case in &iff.cases {
+                    let condition = self.check_expr(&case.condition, Some(&Type::Bool));
+                    self.expect_type(&Type::Bool, &condition, case.condition.span.clone());
+                    diverges &= self.check_block(&case.body, None).1;
+                }
+                match &iff.else_case {
+                    Some(else_case) => diverges && self.check_block(else_case, None).1,
+                    None => false,
+                }
+            }
+            ast::StatementKind::For(forr) => {
+                let ast::Iterable::Range(start, end) = &forr.iterable;
+                let Type::Primitive(counter) = Type::DEFAULT_INT else {
+                    unreachable!("the default integer type is primitive")
+                };
+                let fits = |bound: &IntLiteral| {
+                    bound
+                        .value()
+                        .is_some_and(|value| value <= counter.max_value())
+                };
+                if !fits(start) || !fits(end) {
+                    self.error("range bound is too large", stmt.span.clone());
+                }
+                self.declare(stmt, Type::DEFAULT_INT, false);
+                self.loop_depth += 1;
+                self.check_block(&forr.body, None);
+                self.loop_depth -= 1;
+                false
+            }
+            ast::StatementKind::Return(expr) => {
+                let return_type = self.return_type.clone();
+                let ty = self.check_expr(expr, Some(&return_type));
+                self.expect_type(&return_type, &ty, expr.span.clone());
+                true
+            }
+            ast::StatementKind::Break => {
+                if self.loop_depth == 0 {
+                    self.error("`break` outside of a loop", stmt.span.clone());
+                }
+                false
+            }
+        }
+    }
+
+    /// Checks the destination of an assignment.
+    fn check_place(&mut self, expr: &ast::Expr) -> Type {
+        let mut base = expr;
+        while let ast::ExprKind::Suffix(inner, _) = &base.kind {
+            base = inner;
+        }
+        match &base.kind {
+            ast::ExprKind::Path(path) if path.elements.len() == 1 => {
+                if let Some(binding) = self.lookup(path) {
+                    if !binding.is_mut {
+                        self.error(
+                            format!("cannot assign to immutable variable `{}`", path),
+                            expr.span.clone(),
+                        );
+                    }
+                }
+            }
+            _ => self.error("invalid left-hand side of assignment", expr.span.clone()),
+        }
+        self.check_expr(expr, None)
+    }
+
+    fn check_expr(&mut self, expr: &ast::Expr, expected: Option<&Type>) -> Type {
+        let ty = self.check_expr_kind(expr, expected);
+        self.results.exprs.insert(expr.span.clone(), ty.clone());
+        ty
+    }
+
+    fn check_expr_kind(&mut self, expr: &ast::Expr, expected: Option<&Type>) -> Type {
+        match &expr.kind {
+            ast::ExprKind::Path(path) => self.check_path(path),
+            ast::ExprKind::IntLiteral(int) => {
+                let ty = match expected {
+                    Some(ty) if ty.is_integer() => ty.clone(),
+                    _ => Type::DEFAULT_INT,
+                };
+                let fits = match (&ty, int.value()) {
+                    (Type::Primitive(prim), Some(value)) => value <= prim.max_value(),
+                    _ => false,
+                };
+                if !fits {
+                    self.error(
+                        format!("literal out of range for `{}`", ty),
+                        expr.span.clone(),
+                    );
+                }
+                ty
+            }
+            ast::ExprKind::BoolLiteral(_) => Type::Bool,
+            ast::ExprKind::StructInit(init) => self.check_struct_init(init),
+            ast::ExprKind::ArrayInit(init) => {
+                let expected_element = match expected {
+                    Some(Type::Array(element, _)) => Some(element.as_ref()),
+                    _ => None,
+                };
+                match init {
+                    ast::ArrayInit::Elements(elements) => {
+                        let mut element_type = expected_element.cloned();
+                        for element in elements {
+                            let ty = self.check_expr(element, element_type.as_ref());
+                            match &element_type {
+                                Some(expected) => {
+                                    self.expect_type(expected, &ty, element.span.clone())
+                                }
+                                None => element_type = Some(ty),
+                            }
+                        }
+                        match element_type {
+                            Some(ty) => Type::Array(Box::new(ty), elements.len() as u64),
+                            None => {
+                                self.error(
+                                    "cannot infer the element type of an empty array",
+                                    expr.span.clone(),
+                                );
+                                Type::Error
+                            }
+                        }
+                    }
+                    ast::ArrayInit::Fill { element, size } => {
+                        let ty = self.check_expr(element, expected_element);
+                        match size.value() {
+                            Some(size) => Type::Array(Box::new(ty), size),
+                            None => {
+                                self.error("array size is too large", expr.span.clone());
+                                Type::Error
+                            }
+                        }
+                    }
+                }
+            }
+            ast::ExprKind::Prefix(ast::PrefixOp::Not, operand) => {
+                let ty = self.check_expr(operand, Some(&Type::Bool));
+                self.expect_type(&Type::Bool, &ty, operand.span.clone());
+                Type::Bool
+            }
+            ast::ExprKind::Suffix(base, ast::SuffixOp::FieldAccess(field)) => {
+                match self.check_expr(base, None) {
+                    Type::Struct(name) => match self.results.structs[&name].field(field) {
+                        Some((_, ty)) => ty.clone(),
+                        None => {
+                            self.error(
+                                format!("no field `{}` on type `{}`", field, name),
+                                expr.span.clone(),
+                            );
+                            Type::Error
+                        }
+                    },
+                    Type::Error => Type::Error,
+                    ty => {
+                        self.error(
+                            format!("no field `{}` on type `{}`", field, ty),
+                            expr.span.clone(),
+                        );
+                        Type::Error
+                    }
+                }
+            }
+            ast::ExprKind::Suffix(base, ast::SuffixOp::ArrayIndex(index)) => {
+                let base_type = self.check_expr(base, None);
+                let index_type = self.check_expr(index, None);
+                if !index_type.is_integer() && index_type != Type::Error {
+                    self.error(
+                        format!("array index must be an integer, found `{}`", index_type),
+                        index.span.clone(),
+                    );
+                }
+                match base_type {
+                    Type::Array(element, _) => *element,
+                    Type::Error => Type::Error,
+                    ty => {
+                        self.error(
+                            format!("cannot index into a value of type `{}`", ty),
+                            base.span.clone(),
+                        );
+                        Type::Error
+                    }
+                }
+            }
+            ast::ExprKind::Binary(op, left, right) => {
+                let operand_expected = match op {
+                    ast::BinaryOp::Plus | ast::BinaryOp::Minus => expected,
+                    ast::BinaryOp::CmpEq => None,
+                    ast::BinaryOp::LogicAnd => Some(&Type::Bool),
+                };
+                // Check the non-literal side first, so that an integer
+                // literal on the left takes the type of the right operand.
+                let (left_type, right_type) = if is_int_literal(left) && !is_int_literal(right) {
+                    let right_type = self.check_expr(right, operand_expected);
+                    (self.check_expr(left, Some(&right_type)), right_type)
+                } else {
+                    let left_type = self.check_expr(left, operand_expected);
+                    (left_type.clone(), self.check_expr(right, Some(&left_type)))
+                };
+                self.check_binary(*op, &left_type, &right_type, expr.span.clone())
+            }
+        }
+    }
+
+    fn check_path(&self, path: &ast::Path) -> Type {
+        match self.results.resolutions.path(path) {
+            Res::Variant(enum_name, _) => Type::Enum(enum_name.clone()),
+            _ => match self.lookup(path) {
+                Some(binding) => binding.ty.clone(),
+                None => unreachable!("values resolve to variables or variants"),
+            },
+        }
+    }
+
+    fn check_struct_init(&mut self, init: &ast::StructInit) -> Type {
+        let Res::Struct(name) = self.results.resolutions.path(&init.name).clone() else {
+            unreachable!("struct initializers resolve to structs");
+        };
+        let def = self.results.structs[&name].clone();
+        let name = &name;
+
+        let mut initialized = vec![false; def.fields.len()];
+        for field in &init.fields {
+            let Some((index, field_type)) = def.field(&field.name) else {
+                self.error(
+                    format!("struct `{}` has no field named `{}`", name, field.name),
+                    field.span.clone(),
+                );
+                self.check_expr(&field.value, None);
+                continue;
+            };
+            if initialized[index] {
+                self.error(
+                    format!("field `{}` specified more than once", field.name),
+                    field.span.clone(),
+                );
+            }
+            initialized[index] = true;
+            let ty = self.check_expr(&field.value, Some(field_type));
+            self.expect_type(field_type, &ty, field.value.span.clone());
+        }
+
+        let missing: Vec<String> = def
+            .fields
+            .iter()
+            .zip(initialized)
+            .filter(|(_, initialized)| !initialized)
+            .map(|((name, _), _)| format!("`{}`", name))
+            .collect();
+        if !missing.is_empty() {
+            self.error(
+                format!(
+                    "missing fields {} in initializer of `{}`",
+                    missing.join(", "),
+                    name
+                ),
+                init.span.clone(),
+            );
+        }
+        Type::Struct(name.clone())
+    }
+
+    fn check_binary(&mut self, op: ast::BinaryOp, left: &Type, right: &Type, span: Span) -> Type {
+        if *left == Type::Error || *right == Type::Error {
+            return match op {
+                ast::BinaryOp::Plus | ast::BinaryOp::Minus => Type::Error,
+                ast::BinaryOp::CmpEq | ast::BinaryOp::LogicAnd => Type::Bool,
+            };
+        }
+        let (valid, result) = match op {
+            ast::BinaryOp::Plus | ast::BinaryOp::Minus => (
+                left == right && matches!(left, Type::Primitive(_)),
+                left.clone(),
+            ),
+            ast::BinaryOp::CmpEq => (
+                left == right && matches!(left, Type::Primitive(_) | Type::Bool | Type::Enum(_)),
+                Type::Bool,
+            ),
+            ast::BinaryOp::LogicAnd => (*left == Type::Bool && *right == Type::Bool, Type::Bool),
+        };
+        if !valid {
+            self.error(
+                format!("cannot apply `{}` to `{}` and `{}`", op, left, right),
+                span,
+            );
+            return match op {
+                ast::BinaryOp::Plus | ast::BinaryOp::Minus => Type::Error,
+                _ => Type::Bool,
+            };
+        }
+        result
+    }
+}
+
+fn is_int_literal(expr: &ast::Expr) -> bool {
+    matches!(expr.kind, ast::ExprKind::IntLiteral(_))
+}